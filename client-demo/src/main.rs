@@ -3,16 +3,240 @@
 #![warn(missing_docs)]
 
 use argh::FromArgs;
+use futures::channel::mpsc;
 use futures::io::{AsyncReadExt, AsyncWriteExt};
 use futures::stream::StreamExt;
+use futures::FutureExt;
 use log::{error, info, warn, LevelFilter};
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use tor_chanmgr::transport::nativetls::NativeTlsTransport;
+use tor_chanmgr::transport::{nativetls::NativeTlsTransport, Transport};
+use tor_error::HasKind;
 
 use anyhow::Result;
 
+/// Map a [`tor_error::ErrorKind`] onto the closest matching SOCKS5 reply
+/// status, so a client gets a diagnosable error instead of the connection
+/// just hanging up.
+///
+/// This conversion really belongs next to `SocksStatus` in `tor_socksproto`,
+/// so every SOCKS-speaking front-end can share it instead of duplicating
+/// this match; it lives here for now since this binary is the only thing
+/// that needs it in this tree.
+fn socks_status_for_kind(kind: tor_error::ErrorKind) -> tor_socksproto::SocksStatus {
+    use tor_error::ErrorKind as EK;
+    use tor_socksproto::SocksStatus as Status;
+
+    match kind {
+        EK::NoExit | EK::NoPath | EK::RemoteNameError => Status::HOST_UNREACHABLE,
+        EK::InvalidStreamTarget | EK::ForbiddenStreamTarget => Status::CONNECTION_NOT_ALLOWED,
+        EK::RemoteRefused
+        | EK::RemoteStreamClosed
+        | EK::RemoteStreamError
+        | EK::CircuitCollapse => Status::CONNECTION_REFUSED,
+        EK::RemoteNetworkTimeout | EK::TorNetworkTimeout => Status::TTL_EXPIRED,
+        _ => Status::GENERAL_FAILURE,
+    }
+}
+
+/// A single-use cancellation signal handed out by [`Shutdown::subscribe`].
+///
+/// Resolves once the corresponding [`Shutdown`] is triggered; can be waited
+/// on more than once (it just keeps resolving immediately thereafter).
+struct Cancelled(mpsc::Receiver<()>);
+
+impl Cancelled {
+    /// Wait for the corresponding [`Shutdown`] to be triggered.
+    async fn wait(&mut self) {
+        let _ = self.0.next().await;
+    }
+}
+
+/// What happened to the connections that were still in flight when a
+/// [`Shutdown`] was triggered.
+#[derive(Debug, Default)]
+struct DrainSummary {
+    /// Connections that finished on their own before the drain timeout.
+    drained: usize,
+    /// Connections still running when the drain timeout expired.
+    force_closed: usize,
+}
+
+/// A broadcastable shutdown trigger, modeled on the command-channel pattern
+/// `tor_rtcompat::scheduler`'s `TaskHandle`/`TaskSchedule` use to drive
+/// background directory tasks: triggering it resolves every outstanding
+/// [`Cancelled`] signal at once, so the SOCKS listener can stop accepting
+/// and every in-flight connection can tear down its relay tasks together.
+#[derive(Clone)]
+struct Shutdown {
+    /// Shared state; cloning a `Shutdown` clones the `Arc`, not the state.
+    inner: Arc<ShutdownInner>,
+}
+
+/// Shared state behind a [`Shutdown`].
+struct ShutdownInner {
+    /// Senders for every [`Cancelled`] signal we've handed out; closing one
+    /// of these resolves the matching `Cancelled::wait`.
+    waiters: Mutex<Vec<mpsc::Sender<()>>>,
+    /// Number of connections currently registered via
+    /// [`Shutdown::track_connection`] that haven't finished yet.
+    active: AtomicUsize,
+    /// Notified once (via a dummy message) every time a tracked connection
+    /// finishes, so [`Shutdown::drain`] doesn't have to poll.
+    finished_tx: mpsc::UnboundedSender<()>,
+    /// The receiving end of `finished_tx`; only `drain` ever consumes this.
+    ///
+    /// An async-aware mutex, since `drain` holds the guard across an
+    /// `.await` while it waits for the next notification.
+    finished_rx: futures::lock::Mutex<mpsc::UnboundedReceiver<()>>,
+}
+
+/// Un-registers a connection from its [`Shutdown`]'s active count when
+/// dropped, whether the connection finished normally, errored, or panicked.
+struct ConnGuard {
+    /// The shutdown tracker this connection was registered with.
+    shutdown: Shutdown,
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.shutdown.inner.active.fetch_sub(1, Ordering::SeqCst);
+        let _ = self.shutdown.inner.finished_tx.unbounded_send(());
+    }
+}
+
+impl Shutdown {
+    /// Create a new, untriggered shutdown tracker.
+    fn new() -> Self {
+        let (finished_tx, finished_rx) = mpsc::unbounded();
+        Shutdown {
+            inner: Arc::new(ShutdownInner {
+                waiters: Mutex::new(Vec::new()),
+                active: AtomicUsize::new(0),
+                finished_tx,
+                finished_rx: futures::lock::Mutex::new(finished_rx),
+            }),
+        }
+    }
+
+    /// Get a cancellation signal that resolves once this shutdown is
+    /// triggered.
+    fn subscribe(&self) -> Cancelled {
+        let (tx, rx) = mpsc::channel(0);
+        self.inner
+            .waiters
+            .lock()
+            .expect("shutdown waiters lock poisoned")
+            .push(tx);
+        Cancelled(rx)
+    }
+
+    /// Register a newly-accepted connection, returning a guard that
+    /// un-registers it again once dropped (whether it finished normally or
+    /// was cancelled).
+    fn track_connection(&self) -> ConnGuard {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        ConnGuard {
+            shutdown: self.clone(),
+        }
+    }
+
+    /// Stop accepting new connections and cancel every outstanding one.
+    ///
+    /// Safe to call more than once (or concurrently with a Ctrl-C handler
+    /// and a programmatic call both firing); later calls are no-ops.
+    fn trigger(&self) {
+        for mut tx in self
+            .inner
+            .waiters
+            .lock()
+            .expect("shutdown waiters lock poisoned")
+            .drain(..)
+        {
+            tx.close_channel();
+        }
+    }
+
+    /// Wait up to `timeout` for every registered connection to finish,
+    /// then report how many drained cleanly versus had to be force-closed.
+    async fn drain(&self, timeout: Duration) -> DrainSummary {
+        let total = self.inner.active.load(Ordering::SeqCst);
+        let mut finished_rx = self.inner.finished_rx.lock().await;
+        let deadline = tor_rtcompat::task::sleep(timeout).fuse();
+        futures::pin_mut!(deadline);
+        loop {
+            let active = self.inner.active.load(Ordering::SeqCst);
+            if active == 0 {
+                return DrainSummary {
+                    drained: total,
+                    force_closed: 0,
+                };
+            }
+            futures::select_biased! {
+                _ = deadline => {
+                    return DrainSummary {
+                        drained: total.saturating_sub(active),
+                        force_closed: active,
+                    };
+                }
+                _ = finished_rx.next() => {}
+            }
+        }
+    }
+}
+
+/// A transport, boxed so that `ChanMgr` doesn't need to be monomorphized
+/// over every transport kind a [`TransportRegistry`] can produce.
+type BoxedTransport = Box<dyn Transport + Send + Sync>;
+
+/// A factory that builds a fresh transport of one particular kind.
+type TransportFactory = Box<dyn Fn() -> BoxedTransport + Send + Sync>;
+
+/// Maps transport names (as given to `--transport`, or eventually a
+/// per-bridge override) to the factories that build them.
+///
+/// Only `native-tls` is registered today; pluggable transports like obfs4
+/// or meek are expected to register themselves here behind their own
+/// disabled-by-default feature flags.
+struct TransportRegistry {
+    /// The transports we know how to build, by name.
+    factories: HashMap<String, TransportFactory>,
+}
+
+impl TransportRegistry {
+    /// Create a registry with the transports enabled by this build's
+    /// feature flags already registered.
+    fn with_defaults() -> Self {
+        let mut registry = TransportRegistry {
+            factories: HashMap::new(),
+        };
+        registry.register("native-tls", || Box::new(NativeTlsTransport::new()));
+        registry
+    }
+
+    /// Register a transport under `name`, replacing any existing entry.
+    fn register(
+        &mut self,
+        name: &str,
+        factory: impl Fn() -> BoxedTransport + Send + Sync + 'static,
+    ) {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// Build a fresh transport of the kind registered under `name`.
+    fn build(&self, name: &str) -> Result<BoxedTransport> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("unrecognized transport {:?}", name))?;
+        Ok(factory())
+    }
+}
+
 #[derive(FromArgs)]
 /// Make a connection to the Tor network, open a SOCKS port, and proxy
 /// traffic.
@@ -28,12 +252,24 @@ struct Args {
     /// run a socks proxy on port N.
     #[argh(option, default = "9051")]
     socksport: u16,
+    /// name of the transport to connect to relays with, as registered in
+    /// the `TransportRegistry` (e.g. "native-tls").
+    ///
+    /// TODO: this should also be overridable per-bridge, once we have
+    /// bridge-line parsing; for now it's a single global default.
+    #[argh(option, default = "\"native-tls\".to_string()")]
+    transport: String,
+    /// how long to wait for in-flight connections to finish on shutdown
+    /// before force-closing them, in seconds.
+    #[argh(option, default = "10")]
+    drain_timeout_secs: u64,
 }
 
 async fn handle_socks_conn(
     dir: Arc<tor_netdir::NetDir>,
-    circmgr: Arc<tor_circmgr::CircMgr<NativeTlsTransport>>,
+    circmgr: Arc<tor_circmgr::CircMgr<BoxedTransport>>,
     stream: tor_rtcompat::net::TcpStream,
+    mut cancel: Cancelled,
 ) -> Result<()> {
     let mut handshake = tor_socksproto::SocksHandshake::new();
 
@@ -69,22 +305,50 @@ async fn handle_socks_conn(
     let port = request.port();
     info!("Got a socks request for {}:{}", addr, port);
 
+    if !matches!(request.command(), tor_socksproto::SocksCmd::Connect) {
+        warn!("Rejecting unsupported SOCKS command for {}:{}", addr, port);
+        let reply = request.reply(tor_socksproto::SocksStatus::COMMAND_NOT_SUPPORTED, None);
+        w.write(&reply[..]).await?;
+        return Ok(());
+    }
+
     let exit_ports = [port];
-    let circ = circmgr
+    let circ = match circmgr
         .get_or_launch_exit(dir.as_ref().into(), &exit_ports)
-        .await?;
+        .await
+    {
+        Ok(circ) => circ,
+        Err(e) => {
+            warn!("Couldn't get a circuit for {}:{}: {}", addr, port, e);
+            let reply = request.reply(socks_status_for_kind(e.kind()), None);
+            w.write(&reply[..]).await?;
+            return Ok(());
+        }
+    };
     info!("Got a circuit for {}:{}", addr, port);
 
-    let stream = circ.begin_stream(&addr, port).await?;
+    let stream = match circ.begin_stream(&addr, port).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("Couldn't open a stream for {}:{}: {}", addr, port, e);
+            let reply = request.reply(socks_status_for_kind(e.kind()), None);
+            w.write(&reply[..]).await?;
+            return Ok(());
+        }
+    };
     info!("Got a stream for {}:{}", addr, port);
-    // TODO: Should send a SOCKS reply if something fails.
 
     let reply = request.reply(tor_socksproto::SocksStatus::SUCCEEDED, None);
     w.write(&reply[..]).await?;
 
     let (mut rstream, wstream) = stream.split();
 
-    let _t1 = tor_rtcompat::task::spawn(async move {
+    // Relay each direction inline, rather than spawning detached tasks for
+    // them: that way, whichever of the two directions (or the shutdown
+    // signal) finishes first, we fall out of the `select` below and drop
+    // every stream half together, closing both the TCP socket and the Tor
+    // stream instead of leaving the other direction's task running forever.
+    let relay_up = async {
         let mut buf = [0u8; 1024];
         loop {
             let n = match r.read(&mut buf[..]).await {
@@ -96,8 +360,8 @@ async fn handle_socks_conn(
                 break e;
             }
         }
-    });
-    let _t2 = tor_rtcompat::task::spawn(async move {
+    };
+    let relay_down = async {
         let mut buf = [0u8; 1024];
         loop {
             let n = match rstream.read_bytes(&mut buf[..]).await {
@@ -108,21 +372,35 @@ async fn handle_socks_conn(
                 break e.into();
             }
         }
-    });
+    };
+    futures::pin_mut!(relay_up);
+    futures::pin_mut!(relay_down);
 
-    // TODO: we should close the TCP stream if either task fails.
+    futures::select_biased! {
+        _ = cancel.wait().fuse() => {
+            info!("Connection for {}:{} cancelled by shutdown", addr, port);
+        }
+        e = relay_up.fuse() => {
+            warn!("Upstream relay for {}:{} closed: {}", addr, port, e);
+        }
+        e = relay_down.fuse() => {
+            warn!("Downstream relay for {}:{} closed: {}", addr, port, e);
+        }
+    }
 
     Ok(())
 }
 
 async fn run_socks_proxy(
     dir: tor_dirmgr::DirMgr,
-    circmgr: Arc<tor_circmgr::CircMgr<NativeTlsTransport>>,
+    circmgr: Arc<tor_circmgr::CircMgr<BoxedTransport>>,
     args: Args,
+    shutdown: Shutdown,
 ) -> Result<()> {
     use tor_rtcompat::net::TcpListener;
 
     let socksport = args.socksport;
+    let drain_timeout = Duration::from_secs(args.drain_timeout_secs);
     let mut listeners = Vec::new();
 
     for localhost in &["127.0.0.1", "::1"] {
@@ -140,19 +418,40 @@ async fn run_socks_proxy(
         return Ok(());
     }
     let mut incoming = futures::stream::select_all(listeners.iter().map(TcpListener::incoming));
+    let mut stop_accepting = shutdown.subscribe();
 
-    while let Some(stream) = incoming.next().await {
-        let stream = stream?;
-        let d = dir.netdir().await.unwrap();
-        let ci = Arc::clone(&circmgr);
-        tor_rtcompat::task::spawn(async move {
-            let res = handle_socks_conn(d, ci, stream).await;
-            if let Err(e) = res {
-                warn!("connection edited with error: {}", e);
+    loop {
+        futures::select_biased! {
+            _ = stop_accepting.wait().fuse() => {
+                info!("Shutdown requested; no longer accepting new SOCKS connections.");
+                break;
             }
-        });
+            stream = incoming.next().fuse() => {
+                let stream = match stream {
+                    Some(stream) => stream?,
+                    None => break,
+                };
+                let d = dir.netdir().await.unwrap();
+                let ci = Arc::clone(&circmgr);
+                let guard = shutdown.track_connection();
+                let cancel = shutdown.subscribe();
+                tor_rtcompat::task::spawn(async move {
+                    let _guard = guard;
+                    let res = handle_socks_conn(d, ci, stream, cancel).await;
+                    if let Err(e) = res {
+                        warn!("connection edited with error: {}", e);
+                    }
+                });
+            }
+        }
     }
 
+    let summary = shutdown.drain(drain_timeout).await;
+    info!(
+        "Shutdown complete: {} connection(s) drained, {} force-closed.",
+        summary.drained, summary.force_closed
+    );
+
     Ok(())
 }
 
@@ -173,8 +472,18 @@ fn main() -> Result<()> {
         dircfg.add_default_authorities();
     }
 
+    let registry = TransportRegistry::with_defaults();
+    let transport = registry.build(&args.transport)?;
+
+    let shutdown = Shutdown::new();
+    let shutdown_on_signal = shutdown.clone();
+    ctrlc::set_handler(move || {
+        info!("Got Ctrl-C; shutting down.");
+        shutdown_on_signal.trigger();
+    })
+    .expect("failed to install Ctrl-C handler");
+
     tor_rtcompat::task::block_on(async {
-        let transport = NativeTlsTransport::new();
         let chanmgr = Arc::new(tor_chanmgr::ChanMgr::new(transport));
         let circmgr = Arc::new(tor_circmgr::CircMgr::new(Arc::clone(&chanmgr)));
         let dirmgr = tor_dirmgr::DirMgr::from_config(dircfg.finalize())?;
@@ -185,6 +494,6 @@ fn main() -> Result<()> {
         // TODO CONFORMANCE: we should stop now if there are required
         // protovers we don't support.
 
-        return run_socks_proxy(dirmgr, circmgr, args).await;
+        return run_socks_proxy(dirmgr, circmgr, args, shutdown).await;
     })
 }
@@ -5,9 +5,20 @@
 use crate::{err::ErrorDetail, BootstrapBehavior, Result, TorClient, TorClientConfig};
 use fs_mistrust::Mistrust;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tor_checkable::{timed::TimerangeBound, Timebound};
 use tor_dirmgr::DirMgrConfig;
 use tor_rtcompat::Runtime;
 
+/// How much clock skew to tolerate when deciding whether a seed directory
+/// (see [`TorClientBuilder::seed_directory`]) is still usable.
+///
+/// This is deliberately generous: a seed directory is only ever used as a
+/// starting point, and a directory that's a little early or a little stale
+/// is still far better than none at all, since Arti will replace it with a
+/// freshly fetched consensus as soon as it can reach the network.
+const SEED_DIRECTORY_CLOCK_SKEW: Duration = Duration::from_secs(60 * 60);
+
 /// An object that knows how to construct some kind of DirProvider.
 ///
 /// Note that this type is only actually exposed when the `experimental-api`
@@ -22,6 +33,27 @@ pub trait DirProviderBuilder<R: Runtime> {
     ) -> Result<Arc<dyn tor_dirmgr::DirProvider + 'static>>;
 }
 
+/// A DirProviderBuilder that just returns a pre-built `DirProvider`, so that
+/// several `TorClient`s can share one directory cache.
+///
+/// See [`TorClientBuilder::dirmgr`].
+#[derive(Clone)]
+struct SharedDirProviderBuilder {
+    /// The provider to hand back every time [`build`](DirProviderBuilder::build) is called.
+    provider: Arc<dyn tor_dirmgr::DirProvider + 'static>,
+}
+
+impl<R: Runtime> DirProviderBuilder<R> for SharedDirProviderBuilder {
+    fn build(
+        &self,
+        _runtime: R,
+        _circmgr: Arc<tor_circmgr::CircMgr<R>>,
+        _config: DirMgrConfig,
+    ) -> Result<Arc<dyn tor_dirmgr::DirProvider + 'static>> {
+        Ok(Arc::clone(&self.provider))
+    }
+}
+
 /// A DirProviderBuilder that constructs a regular DirMgr.
 #[derive(Clone, Debug)]
 struct DirMgrBuilder {}
@@ -76,6 +108,36 @@ pub struct TorClientBuilder<R: Runtime> {
     /// Only available when `arti-client` is built with the `dirfilter` and `experimental-api` features.
     #[cfg(feature = "dirfilter")]
     dirfilter: tor_dirmgr::filter::FilterConfig,
+    /// Overrides to apply to the `network` section of the configuration.
+    ///
+    /// Built up by [`add_authority`](Self::add_authority),
+    /// [`fallback_dirs`](Self::fallback_dirs), and [`network`](Self::network).
+    /// When any of these have been called, the resulting
+    /// [`NetworkConfigBuilder`](tor_dirmgr::NetworkConfigBuilder) is used in
+    /// place of the `network` section of the configuration passed to
+    /// [`config`](Self::config).
+    network: tor_dirmgr::NetworkConfigBuilder,
+    /// True if [`network`](Self::network), [`add_authority`](Self::add_authority),
+    /// or [`fallback_dirs`](Self::fallback_dirs) has been called, and `network`
+    /// should therefore override the configuration's own `network` section.
+    network_overridden: bool,
+    /// Optional alternate storage backend for the directory cache.
+    ///
+    /// If set, this is used in place of the on-disk store that would
+    /// otherwise be built from the `storage` section of the configuration.
+    ///
+    /// Only available when `arti-client` is built with the `experimental-api` feature.
+    #[cfg(feature = "experimental-api")]
+    storage_provider: Option<Arc<dyn tor_dirmgr::storage::DynStore + 'static>>,
+    /// A pre-obtained consensus directory to seed the directory cache with,
+    /// for an offline or air-gapped start.
+    ///
+    /// Set by [`seed_directory`](Self::seed_directory).
+    seed_directory: Option<Vec<u8>>,
+    /// Overrides for the directory cache's download-retry schedules.
+    ///
+    /// Set by [`download_schedule`](Self::download_schedule).
+    download_schedule: Option<tor_dirmgr::DownloadScheduleBuilder>,
 }
 
 impl<R: Runtime> TorClientBuilder<R> {
@@ -87,8 +149,14 @@ impl<R: Runtime> TorClientBuilder<R> {
             bootstrap_behavior: BootstrapBehavior::default(),
             fs_mistrust_override: FsMistrustOverride::FromEnvironment,
             dirmgr_builder: Arc::new(DirMgrBuilder {}),
+            network: tor_dirmgr::NetworkConfigBuilder::default(),
+            network_overridden: false,
             #[cfg(feature = "dirfilter")]
             dirfilter: None,
+            #[cfg(feature = "experimental-api")]
+            storage_provider: None,
+            seed_directory: None,
+            download_schedule: None,
         }
     }
 
@@ -100,6 +168,61 @@ impl<R: Runtime> TorClientBuilder<R> {
         self
     }
 
+    /// Add `authority` to the list of directory authorities to trust, for a
+    /// client running on a custom Tor network.
+    ///
+    /// Calling this method at least once replaces the `network`'s default
+    /// authority list with one built entirely out of the authorities added
+    /// this way; it should not be combined with the default public Tor
+    /// network's authorities.
+    pub fn add_authority(mut self, authority: tor_dirmgr::authority::AuthorityBuilder) -> Self {
+        self.network.authorities().push(authority);
+        self.network_overridden = true;
+        self
+    }
+
+    /// Set the list of fallback directories to use, for a client running on
+    /// a custom Tor network.
+    ///
+    /// Calling this method replaces any fallback directories set by a
+    /// previous call.
+    pub fn fallback_dirs(
+        mut self,
+        fallback_dirs: impl IntoIterator<Item = tor_dirmgr::FallbackDirBuilder>,
+    ) -> Self {
+        self.network
+            .fallback_caches(fallback_dirs.into_iter().collect());
+        self.network_overridden = true;
+        self
+    }
+
+    /// Replace the entire `network` section of the configuration, for a
+    /// client running on a custom Tor network.
+    ///
+    /// This is a lower-level alternative to
+    /// [`add_authority`](Self::add_authority) and
+    /// [`fallback_dirs`](Self::fallback_dirs): it discards any
+    /// authorities or fallback directories set by earlier calls to those
+    /// methods.
+    pub fn network(mut self, network: tor_dirmgr::NetworkConfigBuilder) -> Self {
+        self.network = network;
+        self.network_overridden = true;
+        self
+    }
+
+    /// Override the directory cache's download and retry schedules.
+    ///
+    /// By default, the schedules used to decide when and how often to
+    /// (re)try fetching consensuses, certificates, and descriptors come from
+    /// the `schedule` section of the configuration. Calling this method
+    /// overrides them with `schedule` instead -- useful, for instance, for
+    /// an application that wants to retry more aggressively right after
+    /// startup, or back off more patiently on a constrained network.
+    pub fn download_schedule(mut self, schedule: tor_dirmgr::DownloadScheduleBuilder) -> Self {
+        self.download_schedule = Some(schedule);
+        self
+    }
+
     /// Set the bootstrap behavior for the `TorClient` under construction.
     ///
     /// If not called, then the default ([`BootstrapBehavior::OnDemand`]) will
@@ -109,6 +232,26 @@ impl<R: Runtime> TorClientBuilder<R> {
         self
     }
 
+    /// Seed the directory cache with a pre-obtained consensus directory,
+    /// for an offline or air-gapped start.
+    ///
+    /// `consensus` should be the text of a consensus document (optionally
+    /// followed by its certificates and referenced microdescriptors/router
+    /// descriptors, in the usual on-disk concatenated form), obtained ahead
+    /// of time by some out-of-band means.
+    ///
+    /// If the seed's `valid-after`/`valid-until` [`Lifetime`](tor_netdoc::doc::netstatus::Lifetime)
+    /// is still current (within a small clock-skew tolerance) when
+    /// [`create_unbootstrapped`](Self::create_unbootstrapped) runs, the
+    /// client starts out already knowing this directory, without needing to
+    /// reach the network first. If the seed is stale or not-yet-valid, it is
+    /// discarded, and the client falls back to whatever
+    /// [`bootstrap_behavior`](Self::bootstrap_behavior) was configured.
+    pub fn seed_directory(mut self, consensus: impl Into<Vec<u8>>) -> Self {
+        self.seed_directory = Some(consensus.into());
+        self
+    }
+
     /// Build an [`TorClient`] that will not validate permissions and ownership
     /// on the filesystem.
     ///
@@ -151,6 +294,55 @@ impl<R: Runtime> TorClientBuilder<R> {
         self
     }
 
+    /// Use `dirmgr` as the directory provider for the `TorClient` under
+    /// construction, instead of building a new one.
+    ///
+    /// This is useful when an application wants several `TorClient`s to
+    /// share a single directory cache -- for example, several clients with
+    /// different circuit-isolation settings but otherwise identical network
+    /// configuration -- rather than each of them independently fetching and
+    /// storing its own copy of the consensus. Each resulting `TorClient`
+    /// still observes its own configured
+    /// [`bootstrap_behavior`](Self::bootstrap_behavior) against `dirmgr`'s
+    /// state: for instance, a client with
+    /// [`BootstrapBehavior::OnDemand`](crate::BootstrapBehavior::OnDemand)
+    /// will trigger `dirmgr` to bootstrap on first use even if another
+    /// client sharing it was built with
+    /// [`BootstrapBehavior::Manual`](crate::BootstrapBehavior::Manual).
+    ///
+    /// Note that settings which only make sense when this builder is the one
+    /// constructing the directory provider -- such as
+    /// [`storage_provider`](Self::storage_provider) and
+    /// [`seed_directory`](Self::seed_directory) -- are ignored when `dirmgr`
+    /// is set this way.
+    pub fn dirmgr(mut self, dirmgr: Arc<dyn tor_dirmgr::DirProvider + 'static>) -> Self {
+        self.dirmgr_builder = Arc::new(SharedDirProviderBuilder { provider: dirmgr });
+        self
+    }
+
+    /// Use `storage` in place of the default on-disk directory cache.
+    ///
+    /// By default, the directory cache described by the `storage` section of
+    /// the configuration is backed by files on disk. Calling this method
+    /// overrides that default, so that the client reads and writes its
+    /// directory documents through `storage` instead -- for example, an
+    /// in-memory store, an encrypted store, or a store backed by some
+    /// platform-specific database. Note that when a custom storage backend
+    /// is in use, [`disable_fs_permission_checks`](Self::disable_fs_permission_checks)
+    /// and its siblings have no effect, since there are no filesystem
+    /// permissions to check.
+    ///
+    /// Only available when compiled with the `experimental-api` feature: this
+    /// code is unstable.
+    #[cfg(feature = "experimental-api")]
+    pub fn storage_provider(
+        mut self,
+        storage: Arc<dyn tor_dirmgr::storage::DynStore + 'static>,
+    ) -> Self {
+        self.storage_provider = Some(storage);
+        self
+    }
+
     /// Install a [`DirFilter`](tor_dirmgr::filter::DirFilter) to
     ///
     /// Only available when compiled with the `dirfilter` feature: this code
@@ -186,6 +378,27 @@ impl<R: Runtime> TorClientBuilder<R> {
         {
             dirmgr_extensions.filter = self.dirfilter;
         }
+        #[cfg(feature = "experimental-api")]
+        {
+            dirmgr_extensions.storage = self.storage_provider;
+        }
+        if self.network_overridden {
+            dirmgr_extensions.network_overrides = Some(self.network);
+        }
+        if let Some(seed) = self.seed_directory {
+            match parse_seed_directory(&seed, SEED_DIRECTORY_CLOCK_SKEW) {
+                Ok(netdir) => dirmgr_extensions.seed_netdir = Some(netdir),
+                Err(e) => {
+                    tracing::warn!(
+                        "Ignoring seed directory passed to `seed_directory()`: {}",
+                        e
+                    );
+                }
+            }
+        }
+        if let Some(schedule) = self.download_schedule {
+            dirmgr_extensions.download_schedule_overrides = Some(schedule);
+        }
 
         let override_mistrust: Option<Mistrust> = match self.fs_mistrust_override {
             FsMistrustOverride::FromEnvironment
@@ -215,3 +428,31 @@ impl<R: Runtime> TorClientBuilder<R> {
         Ok(r)
     }
 }
+
+/// Parse `seed` as a consensus directory (optionally followed by its
+/// certificates and referenced descriptors), and check that its
+/// [`Lifetime`](tor_netdoc::doc::netstatus::Lifetime) is current, within
+/// `clock_skew` of the system clock in either direction.
+///
+/// Returns an error -- which the caller should treat as non-fatal, simply
+/// discarding the seed and falling back to the configured
+/// [`BootstrapBehavior`] -- if the seed can't be parsed, or its lifetime
+/// isn't current.
+fn parse_seed_directory(
+    seed: &[u8],
+    clock_skew: Duration,
+) -> std::result::Result<tor_netdir::NetDir, String> {
+    let text = std::str::from_utf8(seed).map_err(|e| e.to_string())?;
+    let bound: TimerangeBound<tor_netdir::NetDir> =
+        tor_netdoc::doc::netstatus::build_netdir_unverified_lifetime(text)
+            .map_err(|e| e.to_string())?;
+
+    let now = SystemTime::now();
+    let earliest = now.checked_sub(clock_skew).unwrap_or(now);
+    let latest = now.checked_add(clock_skew).unwrap_or(now);
+
+    if bound.is_valid_at(&earliest).is_err() && bound.is_valid_at(&latest).is_err() {
+        return Err("seed directory consensus is outside its validity lifetime".into());
+    }
+    Ok(bound.dangerously_assume_timely())
+}
@@ -0,0 +1,54 @@
+// @@ begin test lint list maintained by maint/add_warning @@
+#![allow(clippy::bool_assert_comparison)]
+#![allow(clippy::clone_on_copy)]
+#![allow(clippy::dbg_macro)]
+#![allow(clippy::print_stderr)]
+#![allow(clippy::print_stdout)]
+#![allow(clippy::unwrap_used)]
+//! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+//!
+//! Like `hyper.rs`, but fetches an `https://` URL: this exercises
+//! `ArtiHttpConnector`'s rustls-backed path, which performs the TLS
+//! handshake over the Tor stream itself rather than passing bytes straight
+//! through as the plaintext `http://` path does.
+
+use arti_hyper::*;
+
+use anyhow::Result;
+use arti_client::{TorClient, TorClientConfig};
+use hyper::Body;
+use tls_api::{TlsConnector, TlsConnectorBuilder};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    // Unlike `hyper.rs`'s example, this one defaults to an `https://` URL,
+    // since the whole point here is to prove the TLS-over-Tor handshake
+    // works end-to-end.
+    let url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "https://icanhazip.com".into());
+
+    eprintln!("starting Arti...");
+
+    let config = TorClientConfig::default();
+    let tor_client = TorClient::create_bootstrapped(config).await?;
+
+    // `tls_api_rustls`'s connector is what `ArtiHttpConnector` uses to speak
+    // TLS over the raw Tor stream when it sees an `https://` target; for a
+    // plain `http://` target it just passes bytes through unchanged.
+    let tls_connector = tls_api_rustls::TlsConnector::builder()?.build()?;
+
+    let tor_connector = ArtiHttpConnector::new(tor_client, tls_connector);
+    let http = hyper::Client::builder().build::<_, Body>(tor_connector);
+
+    eprintln!("requesting {} via Tor...", url);
+    let mut resp = http.get(url.try_into()?).await?;
+
+    eprintln!("status: {}", resp.status());
+
+    let body = hyper::body::to_bytes(resp.body_mut()).await?;
+    eprintln!("body: {}", std::str::from_utf8(&body)?);
+    Ok(())
+}
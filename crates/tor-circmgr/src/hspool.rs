@@ -2,16 +2,28 @@
 //
 // TODO HS: We need tests here. First, though, we need a testing strategy.
 mod pool;
+#[cfg(feature = "hs-common")]
+mod vanguards;
 
 use std::{
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
     time::Duration,
 };
 
 use crate::{CircMgr, Error, Result};
-use futures::{task::SpawnExt, StreamExt, TryFutureExt};
+use futures::{
+    future::{BoxFuture, Shared},
+    stream::FuturesUnordered,
+    task::SpawnExt,
+    FutureExt, StreamExt, TryFutureExt,
+};
 use once_cell::sync::OnceCell;
-use tor_error::{bad_api_usage, internal, ErrorReport};
+use retry_error::RetryError;
+use tor_basic_utils::retry::RetryDelay;
+use tor_error::{bad_api_usage, internal, ErrorReport, HasRetryTime, RetryTime};
 use tor_linkspec::{OwnedChanTarget, OwnedCircTarget};
 use tor_netdir::{NetDir, NetDirProvider, Relay, SubnetConfig};
 use tor_proto::circuit::ClientCirc;
@@ -21,6 +33,9 @@ use tor_rtcompat::{
 };
 use tracing::{debug, warn};
 
+#[cfg(feature = "hs-common")]
+pub use vanguards::VanguardConfig;
+
 /// The (onion-service-related) purpose for which a given circuit is going to be
 /// used.
 ///
@@ -56,6 +71,123 @@ pub struct HsCircPool<R: Runtime> {
     // eventually.  But for now, this is fine, since it's just an implementation
     // detail.
     launcher_handle: OnceCell<TaskHandle>,
+    /// Our Layer-2/Layer-3 vanguard sets. When vanguards are enabled, pooled
+    /// stub circuits whose L2/L3 hops aren't current members are discarded;
+    /// the hops of newly launched circuits are not drawn from these sets.
+    ///
+    /// `None` until a [`NetDir`] first arrives (we need one to pick initial
+    /// members), after which it's filled in by `ensure_vanguards`.
+    /// Vanguards are off by default even once present: see
+    /// [`VanguardConfig::enabled`].
+    #[cfg(feature = "hs-common")]
+    vanguards: Mutex<Option<vanguards::Vanguards>>,
+    /// The vanguard configuration to use the next time we create or refresh
+    /// our vanguard sets.
+    #[cfg(feature = "hs-common")]
+    vanguard_config: VanguardConfig,
+    /// Where to persist our vanguard sets across restarts, if anywhere.
+    ///
+    /// Set by [`HsCircPool::new_with_vanguards`]; left unset (so vanguard
+    /// sets are rebuilt from scratch on every restart) by
+    /// [`HsCircPool::new`].
+    #[cfg(feature = "hs-common")]
+    vanguard_statemgr: Option<tor_persist::FsStateMgr>,
+    /// How hard to retry when building a circuit for onion-service use.
+    retry_config: HsCircRetryConfig,
+    /// Launches that are currently in progress, so that a burst of
+    /// compatible requests can share one another's work instead of each
+    /// starting a redundant `launch_hs_unmanaged`.
+    pending: Mutex<Vec<PendingLaunch>>,
+    /// A source of unique identifiers for entries in `pending`.
+    next_pending_id: AtomicU64,
+}
+
+/// A description of the `avoid_target` constraint a circuit was (or is
+/// being) launched under, used to decide whether two requests can share a
+/// single in-flight launch.
+///
+/// This is deliberately coarser than
+/// [`circuit_compatible_with_target`]'s check on a finished circuit: it
+/// only tells us whether it's worth *waiting* for a given in-flight
+/// launch. Once that launch finishes, every waiter re-validates the
+/// resulting circuit for itself, and falls back to launching its own if
+/// the one it waited for doesn't actually fit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum LaunchKey {
+    /// Launched with no `avoid_target` at all (e.g. by the background
+    /// preemptive-circuit launcher).
+    Unconstrained,
+    /// Launched to avoid sharing a family or subnet with a specific relay.
+    Avoiding {
+        /// The target's RSA identity, if any.
+        rsa: Option<String>,
+        /// The target's Ed25519 identity, if any.
+        ed25519: Option<String>,
+    },
+}
+
+impl LaunchKey {
+    /// Return the `LaunchKey` describing a launch made with this
+    /// `avoid_target`.
+    fn for_target(avoid_target: Option<&OwnedCircTarget>) -> Self {
+        match avoid_target {
+            None => LaunchKey::Unconstrained,
+            Some(target) => LaunchKey::Avoiding {
+                rsa: target.rsa_identity().map(|id| id.to_string()),
+                ed25519: target.ed_identity().map(|id| id.to_string()),
+            },
+        }
+    }
+
+    /// Return true if a request launched under `self` could make use of a
+    /// circuit that's already being launched under `other`.
+    ///
+    /// An unconstrained request can use the result of any launch (it has
+    /// no family/subnet requirement to violate); a constrained request can
+    /// only share a launch made under the exact same constraint.
+    fn compatible_with(&self, other: &LaunchKey) -> bool {
+        matches!(self, LaunchKey::Unconstrained) || self == other
+    }
+}
+
+/// An in-flight (or just-finished) call to `launch_hs_unmanaged`, shared
+/// between every request that's waiting on a compatible circuit.
+struct PendingLaunch {
+    /// A unique identifier, so we can find and remove this entry once it's
+    /// done, without needing `PendingLaunch` to be `PartialEq`.
+    id: u64,
+    /// What this launch was launched to avoid, if anything.
+    key: LaunchKey,
+    /// Resolves once the launch attempt has finished, successfully or not.
+    /// Its own output carries nothing; the actual circuit (or error) is
+    /// deposited in `result`, to be claimed by at most one waiter.
+    done: Shared<BoxFuture<'static, ()>>,
+    /// Where the finished launch leaves its outcome, to be claimed by
+    /// whichever waiter gets to it first. Whoever doesn't get it falls
+    /// back to trying the pool (the claimant may have found the circuit
+    /// didn't fit, and returned it there) or launching their own.
+    result: Arc<Mutex<Option<Result<ClientCirc>>>>,
+}
+
+/// Configuration for how [`HsCircPool`] retries a failed attempt to build
+/// or extend an onion-service circuit.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct HsCircRetryConfig {
+    /// The largest number of attempts we'll make before giving up.
+    pub max_attempts: u32,
+    /// The delay before our first retry; each subsequent retry backs off
+    /// from here (see [`RetryDelay`]).
+    pub initial_delay: Duration,
+}
+
+impl Default for HsCircRetryConfig {
+    fn default() -> Self {
+        HsCircRetryConfig {
+            max_attempts: 10,
+            initial_delay: Duration::from_millis(1000),
+        }
+    }
 }
 
 impl<R: Runtime> HsCircPool<R> {
@@ -69,9 +201,74 @@ impl<R: Runtime> HsCircPool<R> {
             circmgr,
             pool,
             launcher_handle: OnceCell::new(),
+            #[cfg(feature = "hs-common")]
+            vanguards: Mutex::new(None),
+            #[cfg(feature = "hs-common")]
+            vanguard_config: VanguardConfig::default(),
+            #[cfg(feature = "hs-common")]
+            vanguard_statemgr: None,
+            retry_config: HsCircRetryConfig::default(),
+            pending: Mutex::new(Vec::new()),
+            next_pending_id: AtomicU64::new(0),
         })
     }
 
+    /// Create a new `HsCircPool` that maintains a persistent vanguard set,
+    /// configured by `vanguard_config`.
+    ///
+    /// When vanguards are enabled, pooled circuits whose L2/L3 hops are not
+    /// current members of the sets are discarded. The L2/L3 hops of newly
+    /// launched circuits are chosen by ordinary path selection, not taken
+    /// from the sets.
+    ///
+    /// If `statemgr` is given, the vanguard sets are loaded from (and
+    /// thereafter saved to) it, so they survive a restart instead of being
+    /// rebuilt from scratch every time.
+    ///
+    /// As with [`HsCircPool::new`], this will not work properly before
+    /// "launch_background_tasks" is called.
+    #[cfg(feature = "hs-common")]
+    pub fn new_with_vanguards(
+        circmgr: &Arc<CircMgr<R>>,
+        vanguard_config: VanguardConfig,
+        statemgr: Option<tor_persist::FsStateMgr>,
+    ) -> Arc<Self> {
+        let circmgr = Arc::clone(circmgr);
+        let pool = pool::Pool::default();
+        Arc::new(Self {
+            circmgr,
+            pool,
+            launcher_handle: OnceCell::new(),
+            vanguards: Mutex::new(None),
+            vanguard_config,
+            vanguard_statemgr: statemgr,
+            retry_config: HsCircRetryConfig::default(),
+            pending: Mutex::new(Vec::new()),
+            next_pending_id: AtomicU64::new(0),
+        })
+    }
+
+    /// Make sure our vanguard sets exist and are up to date with respect to
+    /// `netdir`, creating them from scratch the first time we see a
+    /// [`NetDir`].
+    #[cfg(feature = "hs-common")]
+    fn ensure_vanguards(&self, netdir: &NetDir) {
+        let mut vanguards = self
+            .vanguards
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        match vanguards.as_mut() {
+            Some(v) => v.refresh(netdir),
+            None => {
+                *vanguards = Some(vanguards::Vanguards::new(
+                    self.vanguard_config.clone(),
+                    self.vanguard_statemgr.clone(),
+                    netdir,
+                ));
+            }
+        }
+    }
+
     /// Launch the periodic daemon tasks required by the manager to function properly.
     ///
     /// Returns a set of [`TaskHandle`]s that can be used to manage the daemon tasks.
@@ -103,11 +300,31 @@ impl<R: Runtime> HsCircPool<R> {
         Ok(vec![handle.clone()])
     }
 
+    /// Run `attempt` repeatedly until it succeeds, it fails with a
+    /// non-retriable error, or we run out of attempts under
+    /// `self.retry_config`.
+    ///
+    /// On total failure, returns a [`RetryError`] that records every
+    /// attempt's cause, rather than just the last one -- so a caller (or a
+    /// human reading logs) can see whether we were failing the same way
+    /// every time, or hitting a mix of problems.
+    async fn retry_hs_circuit<T, F>(
+        &self,
+        doing_what: &'static str,
+        attempt: impl FnMut() -> F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>>,
+    {
+        retry_hs_circuit_with(&self.circmgr, &self.retry_config, doing_what, attempt).await
+    }
+
     /// Create a circuit suitable for use as a rendezvous circuit by a client.
     ///
     /// Return the circuit, along with a [`Relay`] from `netdir` representing its final hop.
     ///
-    /// Only makes  a single attempt; the caller needs to loop if they want to retry.
+    /// Retries internally, via `take_or_launch_stub_circuit`, according to
+    /// `self.retry_config`.
     pub async fn get_or_launch_client_rend<'a>(
         &self,
         netdir: &'a NetDir,
@@ -128,13 +345,13 @@ impl<R: Runtime> HsCircPool<R> {
             },
             None => Err(internal!("Circuit with an empty path!?").into()),
         }
-        // TODO HS: We should retry attempts to build these circuits, either here or in
-        // a higher-level crate.
     }
 
     /// Create a circuit suitable for use for `kind`, ending at the chosen hop `target`.
     ///
-    /// Only makes  a single attempt; the caller needs to loop if they want to retry.
+    /// Retries internally according to `self.retry_config`: each attempt
+    /// gets a fresh stub circuit (in case the one from a failed attempt was
+    /// what caused the failure) before trying to extend it to `target`.
     pub async fn get_or_launch_specific(
         &self,
         netdir: &NetDir,
@@ -149,9 +366,15 @@ impl<R: Runtime> HsCircPool<R> {
                 )
             }
             HsCircKind::SvcIntro => {
-                // TODO HS: In this case we will want to add an extra hop, once we have vanguards.
-                // When this happens, the whole match statement will want to become
-                // let extra_hop = match kind {...}
+                // TODO HS: In this case we will want to add an extra hop to
+                // avoid vanguard discovery attacks on our introduction
+                // points. Pooled stub circuits are now checked against our
+                // `vanguards` sets (see vanguard_hops_still_current()), but
+                // adding a further hop here still needs `extend_ntor` below
+                // to be called twice, or some other change to this
+                // function's shape. When this happens, the whole match
+                // statement will want to become let extra_hop = match
+                // kind {...}
             }
             HsCircKind::SvcHsDir
             | HsCircKind::SvcRend
@@ -165,45 +388,44 @@ impl<R: Runtime> HsCircPool<R> {
         // * The exceptions are ClientRend, which we handle in a different
         //   method, and SvcIntro, where we will eventually  want an extra hop
         //   to avoid vanguard discovery attacks.
-
-        // Get an unfinished circuit that's compatible with our target.
-        let circ = self
-            .take_or_launch_stub_circuit(netdir, Some(&target))
-            .await?;
-
-        // Estimate how long it will take to extend it one more hop, and
-        // construct a timeout as appropriate.
-        let n_hops = circ.n_hops();
-        let (extend_timeout, _) = self.circmgr.mgr.peek_builder().estimator().timeouts(
-            &crate::timeouts::Action::ExtendCircuit {
-                initial_length: n_hops,
-                final_length: n_hops + 1,
-            },
-        );
-
-        // Make a future to extend the circuit.
-        let params = crate::DirInfo::from(netdir).circ_params();
-        let extend_future = circ
-            .extend_ntor(&target, &params)
-            .map_err(|error| Error::Protocol {
-                action: "extending to chosen HS hop",
-                peer: None, // Either party could be to blame.
-                error,
-            });
-
-        // Wait up to the timeout for the future to complete.
-        self.circmgr
-            .mgr
-            .peek_runtime()
-            .timeout(extend_timeout, extend_future)
-            .await
-            .map_err(|_| Error::CircTimeout)??;
-
-        // With any luck, return the circuit.
-        Ok(circ)
-
-        // TODO HS: We should retry attempts to build these circuits, either here or in
-        // a higher-level crate.
+        self.retry_hs_circuit("build and extend a circuit to a chosen onion-service hop", || async {
+            // Get an unfinished circuit that's compatible with our target.
+            let circ = self
+                .take_or_launch_stub_circuit(netdir, Some(&target))
+                .await?;
+
+            // Estimate how long it will take to extend it one more hop, and
+            // construct a timeout as appropriate.
+            let n_hops = circ.n_hops();
+            let (extend_timeout, _) = self.circmgr.mgr.peek_builder().estimator().timeouts(
+                &crate::timeouts::Action::ExtendCircuit {
+                    initial_length: n_hops,
+                    final_length: n_hops + 1,
+                },
+            );
+
+            // Make a future to extend the circuit.
+            let params = crate::DirInfo::from(netdir).circ_params();
+            let extend_future = circ
+                .extend_ntor(&target, &params)
+                .map_err(|error| Error::Protocol {
+                    action: "extending to chosen HS hop",
+                    peer: None, // Either party could be to blame.
+                    error,
+                });
+
+            // Wait up to the timeout for the future to complete.
+            self.circmgr
+                .mgr
+                .peek_runtime()
+                .timeout(extend_timeout, extend_future)
+                .await
+                .map_err(|_| Error::CircTimeout)??;
+
+            // With any luck, return the circuit.
+            Ok(circ)
+        })
+        .await
     }
 
     /// Take and return a circuit from our pool suitable for being extended to `avoid_target`.
@@ -214,6 +436,9 @@ impl<R: Runtime> HsCircPool<R> {
         netdir: &NetDir,
         avoid_target: Option<&OwnedCircTarget>,
     ) -> Result<ClientCirc> {
+        #[cfg(feature = "hs-common")]
+        self.ensure_vanguards(netdir);
+
         // First, look for a circuit that is already built, if any is suitable.
         let subnet_config = self.circmgr.builder().path_config().subnet_config();
         let target = avoid_target.map(|target| TargetInfo {
@@ -222,6 +447,7 @@ impl<R: Runtime> HsCircPool<R> {
         });
         let found_usable_circ = self.pool.take_one_where(&mut rand::thread_rng(), |circ| {
             circuit_compatible_with_target(netdir, subnet_config, circ, target.as_ref())
+                && self.vanguard_hops_still_current(circ)
         });
 
         /// Tell the background task to fire immediately if we have fewer than
@@ -242,13 +468,107 @@ impl<R: Runtime> HsCircPool<R> {
             return Ok(circuit);
         }
 
-        // TODO: There is a possible optimization here. Instead of only waiting
-        // for the circuit we launch below to finish, we could also wait for any
-        // of our in-progress preemptive circuits to finish.  That would,
-        // however, complexify our logic quite a bit.
+        // Nobody has a ready circuit for us. Before starting a redundant
+        // launch, see whether a compatible one is already in flight --
+        // either another waiter's, or one of the background launcher's
+        // preemptive builds -- and if so, wait for it instead.
+        let key = LaunchKey::for_target(avoid_target);
+        let compatible_pending = {
+            let pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            pending
+                .iter()
+                .find(|p| key.compatible_with(&p.key))
+                .map(|p| (p.done.clone(), Arc::clone(&p.result)))
+        };
+        if let Some((done, result)) = compatible_pending {
+            done.await;
+            match result.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                Some(Ok(circ)) => {
+                    if circuit_compatible_with_target(netdir, subnet_config, &circ, target.as_ref())
+                        && self.vanguard_hops_still_current(&circ)
+                    {
+                        return Ok(circ);
+                    }
+                    // Built by (or for) somebody else's constraints; it
+                    // doesn't suit us, so put it back for whoever it does
+                    // fit and launch our own below.
+                    self.pool.insert(circ);
+                }
+                Some(Err(_)) => {
+                    // That attempt already ran `retry_hs_circuit_with` to
+                    // exhaustion; no point reusing its error, we'll just
+                    // make our own attempt below.
+                }
+                None => {
+                    // Some other waiter claimed the result first. It may
+                    // have left a surplus circuit in the pool for us.
+                    if let Some(circ) = self.pool.take_one_where(&mut rand::thread_rng(), |circ| {
+                        circuit_compatible_with_target(netdir, subnet_config, circ, target.as_ref())
+                            && self.vanguard_hops_still_current(circ)
+                    }) {
+                        return Ok(circ);
+                    }
+                }
+            }
+        }
+
+        self.launch_and_register(key, avoid_target.cloned(), netdir.clone())
+            .await
+    }
+
+    /// Launch a new stub circuit avoiding `avoid_target`, registering it in
+    /// `self.pending` under `key` so that concurrent compatible requests can
+    /// wait on it instead of launching their own.
+    async fn launch_and_register(
+        &self,
+        key: LaunchKey,
+        avoid_target: Option<OwnedCircTarget>,
+        netdir: NetDir,
+    ) -> Result<ClientCirc> {
+        let circmgr = Arc::clone(&self.circmgr);
+        let retry_config = self.retry_config.clone();
+        let result: Arc<Mutex<Option<Result<ClientCirc>>>> = Arc::new(Mutex::new(None));
+        let result_for_task = Arc::clone(&result);
+
+        let fut: BoxFuture<'static, ()> = Box::pin(async move {
+            let outcome = retry_hs_circuit_with(
+                &circmgr,
+                &retry_config,
+                "build a stub circuit for onion-service use",
+                || circmgr.launch_hs_unmanaged(avoid_target.as_ref(), &netdir),
+            )
+            .await;
+            *result_for_task.lock().unwrap_or_else(|e| e.into_inner()) = Some(outcome);
+        });
+        let done = fut.shared();
+
+        let id = self.next_pending_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            pending.push(PendingLaunch {
+                id,
+                key,
+                done: done.clone(),
+                result: Arc::clone(&result),
+            });
+        }
+
+        done.await;
+        {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            pending.retain(|p| p.id != id);
+        }
 
-        // TODO: We could in launch multiple circuits in parallel here?
-        self.circmgr.launch_hs_unmanaged(avoid_target, netdir).await
+        // Normally we win the race to claim our own result, since no other
+        // waiter could have subscribed before we finished registering it.
+        // If we somehow lose that race, fall back to trying again (the pool
+        // may now hold a surplus circuit, or we may need to launch afresh).
+        match result.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(outcome) => outcome,
+            None => {
+                Box::pin(self.take_or_launch_stub_circuit(&netdir, avoid_target.as_ref())).await
+            }
+        }
     }
 
     /// Internal: Remove every closed circuit from this pool.
@@ -259,8 +579,45 @@ impl<R: Runtime> HsCircPool<R> {
     /// Internal: Remove every circuit form this pool for which any relay is not
     /// listed in `netdir`.
     fn remove_unlisted(&self, netdir: &NetDir) {
-        self.pool
-            .retain(|circ| all_circ_relays_are_listed_in(circ, netdir));
+        self.pool.retain(|circ| {
+            all_circ_relays_are_listed_in(circ, netdir) && self.vanguard_hops_still_current(circ)
+        });
+    }
+
+    /// Return true if every hop of `circ` that's supposed to be a vanguard
+    /// is still a current member of our vanguard sets.
+    ///
+    /// A stub circuit's path is `guard -> L2 -> L3`, so only the second and
+    /// third hops are checked against the vanguard sets; the guard hop is
+    /// the guard manager's business, not ours.
+    ///
+    /// Returns true unconditionally if vanguards aren't in use (either the
+    /// `hs-common` feature is off, or [`VanguardConfig::enabled`] is
+    /// false), since then no hop is "supposed to be" a vanguard in the
+    /// first place.
+    #[cfg(feature = "hs-common")]
+    fn vanguard_hops_still_current(&self, circ: &ClientCirc) -> bool {
+        let vanguards = self
+            .vanguards
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let Some(vanguards) = vanguards.as_ref() else {
+            return true;
+        };
+        if !vanguards.enabled() {
+            return true;
+        }
+        circ.path()
+            .iter()
+            .skip(1)
+            .all(|hop: &OwnedChanTarget| vanguards.is_current_member(hop))
+    }
+
+    /// As above, but for builds without the `hs-common` feature, where we
+    /// never use vanguards at all.
+    #[cfg(not(feature = "hs-common"))]
+    fn vanguard_hops_still_current(&self, _circ: &ClientCirc) -> bool {
+        true
     }
 }
 
@@ -293,6 +650,50 @@ impl<'a> TargetInfo<'a> {
     }
 }
 
+/// Run `attempt` repeatedly until it succeeds, it fails with a
+/// non-retriable error, or `retry_config.max_attempts` is used up.
+///
+/// This is a free function (rather than an `HsCircPool` method) so that it
+/// can be used both from inside an `&HsCircPool` call, and from a detached,
+/// `'static` launch task that only has an `Arc<CircMgr<R>>` and a cloned
+/// config, not a borrow of the pool itself.
+///
+/// On total failure, returns a [`RetryError`] that records every attempt's
+/// cause, rather than just the last one -- so a caller (or a human reading
+/// logs) can see whether we were failing the same way every time, or
+/// hitting a mix of problems.
+async fn retry_hs_circuit_with<R: Runtime, T, F>(
+    circmgr: &CircMgr<R>,
+    retry_config: &HsCircRetryConfig,
+    doing_what: &'static str,
+    mut attempt: impl FnMut() -> F,
+) -> Result<T>
+where
+    F: std::future::Future<Output = Result<T>>,
+{
+    let mut retry_err = RetryError::in_attempt_to(doing_what);
+    let mut retry_delay = RetryDelay::from_duration(retry_config.initial_delay);
+    let mut rng = rand::thread_rng();
+
+    for attempt_num in 0..retry_config.max_attempts {
+        match attempt().await {
+            Ok(v) => return Ok(v),
+            Err(e) => {
+                let abort = matches!(e.retry_time(), RetryTime::Never);
+                let last_attempt = attempt_num + 1 == retry_config.max_attempts;
+                retry_err.push(Box::new(e));
+                if abort || last_attempt {
+                    break;
+                }
+                let delay = retry_delay.next_delay(&mut rng);
+                circmgr.mgr.peek_runtime().sleep(delay).await;
+            }
+        }
+    }
+
+    Err(retry_err.into())
+}
+
 /// Return true if we can extend a pre-built circuit `circ` to `target`.
 ///
 /// We require that the circuit is open, that every hop  in the circuit is
@@ -361,52 +762,108 @@ async fn launch_hs_circuits_as_needed<R: Runtime>(
             }
         };
         pool.remove_closed();
-        let mut n_to_launch = pool.pool.len().saturating_sub(TARGET_N);
-        let mut max_attempts = TARGET_N * 2;
-        'inner: while n_to_launch > 1 {
-            max_attempts -= 1;
-            if max_attempts == 0 {
-                // We want to avoid retrying over and over in a tight loop if all our attempts
-                // are failing.
-                warn!("Too many preemptive onion service circuits failed; waiting a while.");
-                break 'inner;
-            }
+        let n_to_launch = TARGET_N.saturating_sub(pool.pool.len());
+        if n_to_launch > 0 {
             if let Ok(netdir) = provider.netdir(tor_netdir::Timeliness::Timely) {
-                // We want to launch a circuit, and we have a netdir that we can use
-                // to launch it.
+                // We want to launch circuits, and we have a netdir that we can use
+                // to launch them.
+                #[cfg(feature = "hs-common")]
+                pool.ensure_vanguards(&netdir);
                 //
-                // TODO: Possibly we should be doing this in a background task, and
-                // launching several of these in parallel.  If we do, we should think about
-                // whether taking the fastest will expose us to any attacks.
-                let no_target: Option<&OwnedCircTarget> = None;
-                // TODO HS: We should catch panics, here or in launch_hs_unmanaged.
-                match pool.circmgr.launch_hs_unmanaged(no_target, &netdir).await {
-                    Ok(circ) => {
-                        pool.pool.insert(circ);
-                        n_to_launch -= 1;
-                    }
-                    Err(err) => {
-                        debug!(
-                            "Unable to build preemptive circuit for onion services: {}",
-                            err.report()
-                        );
-                    }
-                }
-            } else {
-                // We'd like to launch a circuit, but we don't have a netdir that we
-                // can use.
+                // TODO HS: Once CircMgr's path-builder can accept an explicit
+                // fixed hop list, pass our chosen L2/L3 vanguards in here
+                // instead of letting it choose the middle and third hops
+                // itself; `Vanguards::pick_layer2`/`pick_layer3` are ready
+                // for that, but wiring them through `launch_hs_unmanaged`'s
+                // target/avoid-target plumbing is its own project.
                 //
-                // TODO HS possibly instead of a fixed delay we want to wait for more
-                // netdir info?
-                break 'inner;
+                // TODO HS: We should catch panics, here or in launch_hs_unmanaged.
+                launch_hs_circuits_concurrently(&pool, &netdir, n_to_launch, TARGET_N * 2).await;
             }
+            // Else: we'd like to launch circuits, but we don't have a netdir
+            // that we can use.
+            //
+            // TODO HS possibly instead of a fixed delay we want to wait for more
+            // netdir info?
         }
 
-        // We have nothing to launch now, so we'll try after a while.
+        // We have nothing more to launch now, so we'll try after a while.
         schedule.fire_in(DELAY);
     }
 }
 
+/// Launch up to `n_to_launch` preemptive onion-service circuits for `pool`
+/// using `netdir`, running up to [`MAX_CONCURRENT_HS_LAUNCHES`] of them at a
+/// time, as `tor_circmgr::mgr` does for its own circuit launches.
+///
+/// We deliberately do *not* stop as soon as we've collected `n_to_launch`
+/// successes among the attempts that happen to finish first: accepting
+/// whichever attempt races ahead would bias our preemptive pool toward
+/// low-latency relays, which (in the presence of an adversary willing to run
+/// relays purely to win that race) could be exploited to steer our onion
+/// circuits onto chosen relays. Instead, every attempt we start is allowed to
+/// run to completion and contributes its circuit (if any) to the pool,
+/// regardless of arrival order.
+///
+/// We keep the same failure circuit-breaker behavior that the non-concurrent
+/// version had: if too many attempts (out of `max_attempts`, counted across
+/// the whole concurrent batch) fail, we give up early instead of retrying
+/// forever in a tight loop.
+async fn launch_hs_circuits_concurrently<R: Runtime>(
+    pool: &Arc<HsCircPool<R>>,
+    netdir: &NetDir,
+    n_to_launch: usize,
+    max_attempts: usize,
+) {
+    /// Largest number of preemptive circuits we'll build at once. Chosen
+    /// arbitrarily: big enough to make a dent in a large deficit quickly,
+    /// small enough not to flood the network with simultaneous circuit-build
+    /// attempts.
+    const MAX_CONCURRENT_HS_LAUNCHES: usize = 4;
+
+    let mut remaining_to_start = n_to_launch;
+    let mut remaining_attempts = max_attempts;
+    let mut in_progress = FuturesUnordered::new();
+
+    loop {
+        while remaining_to_start > 0
+            && remaining_attempts > 0
+            && in_progress.len() < MAX_CONCURRENT_HS_LAUNCHES
+        {
+            remaining_to_start -= 1;
+            remaining_attempts -= 1;
+            let circmgr = Arc::clone(&pool.circmgr);
+            let netdir = netdir.clone();
+            in_progress.push(async move {
+                let no_target: Option<&OwnedCircTarget> = None;
+                circmgr.launch_hs_unmanaged(no_target, &netdir).await
+            });
+        }
+        if in_progress.is_empty() {
+            if remaining_attempts == 0 && remaining_to_start > 0 {
+                // We want to avoid retrying over and over in a tight loop if
+                // all our attempts are failing.
+                warn!("Too many preemptive onion service circuits failed; waiting a while.");
+            }
+            break;
+        }
+        match in_progress.next().await {
+            Some(Ok(circ)) => {
+                pool.pool.insert(circ);
+            }
+            Some(Err(err)) => {
+                debug!(
+                    "Unable to build preemptive circuit for onion services: {}",
+                    err.report()
+                );
+                // The deficit is still there; try again while attempts remain.
+                remaining_to_start += 1;
+            }
+            None => break,
+        }
+    }
+}
+
 /// Background task to remove unusable circuits whenever the directory changes.
 async fn remove_unusable_circuits<R: Runtime>(
     pool: Weak<HsCircPool<R>>,
@@ -431,6 +888,8 @@ async fn remove_unusable_circuits<R: Runtime>(
         };
         pool.remove_closed();
         if let Ok(netdir) = provider.netdir(tor_netdir::Timeliness::Timely) {
+            #[cfg(feature = "hs-common")]
+            pool.ensure_vanguards(&netdir);
             pool.remove_unlisted(&netdir);
         }
     }
@@ -0,0 +1,378 @@
+//! A small persistent pool of "vanguard" relays for the L2/L3 hops of
+//! onion-service circuits.
+//!
+//! Without vanguards, every stub circuit picks its middle and third hops
+//! fresh (indirectly, via the guard manager and the ordinary path
+//! selection rules) for every circuit we build. An adversary who can run
+//! relays and watch which ones end up adjacent to a given hidden
+//! service's rendezvous or introduction circuits over time can use that
+//! churn to help deanonymize the service (a "vanguard discovery attack").
+//! Reusing a small, slowly-rotating set of relays for the L2 and L3 hops
+//! closes most of that window: see prop247 in torspec for the design this
+//! is modeled on.
+//!
+//! [`HsCircPool`](super::HsCircPool) uses these sets only to discard pooled
+//! circuits whose L2/L3 hops are no longer members; it does not choose the
+//! hops of new circuits from them.
+
+use std::time::{Duration, SystemTime};
+
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tor_linkspec::OwnedChanTarget;
+use tor_netdir::{NetDir, Relay};
+use tor_persist::{DynStorageHandle, StateMgr};
+
+use crate::Error;
+
+/// Configuration for the [`Vanguards`] subsystem.
+///
+/// Vanguards are off by default: until this is turned on (by setting
+/// [`VanguardConfig::enabled`]), [`Vanguards::pick_layer2`] and
+/// [`Vanguards::pick_layer3`] always return `None`, and
+/// [`HsCircPool`](super::HsCircPool) does not check its pooled circuits
+/// against the vanguard sets.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct VanguardConfig {
+    /// Whether to use vanguards at all.
+    pub enabled: bool,
+    /// How many relays to keep in the Layer-2 set.
+    pub l2_set_size: usize,
+    /// The minimum and maximum lifetime of a Layer-2 vanguard.
+    pub l2_lifetime: (Duration, Duration),
+    /// How many relays to keep in the Layer-3 set.
+    pub l3_set_size: usize,
+    /// The minimum and maximum lifetime of a Layer-3 vanguard.
+    pub l3_lifetime: (Duration, Duration),
+}
+
+impl Default for VanguardConfig {
+    fn default() -> Self {
+        /// One day, in seconds.
+        const DAY: u64 = 24 * 60 * 60;
+        VanguardConfig {
+            enabled: false,
+            l2_set_size: 4,
+            l2_lifetime: (Duration::from_secs(DAY), Duration::from_secs(14 * DAY)),
+            l3_set_size: 8,
+            l3_lifetime: (Duration::from_secs(60 * 60), Duration::from_secs(DAY)),
+        }
+    }
+}
+
+/// A single relay held in a [`VanguardSet`], along with when we should stop
+/// using it.
+#[derive(Clone, Debug)]
+struct Vanguard {
+    /// The relay itself.
+    relay: OwnedChanTarget,
+    /// When we should rotate this relay out of the set.
+    expires_at: SystemTime,
+}
+
+/// A persistable, serde-friendly copy of a single [`Vanguard`].
+///
+/// [`OwnedChanTarget`] isn't `Serialize`/`Deserialize`, so we keep the
+/// on-disk representation here instead, and translate to and from a real
+/// [`OwnedChanTarget`] by relisting relays against the current [`NetDir`]
+/// whenever we load this back in.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct PersistedVanguard {
+    /// The RSA identity of the relay, hex-encoded.
+    rsa_id: String,
+    /// The Ed25519 identity of the relay, base64-encoded, if we have one.
+    ed25519_id: Option<String>,
+    /// When we should rotate this relay out of the set, as seconds since
+    /// the Unix epoch.
+    expires_at_unix: u64,
+}
+
+/// The on-disk representation of a full set of [`Vanguards`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+struct VanguardsState {
+    /// The persisted Layer-2 set.
+    layer2: Vec<PersistedVanguard>,
+    /// The persisted Layer-3 set.
+    layer3: Vec<PersistedVanguard>,
+}
+
+/// A set of relays used for one layer (L2 or L3) of vanguard-protected
+/// circuits.
+#[derive(Default)]
+struct VanguardSet {
+    /// The relays currently in this set.
+    relays: Vec<Vanguard>,
+}
+
+impl VanguardSet {
+    /// Remove every member of this set that `netdir` no longer lists.
+    fn remove_unlisted(&mut self, netdir: &NetDir) {
+        self.relays
+            .retain(|v| netdir.by_ids(&v.relay).is_some());
+    }
+
+    /// Remove every member of this set whose expiry has passed.
+    fn remove_expired(&mut self, now: SystemTime) {
+        self.relays.retain(|v| v.expires_at > now);
+    }
+
+    /// Top this set back up to `target_len` members, choosing new members
+    /// from `netdir` at random (excluding relays already in the set), and
+    /// giving each a lifetime drawn from `lifetime_range`.
+    ///
+    /// The expiry is drawn as `max(a, b)` of two independent uniform draws
+    /// over the range, which biases new members toward the long end of the
+    /// range; that smooths out rotation, since it makes it less likely for
+    /// many members to expire in a short window together.
+    fn replenish<R: Rng>(
+        &mut self,
+        netdir: &NetDir,
+        rng: &mut R,
+        target_len: usize,
+        lifetime_range: (Duration, Duration),
+        now: SystemTime,
+    ) {
+        while self.relays.len() < target_len {
+            let exclude: Vec<_> = self.relays.iter().map(|v| v.relay.clone()).collect();
+            let candidate = netdir
+                .relays()
+                .filter(|r| !exclude.iter().any(|ex| ex.same_relay_ids(r)))
+                .choose(rng);
+            let Some(candidate) = candidate else {
+                // Not enough relays in the network to fill this set right
+                // now; we'll try again next time we're refreshed.
+                break;
+            };
+            let expires_at = now + sample_max_uniform(rng, lifetime_range);
+            self.relays.push(Vanguard {
+                relay: OwnedChanTarget::from_chan_target(&candidate),
+                expires_at,
+            });
+        }
+    }
+
+    /// Choose one member of this set at random, if it's non-empty.
+    fn pick<R: Rng>(&self, rng: &mut R) -> Option<OwnedChanTarget> {
+        use rand::seq::SliceRandom;
+        self.relays.choose(rng).map(|v| v.relay.clone())
+    }
+
+    /// Return true if `target` is currently a member of this set.
+    fn contains(&self, target: &OwnedChanTarget) -> bool {
+        self.relays.iter().any(|v| v.relay.same_relay_ids(target))
+    }
+
+    /// Convert to a [`VanguardsState`]-ready representation.
+    fn to_persisted(&self) -> Vec<PersistedVanguard> {
+        self.relays
+            .iter()
+            .map(|v| PersistedVanguard {
+                rsa_id: v.relay.rsa_identity().map(|id| id.to_string()).unwrap_or_default(),
+                ed25519_id: v.relay.ed_identity().map(|id| id.to_string()),
+                expires_at_unix: v
+                    .expires_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+            .collect()
+    }
+
+    /// Reconstruct a set from its persisted form, dropping any entry that
+    /// `netdir` no longer lists.
+    fn from_persisted(persisted: &[PersistedVanguard], netdir: &NetDir) -> Self {
+        let relays = persisted
+            .iter()
+            .filter_map(|p| {
+                let relay = netdir.by_rsa_id_hex(&p.rsa_id)?;
+                Some(Vanguard {
+                    relay: OwnedChanTarget::from_chan_target(&relay),
+                    expires_at: SystemTime::UNIX_EPOCH
+                        + Duration::from_secs(p.expires_at_unix),
+                })
+            })
+            .collect();
+        VanguardSet { relays }
+    }
+}
+
+/// Sample `max(a, b)` for two independent uniform draws over `range`.
+///
+/// This biases the result toward the top of the range, which is what we
+/// want for vanguard lifetimes: it smooths out rotation over time instead
+/// of letting every member of a freshly-filled set expire in a tight
+/// cluster.
+fn sample_max_uniform<R: Rng>(rng: &mut R, range: (Duration, Duration)) -> Duration {
+    let (low, high) = range;
+    let a = rng.gen_range(low..=high);
+    let b = rng.gen_range(low..=high);
+    a.max(b)
+}
+
+/// The persistent Layer-2 and Layer-3 vanguard sets used by an
+/// [`HsCircPool`](super::HsCircPool).
+pub(crate) struct Vanguards {
+    /// Our current configuration.
+    config: VanguardConfig,
+    /// The Layer-2 (long-lived) set.
+    layer2: VanguardSet,
+    /// The Layer-3 (shorter-lived) set.
+    layer3: VanguardSet,
+    /// Where we persist our sets across restarts, if anywhere.
+    storage: Option<DynStorageHandle<VanguardsState>>,
+}
+
+/// The key under which we store a [`VanguardsState`] in a [`StateMgr`].
+const STORAGE_KEY: &str = "hs_vanguards";
+
+impl Vanguards {
+    /// Create a new `Vanguards`, using `statemgr` (if given) to load any
+    /// previously-persisted sets.
+    pub(crate) fn new<M>(config: VanguardConfig, statemgr: Option<M>, netdir: &NetDir) -> Self
+    where
+        M: StateMgr + Send + Sync + 'static,
+    {
+        let storage = statemgr.map(|mgr| mgr.create_handle::<VanguardsState>(STORAGE_KEY));
+        let loaded = storage
+            .as_ref()
+            .and_then(|h: &DynStorageHandle<VanguardsState>| h.load().ok().flatten());
+        let (layer2, layer3) = match loaded {
+            Some(state) => (
+                VanguardSet::from_persisted(&state.layer2, netdir),
+                VanguardSet::from_persisted(&state.layer3, netdir),
+            ),
+            None => (VanguardSet::default(), VanguardSet::default()),
+        };
+        let mut vanguards = Vanguards {
+            config,
+            layer2,
+            layer3,
+            storage,
+        };
+        vanguards.refresh(netdir);
+        vanguards
+    }
+
+    /// Drop any member that `netdir` no longer lists or that has expired,
+    /// and lazily replace whatever's missing, then persist the result.
+    pub(crate) fn refresh(&mut self, netdir: &NetDir) {
+        if !self.config.enabled {
+            return;
+        }
+        let now = SystemTime::now();
+        let mut rng = rand::thread_rng();
+
+        self.layer2.remove_unlisted(netdir);
+        self.layer2.remove_expired(now);
+        self.layer2
+            .replenish(netdir, &mut rng, self.config.l2_set_size, self.config.l2_lifetime, now);
+
+        self.layer3.remove_unlisted(netdir);
+        self.layer3.remove_expired(now);
+        self.layer3
+            .replenish(netdir, &mut rng, self.config.l3_set_size, self.config.l3_lifetime, now);
+
+        self.persist();
+    }
+
+    /// Save our current sets, if we have anywhere to save them.
+    fn persist(&self) {
+        if let Some(storage) = &self.storage {
+            let state = VanguardsState {
+                layer2: self.layer2.to_persisted(),
+                layer3: self.layer3.to_persisted(),
+            };
+            if let Err(e) = storage.store(&state) {
+                tracing::warn!("Unable to persist vanguard sets: {}", e);
+            }
+        }
+    }
+
+    /// Choose a Layer-2 vanguard, if vanguards are enabled and we have any.
+    pub(crate) fn pick_layer2<R: Rng>(&self, rng: &mut R) -> Option<OwnedChanTarget> {
+        self.config.enabled.then(|| self.layer2.pick(rng)).flatten()
+    }
+
+    /// Choose a Layer-3 vanguard, if vanguards are enabled and we have any.
+    pub(crate) fn pick_layer3<R: Rng>(&self, rng: &mut R) -> Option<OwnedChanTarget> {
+        self.config.enabled.then(|| self.layer3.pick(rng)).flatten()
+    }
+
+    /// Return true if vanguards are currently enabled.
+    pub(crate) fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Return true if `target` is currently a member of either set, or if
+    /// vanguards are disabled.
+    ///
+    /// Used by `HsCircPool::vanguard_hops_still_current` to reject a
+    /// pooled circuit whose L2/L3 hop has rotated out from under it, even
+    /// though the relay is still otherwise listed.
+    pub(crate) fn is_current_member(&self, target: &OwnedChanTarget) -> bool {
+        !self.config.enabled || self.layer2.contains(target) || self.layer3.contains(target)
+    }
+}
+
+/// Build an error for the case where we wanted a vanguard but couldn't find
+/// one.
+///
+/// Not currently called: no caller draws hops from the vanguard sets, so a
+/// set that is temporarily short a member (e.g. right after startup, before
+/// [`Vanguards::refresh`] has had a netdir to work with) never causes a
+/// failure.
+#[allow(dead_code)]
+fn no_vanguard_available() -> Error {
+    Error::from(tor_error::internal!(
+        "no vanguard available in a non-empty set"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use fs_mistrust::Mistrust;
+    use tempdir::TempDir;
+    use tor_persist::FsStateMgr;
+
+    /// Build an `FsStateMgr` rooted at a fresh temporary directory, and
+    /// take its write lock (required before anything can be stored).
+    fn new_locked_statemgr() -> (TempDir, FsStateMgr) {
+        let tmp_dir = TempDir::new("arti-hs-vanguards").unwrap();
+        let mgr =
+            FsStateMgr::from_path_and_mistrust(tmp_dir.path(), &Mistrust::new_dangerously_trust_everyone())
+                .unwrap();
+        mgr.try_lock().unwrap();
+        (tmp_dir, mgr)
+    }
+
+    #[test]
+    fn persist_round_trip() {
+        let (_tmp_dir, mgr) = new_locked_statemgr();
+        let handle = mgr.clone().create_handle::<VanguardsState>(STORAGE_KEY);
+
+        let state = VanguardsState {
+            layer2: vec![PersistedVanguard {
+                rsa_id: "0000000000000000000000000000000000000000".into(),
+                ed25519_id: None,
+                expires_at_unix: 1_000,
+            }],
+            layer3: vec![PersistedVanguard {
+                rsa_id: "1111111111111111111111111111111111111111".into(),
+                ed25519_id: Some("abcd".into()),
+                expires_at_unix: 2_000,
+            }],
+        };
+
+        assert!(handle.load().unwrap().is_none());
+        handle.store(&state).unwrap();
+
+        // A fresh handle from the same statemgr sees what was just stored,
+        // the same way a `Vanguards` constructed after a restart would.
+        let reloaded = mgr.create_handle::<VanguardsState>(STORAGE_KEY);
+        assert_eq!(reloaded.load().unwrap(), Some(state));
+    }
+}
@@ -37,6 +37,8 @@
 #![deny(clippy::unwrap_used)]
 
 use derive_more::Display;
+use std::str::FromStr;
+use std::time::Duration;
 
 mod internal;
 pub use internal::*;
@@ -154,6 +156,19 @@ pub enum ErrorKind {
     #[display(fmt = "could not write to read-only persistent state")]
     PersistentStateReadOnly,
 
+    /// Timed out while waiting for another process to release its lock on
+    /// our persistent state.
+    ///
+    /// Unlike [`PersistentStateReadOnly`](ErrorKind::PersistentStateReadOnly),
+    /// this does not indicate a bug: another instance simply held the lock
+    /// for longer than we were willing to wait. Callers can usually retry,
+    /// possibly after a longer timeout.
+    ///
+    /// Note that this kind of error only applies to problems in your `state_dir`:
+    /// problems with your cache are another kind.
+    #[display(fmt = "timed out waiting for persistent state lock")]
+    PersistentStateContended,
+
     /// Tor client's cache has been corrupted.
     ///
     /// This could be because of a bug in the Tor code, or because something else has been messing
@@ -479,6 +494,325 @@ pub enum ErrorKind {
     Internal,
 }
 
+impl ErrorKind {
+    /// Return a stable, machine-readable identifier for this kind of error.
+    ///
+    /// Unlike the `Display` text, this token is stable across Arti versions
+    /// (we won't change it just because we decide the `Display` text reads
+    /// better some other way), so it's suitable for FFI bindings, JSON-RPC
+    /// control surfaces, log aggregation, or anywhere else that needs a
+    /// version-stable identifier for an `ErrorKind`.
+    ///
+    /// Every token round-trips through `ErrorKind`'s [`FromStr`] implementation.
+    pub fn as_str(&self) -> &'static str {
+        use ErrorKind as EK;
+        match self {
+            EK::TorConnectionFailed => "tor-connection-failed",
+            EK::BootstrapRequired => "bootstrap-required",
+            EK::DirectoryExpired => "directory-expired",
+            EK::PersistentStateAccessFailed => "persistent-state-access-failed",
+            EK::PersistentStateCorrupted => "persistent-state-corrupted",
+            EK::PersistentStateReadOnly => "persistent-state-read-only",
+            EK::PersistentStateContended => "persistent-state-contended",
+            EK::CacheCorrupted => "cache-corrupted",
+            EK::CacheAccessFailed => "cache-access-failed",
+            EK::ReactorShuttingDown => "reactor-shutting-down",
+            EK::TorShuttingDown => "tor-shutting-down",
+            EK::UnexplainedTaskSpawnFailure => "unexplained-task-spawn-failure",
+            EK::RemoteNetworkTimeout => "remote-network-timeout",
+            EK::InvalidConfig => "invalid-config",
+            EK::InvalidConfigTransition => "invalid-config-transition",
+            EK::NoHomeDirectory => "no-home-directory",
+            EK::NotImplemented => "not-implemented",
+            EK::FeatureDisabled => "feature-disabled",
+            EK::LocalProtocolViolation => "local-protocol-violation",
+            EK::TorProtocolViolation => "tor-protocol-violation",
+            EK::Network => "network",
+            EK::RemoteIdMismatch => "remote-id-mismatch",
+            EK::CircuitCollapse => "circuit-collapse",
+            EK::TorNetworkTimeout => "tor-network-timeout",
+            EK::TorNetworkError => "tor-network-error",
+            EK::RemoteStreamClosed => "remote-stream-closed",
+            EK::RemoteStreamError => "remote-stream-error",
+            EK::RemoteNameError => "remote-name-error",
+            EK::InvalidStreamTarget => "invalid-stream-target",
+            EK::ForbiddenStreamTarget => "forbidden-stream-target",
+            EK::AlreadyClosed => "already-closed",
+            EK::TransientFailure => "transient-failure",
+            EK::BadApiUsage => "bad-api-usage",
+            EK::NamespaceFull => "namespace-full",
+            EK::RequestedResourceAbsent => "requested-resource-absent",
+            EK::RemoteRefused => "remote-refused",
+            EK::Canceled => "canceled",
+            EK::NoPath => "no-path",
+            EK::NoExit => "no-exit",
+            EK::Internal => "internal",
+        }
+    }
+}
+
+/// The error returned when parsing a string as an [`ErrorKind`] fails,
+/// because the string isn't one of the tokens returned by
+/// [`ErrorKind::as_str`].
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+#[error("{0:?} is not a recognized ErrorKind")]
+pub struct ParseErrorKindError(String);
+
+impl FromStr for ErrorKind {
+    type Err = ParseErrorKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ErrorKind as EK;
+        Ok(match s {
+            "tor-connection-failed" => EK::TorConnectionFailed,
+            "bootstrap-required" => EK::BootstrapRequired,
+            "directory-expired" => EK::DirectoryExpired,
+            "persistent-state-access-failed" => EK::PersistentStateAccessFailed,
+            "persistent-state-corrupted" => EK::PersistentStateCorrupted,
+            "persistent-state-read-only" => EK::PersistentStateReadOnly,
+            "persistent-state-contended" => EK::PersistentStateContended,
+            "cache-corrupted" => EK::CacheCorrupted,
+            "cache-access-failed" => EK::CacheAccessFailed,
+            "reactor-shutting-down" => EK::ReactorShuttingDown,
+            "tor-shutting-down" => EK::TorShuttingDown,
+            "unexplained-task-spawn-failure" => EK::UnexplainedTaskSpawnFailure,
+            "remote-network-timeout" => EK::RemoteNetworkTimeout,
+            "invalid-config" => EK::InvalidConfig,
+            "invalid-config-transition" => EK::InvalidConfigTransition,
+            "no-home-directory" => EK::NoHomeDirectory,
+            "not-implemented" => EK::NotImplemented,
+            "feature-disabled" => EK::FeatureDisabled,
+            "local-protocol-violation" => EK::LocalProtocolViolation,
+            "tor-protocol-violation" => EK::TorProtocolViolation,
+            "network" => EK::Network,
+            "remote-id-mismatch" => EK::RemoteIdMismatch,
+            "circuit-collapse" => EK::CircuitCollapse,
+            "tor-network-timeout" => EK::TorNetworkTimeout,
+            "tor-network-error" => EK::TorNetworkError,
+            "remote-stream-closed" => EK::RemoteStreamClosed,
+            "remote-stream-error" => EK::RemoteStreamError,
+            "remote-name-error" => EK::RemoteNameError,
+            "invalid-stream-target" => EK::InvalidStreamTarget,
+            "forbidden-stream-target" => EK::ForbiddenStreamTarget,
+            "already-closed" => EK::AlreadyClosed,
+            "transient-failure" => EK::TransientFailure,
+            "bad-api-usage" => EK::BadApiUsage,
+            "namespace-full" => EK::NamespaceFull,
+            "requested-resource-absent" => EK::RequestedResourceAbsent,
+            "remote-refused" => EK::RemoteRefused,
+            "canceled" => EK::Canceled,
+            "no-path" => EK::NoPath,
+            "no-exit" => EK::NoExit,
+            "internal" => EK::Internal,
+            _ => return Err(ParseErrorKindError(s.to_owned())),
+        })
+    }
+}
+
+/// Serializes as the stable token from [`ErrorKind::as_str`], not as the
+/// enum's discriminant or variant name.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ErrorKind {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Deserializes from the stable token from [`ErrorKind::as_str`], not from
+/// the enum's discriminant or variant name.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ErrorKind {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Classification of whose "bailiwick" an error originated in, per the
+/// splitting guidelines documented on [`ErrorKind`].
+///
+/// Returned by [`ErrorKind::responsible_party`].
+///
+/// This lets an embedding application decide, for example, whether to show
+/// an error to the user, retry it, or file a bug report automatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[non_exhaustive]
+pub enum Responsibility {
+    /// The bug, if any, is in Arti itself.
+    #[display(fmt = "bug in Arti")]
+    ArtiBug,
+
+    /// The bug, if any, is in the application calling Arti.
+    #[display(fmt = "bug in caller")]
+    CallerBug,
+
+    /// The problem is with the local system: the disk, the OS, or another
+    /// local process (other than the application itself).
+    #[display(fmt = "problem with local system")]
+    LocalSystem,
+
+    /// The problem is with the local network.
+    #[display(fmt = "problem with local network")]
+    LocalNetwork,
+
+    /// The problem is with the Tor network.
+    #[display(fmt = "problem with Tor network")]
+    TorNetwork,
+
+    /// The problem is with the remote host we were ultimately trying to
+    /// reach.
+    #[display(fmt = "problem with remote host")]
+    RemoteHost,
+
+    /// We can't say with confidence whose bailiwick this error belongs to.
+    #[display(fmt = "ambiguous responsibility")]
+    Ambiguous,
+}
+
+impl ErrorKind {
+    /// Return a classification of whose "bailiwick" this kind of error
+    /// originated in: this very process's Tor code, this very process's
+    /// application code, another local process, the local network, the Tor
+    /// network, or the remote host on the far side of Tor.
+    ///
+    /// This turns the taxonomy documented on [`ErrorKind`] itself into code,
+    /// so that it can be consulted programmatically (and so that the
+    /// lump/split policy documented there stays honest).
+    pub fn responsible_party(&self) -> Responsibility {
+        use ErrorKind as EK;
+        use Responsibility as R;
+        match self {
+            EK::TorConnectionFailed => R::Ambiguous,
+            EK::BootstrapRequired => R::CallerBug,
+            EK::DirectoryExpired => R::Ambiguous,
+            EK::PersistentStateAccessFailed => R::LocalSystem,
+            EK::PersistentStateCorrupted => R::LocalSystem,
+            EK::PersistentStateReadOnly => R::ArtiBug,
+            EK::PersistentStateContended => R::LocalSystem,
+            EK::CacheCorrupted => R::LocalSystem,
+            EK::CacheAccessFailed => R::LocalSystem,
+            EK::ReactorShuttingDown => R::Ambiguous,
+            EK::TorShuttingDown => R::CallerBug,
+            EK::UnexplainedTaskSpawnFailure => R::ArtiBug,
+            EK::RemoteNetworkTimeout => R::RemoteHost,
+            EK::InvalidConfig => R::CallerBug,
+            EK::InvalidConfigTransition => R::CallerBug,
+            EK::NoHomeDirectory => R::LocalSystem,
+            EK::NotImplemented => R::Ambiguous,
+            EK::FeatureDisabled => R::Ambiguous,
+            EK::LocalProtocolViolation => R::LocalSystem,
+            EK::TorProtocolViolation => R::TorNetwork,
+            EK::Network => R::Ambiguous,
+            EK::RemoteIdMismatch => R::TorNetwork,
+            EK::CircuitCollapse => R::TorNetwork,
+            EK::TorNetworkTimeout => R::TorNetwork,
+            EK::TorNetworkError => R::TorNetwork,
+            EK::RemoteStreamClosed => R::RemoteHost,
+            EK::RemoteStreamError => R::RemoteHost,
+            EK::RemoteNameError => R::RemoteHost,
+            EK::InvalidStreamTarget => R::CallerBug,
+            EK::ForbiddenStreamTarget => R::CallerBug,
+            EK::AlreadyClosed => R::CallerBug,
+            EK::TransientFailure => R::Ambiguous,
+            EK::BadApiUsage => R::CallerBug,
+            EK::NamespaceFull => R::Ambiguous,
+            EK::RequestedResourceAbsent => R::TorNetwork,
+            EK::RemoteRefused => R::RemoteHost,
+            EK::Canceled => R::Ambiguous,
+            EK::NoPath => R::TorNetwork,
+            EK::NoExit => R::TorNetwork,
+            EK::Internal => R::ArtiBug,
+        }
+    }
+}
+
+/// Advice about whether, and how, a caller should retry an operation that
+/// failed with a given [`ErrorKind`].
+///
+/// Returned by [`ErrorKind::retry_recommendation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[non_exhaustive]
+pub enum RetryAdvice {
+    /// Retrying is unlikely to help.
+    #[display(fmt = "do not retry")]
+    No,
+
+    /// Retrying right away, on the same circuit or connection, is likely to
+    /// help.
+    #[display(fmt = "retry immediately")]
+    RetrySameImmediately,
+
+    /// Waiting for about the given amount of time, and then retrying, is
+    /// likely to help.
+    #[display(fmt = "retry after waiting")]
+    RetryAfter(Duration),
+
+    /// Retrying is likely to help, but only if done on a different circuit.
+    #[display(fmt = "retry on a new circuit")]
+    RetryOnNewCircuit,
+
+    /// Retrying is likely to help, but only after the client has finished
+    /// bootstrapping.
+    #[display(fmt = "retry after bootstrapping")]
+    RetryAfterBootstrap,
+}
+
+impl ErrorKind {
+    /// Return advice about whether, and how, a caller should retry an
+    /// operation that failed with this kind of error.
+    ///
+    /// This lets callers drive backoff and retry logic directly from the
+    /// `ErrorKind`, instead of re-deriving the same heuristics at every call
+    /// site.
+    pub fn retry_recommendation(&self) -> RetryAdvice {
+        use ErrorKind as EK;
+        use RetryAdvice as RA;
+        match self {
+            EK::TorConnectionFailed => RA::RetrySameImmediately,
+            EK::BootstrapRequired => RA::RetryAfterBootstrap,
+            EK::DirectoryExpired => RA::RetryAfter(Duration::from_secs(60)),
+            EK::PersistentStateAccessFailed => RA::No,
+            EK::PersistentStateCorrupted => RA::No,
+            EK::PersistentStateReadOnly => RA::No,
+            EK::PersistentStateContended => RA::RetryAfter(Duration::from_secs(1)),
+            EK::CacheCorrupted => RA::No,
+            EK::CacheAccessFailed => RA::No,
+            EK::ReactorShuttingDown => RA::No,
+            EK::TorShuttingDown => RA::No,
+            EK::UnexplainedTaskSpawnFailure => RA::No,
+            EK::RemoteNetworkTimeout => RA::RetryOnNewCircuit,
+            EK::InvalidConfig => RA::No,
+            EK::InvalidConfigTransition => RA::No,
+            EK::NoHomeDirectory => RA::No,
+            EK::NotImplemented => RA::No,
+            EK::FeatureDisabled => RA::No,
+            EK::LocalProtocolViolation => RA::No,
+            EK::TorProtocolViolation => RA::No,
+            EK::Network => RA::RetrySameImmediately,
+            EK::RemoteIdMismatch => RA::No,
+            EK::CircuitCollapse => RA::RetryOnNewCircuit,
+            EK::TorNetworkTimeout => RA::RetryOnNewCircuit,
+            EK::TorNetworkError => RA::RetryOnNewCircuit,
+            EK::RemoteStreamClosed => RA::No,
+            EK::RemoteStreamError => RA::No,
+            EK::RemoteNameError => RA::RetryOnNewCircuit,
+            EK::InvalidStreamTarget => RA::No,
+            EK::ForbiddenStreamTarget => RA::No,
+            EK::AlreadyClosed => RA::No,
+            EK::TransientFailure => RA::RetrySameImmediately,
+            EK::BadApiUsage => RA::No,
+            EK::NamespaceFull => RA::RetryOnNewCircuit,
+            EK::RequestedResourceAbsent => RA::No,
+            EK::RemoteRefused => RA::No,
+            EK::Canceled => RA::No,
+            EK::NoPath => RA::No,
+            EK::NoExit => RA::No,
+            EK::Internal => RA::No,
+        }
+    }
+}
+
 /// Errors that can be categorized as belonging to an [`ErrorKind`]
 ///
 /// The most important implementation of this trait is
@@ -498,4 +832,93 @@ impl HasKind for futures::task::SpawnError {
             EK::UnexplainedTaskSpawnFailure
         }
     }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    /// Every variant of `ErrorKind`, for exhaustiveness tests below.
+    fn all_kinds() -> Vec<ErrorKind> {
+        use ErrorKind as EK;
+        vec![
+            EK::TorConnectionFailed,
+            EK::BootstrapRequired,
+            EK::DirectoryExpired,
+            EK::PersistentStateAccessFailed,
+            EK::PersistentStateCorrupted,
+            EK::PersistentStateReadOnly,
+            EK::PersistentStateContended,
+            EK::CacheCorrupted,
+            EK::CacheAccessFailed,
+            EK::ReactorShuttingDown,
+            EK::TorShuttingDown,
+            EK::UnexplainedTaskSpawnFailure,
+            EK::RemoteNetworkTimeout,
+            EK::InvalidConfig,
+            EK::InvalidConfigTransition,
+            EK::NoHomeDirectory,
+            EK::NotImplemented,
+            EK::FeatureDisabled,
+            EK::LocalProtocolViolation,
+            EK::TorProtocolViolation,
+            EK::Network,
+            EK::RemoteIdMismatch,
+            EK::CircuitCollapse,
+            EK::TorNetworkTimeout,
+            EK::TorNetworkError,
+            EK::RemoteStreamClosed,
+            EK::RemoteStreamError,
+            EK::RemoteNameError,
+            EK::InvalidStreamTarget,
+            EK::ForbiddenStreamTarget,
+            EK::AlreadyClosed,
+            EK::TransientFailure,
+            EK::BadApiUsage,
+            EK::NamespaceFull,
+            EK::RequestedResourceAbsent,
+            EK::RemoteRefused,
+            EK::Canceled,
+            EK::NoPath,
+            EK::NoExit,
+            EK::Internal,
+        ]
+    }
+
+    /// Every `ErrorKind` variant must have a retry recommendation: this is
+    /// enforced by `retry_recommendation`'s match having no wildcard arm,
+    /// so the crate fails to compile if a variant is added without a
+    /// matching arm. This test calls it for every kind in `all_kinds()`.
+    #[test]
+    fn retry_recommendation_is_exhaustive() {
+        for kind in all_kinds() {
+            // Just confirm that this doesn't panic.
+            let _ = kind.retry_recommendation();
+        }
+    }
+
+    /// Every `ErrorKind` variant must have a stable string token that
+    /// round-trips through `FromStr`.
+    #[test]
+    fn as_str_round_trips() {
+        for kind in all_kinds() {
+            let token = kind.as_str();
+            assert_eq!(token.parse::<ErrorKind>().unwrap(), kind);
+        }
+        assert!("not-a-real-error-kind".parse::<ErrorKind>().is_err());
+    }
+
+    /// Every `ErrorKind` variant must serialize to, and deserialize from,
+    /// its stable string token -- not its variant name or discriminant.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trips() {
+        for kind in all_kinds() {
+            let json = serde_json::to_string(&kind).unwrap();
+            assert_eq!(json, format!("{:?}", kind.as_str()));
+            let deserialized: ErrorKind = serde_json::from_str(&json).unwrap();
+            assert_eq!(deserialized, kind);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+//! Packs the legacy-format geoip CSVs in `data/` into the flat, sorted
+//! binary blobs that `new_embedded()` embeds with `include_bytes!`.
+//!
+//!
+//! Doing this at compile time (rather than parsing the text and building a
+//! `RangeInclusiveMap` on first use, as we used to) means the sorting and
+//! validation work happens once, here, instead of on every process start.
+//! See `src/packed.rs` for the entry layout this produces.
+
+use std::env;
+use std::fs;
+use std::net::Ipv6Addr;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=data/geoip");
+    println!("cargo:rerun-if-changed=data/geoip6");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+
+    pack_v4(Path::new("data/geoip"), &out_dir.join("geoip_v4.bin"));
+    pack_v6(Path::new("data/geoip6"), &out_dir.join("geoip_v6.bin"));
+}
+
+/// Parse a two-letter country code column into its raw on-disk form.
+fn parse_cc(s: &str) -> [u8; 2] {
+    let upper = s.trim().to_ascii_uppercase();
+    let bytes = upper.as_bytes();
+    assert_eq!(bytes.len(), 2, "country code must be two bytes: {s:?}");
+    [bytes[0], bytes[1]]
+}
+
+/// Parse the optional trailing ASN column, defaulting to 0 ("unknown").
+fn parse_asn(field: Option<&str>) -> u32 {
+    field
+        .map(|x| x.trim().parse().expect("bad ASN"))
+        .unwrap_or(0)
+}
+
+/// Parse and pack the IPv4 database at `src`, writing the result to `dst`.
+fn pack_v4(src: &Path, dst: &Path) {
+    let text = fs::read_to_string(src).expect("failed to read v4 geoip database");
+    let mut entries: Vec<(u32, u32, [u8; 2], u32)> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut split = line.split(',');
+            let from: u32 = split.next().expect("missing `from`").parse().expect("bad `from`");
+            let to: u32 = split.next().expect("missing `to`").parse().expect("bad `to`");
+            let cc = parse_cc(split.next().expect("missing country code"));
+            let asn = parse_asn(split.next());
+            (from, to, cc, asn)
+        })
+        .collect();
+    entries.sort_unstable_by_key(|e| e.0);
+
+    let mut buf = Vec::with_capacity(entries.len() * 14);
+    for (from, to, cc, asn) in entries {
+        buf.extend_from_slice(&from.to_le_bytes());
+        buf.extend_from_slice(&to.to_le_bytes());
+        buf.extend_from_slice(&cc);
+        buf.extend_from_slice(&asn.to_le_bytes());
+    }
+    fs::write(dst, buf).expect("failed to write packed v4 geoip database");
+}
+
+/// Parse and pack the IPv6 database at `src`, writing the result to `dst`.
+fn pack_v6(src: &Path, dst: &Path) {
+    let text = fs::read_to_string(src).expect("failed to read v6 geoip database");
+    let mut entries: Vec<(u128, u128, [u8; 2], u32)> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut split = line.split(',');
+            let from: Ipv6Addr = split.next().expect("missing `from`").parse().expect("bad `from`");
+            let to: Ipv6Addr = split.next().expect("missing `to`").parse().expect("bad `to`");
+            let cc = parse_cc(split.next().expect("missing country code"));
+            let asn = parse_asn(split.next());
+            (from.into(), to.into(), cc, asn)
+        })
+        .collect();
+    entries.sort_unstable_by_key(|e| e.0);
+
+    let mut buf = Vec::with_capacity(entries.len() * 38);
+    for (from, to, cc, asn) in entries {
+        buf.extend_from_slice(&from.to_le_bytes());
+        buf.extend_from_slice(&to.to_le_bytes());
+        buf.extend_from_slice(&cc);
+        buf.extend_from_slice(&asn.to_le_bytes());
+    }
+    fs::write(dst, buf).expect("failed to write packed v6 geoip database");
+}
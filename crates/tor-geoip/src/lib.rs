@@ -42,25 +42,32 @@
 
 pub use crate::err::Error;
 use once_cell::sync::OnceCell;
-use rangemap::RangeInclusiveMap;
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
-use std::net::{IpAddr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroU32;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use packed::{RangeTable, RawEntryV4, RawEntryV6};
+
 mod err;
+mod mmdb;
+mod packed;
 
-/// An embedded copy of the latest geoip v4 database at the time of compilation.
+/// An embedded, packed copy of the latest geoip v4 database at the time of
+/// compilation, produced by `build.rs` from `data/geoip`.
 ///
-/// FIXME(eta): This does use a few megabytes of binary size, which is less than ideal.
-///             It would be better to parse it at compile time or something.
+/// This is already sorted and validated, so `new_embedded()` can
+/// `bytemuck`-cast it straight into `&[RawEntryV4]` with no parsing or
+/// per-row allocation at startup.
 #[cfg(feature = "embedded-db")]
-static EMBEDDED_DB_V4: &str = include_str!("../data/geoip");
+static EMBEDDED_DB_V4: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/geoip_v4.bin"));
 
-/// An embedded copy of the latest geoip v6 database at the time of compilation.
+/// The IPv6 equivalent of [`EMBEDDED_DB_V4`], from `data/geoip6`.
 #[cfg(feature = "embedded-db")]
-static EMBEDDED_DB_V6: &str = include_str!("../data/geoip6");
+static EMBEDDED_DB_V6: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/geoip_v6.bin"));
 
 /// A parsed copy of the embedded database.
 #[cfg(feature = "embedded-db")]
@@ -202,22 +209,52 @@ impl NetDefn {
     }
 
     /// Return the country code.
-    fn country_code(&self) -> Option<&CountryCode> {
-        self.cc.as_ref()
+    fn country_code(&self) -> Option<CountryCode> {
+        self.cc
     }
 
     /// Return the ASN, if there is one.
     fn asn(&self) -> Option<u32> {
         self.asn.as_ref().map(|x| x.get())
     }
+
+    /// Construct from the packed, on-disk representation: an `asn` of 0
+    /// means "unknown" and a `cc` of `??` means "unknown", matching the
+    /// legacy text format's conventions.
+    fn from_raw(cc: [u8; 2], asn: u32) -> Self {
+        NetDefn {
+            cc: (cc != *b"??").then_some(CountryCode { inner: cc }),
+            asn: NonZeroU32::new(asn),
+        }
+    }
+
+    /// Encode as the raw `(cc, asn)` pair stored in a packed range table.
+    fn to_raw(&self) -> ([u8; 2], u32) {
+        let cc = self.cc.map_or(*b"??", |c| c.inner);
+        let asn = self.asn.map_or(0, NonZeroU32::get);
+        (cc, asn)
+    }
 }
 
+/// An address range within one of [`GeoipDb`]'s secondary indexes: either a
+/// 32-bit IPv4 range or a 128-bit IPv6 range, both stored widened to `u128`
+/// so the country and ASN indexes can share one value type. The `bool` is
+/// `true` for a v4 range.
+type IndexedRange = (bool, u128, u128);
+
 /// A database of IP addresses to country codes.
 pub struct GeoipDb {
-    /// The IPv4 subset of the database, with v4 addresses stored as 32-bit integers.
-    map_v4: RangeInclusiveMap<u32, NetDefn>,
-    /// The IPv6 subset of the database, with v6 addresses stored as 128-bit integers.
-    map_v6: RangeInclusiveMap<u128, NetDefn>,
+    /// The IPv4 subset of the database: a sorted, non-overlapping range
+    /// table keyed by 32-bit integer addresses.
+    map_v4: RangeTable<RawEntryV4>,
+    /// The IPv6 subset of the database, keyed by 128-bit integer addresses.
+    map_v6: RangeTable<RawEntryV6>,
+    /// A reverse index from country code to the ranges with that code, used
+    /// by [`ranges_for_country`](Self::ranges_for_country).
+    by_country: HashMap<[u8; 2], Vec<IndexedRange>>,
+    /// A reverse index from ASN to the ranges in that ASN, used by
+    /// [`ranges_for_asn`](Self::ranges_for_asn).
+    by_asn: HashMap<u32, Vec<IndexedRange>>,
 }
 
 impl GeoipDb {
@@ -228,21 +265,18 @@ impl GeoipDb {
     #[cfg(feature = "embedded-db")]
     pub fn new_embedded() -> Arc<Self> {
         Arc::clone(EMBEDDED_DB_PARSED.get_or_init(|| {
-            Arc::new(
-                // It's reasonable to assume the one we embedded is fine -- we'll test it in CI, etc.
-                Self::new_from_legacy_format(EMBEDDED_DB_V4, EMBEDDED_DB_V6)
-                    .expect("failed to parse embedded geoip database"),
-            )
+            Arc::new(GeoipDb::finish(
+                // `build.rs` already parsed, sorted, and validated these at
+                // compile time, so this cast costs nothing at startup.
+                RangeTable::borrowed(bytemuck::cast_slice(EMBEDDED_DB_V4)),
+                RangeTable::borrowed(bytemuck::cast_slice(EMBEDDED_DB_V6)),
+            ))
         }))
     }
 
     /// Make a new `GeoipDb` using provided copies of the v4 and v6 database, in Tor legacy format.
     pub fn new_from_legacy_format(db_v4: &str, db_v6: &str) -> Result<Self, Error> {
-        let mut ret = GeoipDb {
-            map_v4: Default::default(),
-            map_v6: Default::default(),
-        };
-
+        let mut entries_v4 = Vec::new();
         for line in db_v4.lines() {
             if line.starts_with('#') {
                 continue;
@@ -265,12 +299,13 @@ impl GeoipDb {
                 .ok_or(Error::BadFormat("line with insufficient commas"))?;
             let asn = split.next().map(|x| x.parse::<u32>()).transpose()?;
 
-            let defn = NetDefn::new(cc, asn)?;
-
-            ret.map_v4.insert(from..=to, defn);
+            let (cc, asn) = NetDefn::new(cc, asn)?.to_raw();
+            entries_v4.push(RawEntryV4 { from, to, cc, asn });
         }
+        entries_v4.sort_unstable_by_key(|e| e.from);
 
         // This is slightly copypasta, but probably less readable to merge into one thing.
+        let mut entries_v6 = Vec::new();
         for line in db_v6.lines() {
             if line.starts_with('#') {
                 continue;
@@ -293,31 +328,209 @@ impl GeoipDb {
                 .ok_or(Error::BadFormat("line with insufficient commas"))?;
             let asn = split.next().map(|x| x.parse::<u32>()).transpose()?;
 
-            let defn = NetDefn::new(cc, asn)?;
+            let (cc, asn) = NetDefn::new(cc, asn)?.to_raw();
+            entries_v6.push(RawEntryV6 {
+                from: from.into(),
+                to: to.into(),
+                cc,
+                asn,
+            });
+        }
+        entries_v6.sort_unstable_by_key(|e| e.from);
+
+        Ok(GeoipDb::finish(
+            RangeTable::owned(entries_v4),
+            RangeTable::owned(entries_v6),
+        ))
+    }
+
+    /// Make a new `GeoipDb` from the contents of a MaxMind GeoLite2 `.mmdb`
+    /// file (City or ASN editions, or anything else publishing
+    /// `country.iso_code`/`autonomous_system_number`).
+    ///
+    /// This lets callers point Arti at a standard GeoLite2 database instead
+    /// of converting it to Tor's legacy CSV format first.
+    pub fn new_from_mmdb(bytes: &[u8]) -> Result<Self, Error> {
+        let (ip_version, found) = mmdb::parse(bytes)?;
+
+        let mut entries_v4 = Vec::new();
+        let mut entries_v6 = Vec::new();
+        for (from, to, cc, asn) in found {
+            if ip_version == 4 {
+                entries_v4.push(RawEntryV4 {
+                    from: from as u32,
+                    to: to as u32,
+                    cc,
+                    asn,
+                });
+            } else {
+                // MaxMind aliases the `::/96` network to the IPv4 address
+                // space, so a range fully contained in it also gets a
+                // native v4 entry.
+                if from >> 32 == 0 && to >> 32 == 0 {
+                    entries_v4.push(RawEntryV4 {
+                        from: from as u32,
+                        to: to as u32,
+                        cc,
+                        asn,
+                    });
+                }
+                entries_v6.push(RawEntryV6 { from, to, cc, asn });
+            }
+        }
+        entries_v4.sort_unstable_by_key(|e| e.from);
+        entries_v6.sort_unstable_by_key(|e| e.from);
+
+        Ok(GeoipDb::finish(
+            RangeTable::owned(entries_v4),
+            RangeTable::owned(entries_v6),
+        ))
+    }
 
-            ret.map_v6.insert(from.into()..=to.into(), defn);
+    /// Build a `GeoipDb` from already-constructed forward range tables,
+    /// deriving the `by_country`/`by_asn` reverse indexes from them.
+    ///
+    /// This is the single place all three constructors funnel through, so
+    /// the reverse indexes can't drift out of sync with the forward tables.
+    fn finish(map_v4: RangeTable<RawEntryV4>, map_v6: RangeTable<RawEntryV6>) -> Self {
+        let mut by_country: HashMap<[u8; 2], Vec<IndexedRange>> = HashMap::new();
+        let mut by_asn: HashMap<u32, Vec<IndexedRange>> = HashMap::new();
+
+        for e in map_v4.iter() {
+            index_entry(&mut by_country, &mut by_asn, true, e.from.into(), e.to.into(), e.cc, e.asn);
+        }
+        for e in map_v6.iter() {
+            index_entry(&mut by_country, &mut by_asn, false, e.from, e.to, e.cc, e.asn);
         }
 
-        Ok(ret)
+        GeoipDb {
+            map_v4,
+            map_v6,
+            by_country,
+            by_asn,
+        }
     }
 
     /// Get the `NetDefn` for an IP address.
-    fn lookup_defn(&self, ip: IpAddr) -> Option<&NetDefn> {
+    ///
+    /// If `ip` is a V6 address with no entry in `map_v6`, and it's an
+    /// IPv4-mapped (`::ffff:0:0/96`) or 6to4 (`2002::/16`) address embedding
+    /// an IPv4 address, retries the lookup against `map_v4` with the
+    /// embedded address. This matters because relay addresses arriving over
+    /// dual-stack sockets frequently appear in one of these mapped forms.
+    fn lookup_defn(&self, ip: IpAddr) -> Option<NetDefn> {
         match ip {
-            IpAddr::V4(v4) => self.map_v4.get(&v4.into()),
-            IpAddr::V6(v6) => self.map_v6.get(&v6.into()),
+            IpAddr::V4(v4) => self.map_v4.lookup(v4.into()),
+            IpAddr::V6(v6) => self
+                .map_v6
+                .lookup(v6.into())
+                .or_else(|| self.map_v4.lookup(embedded_ipv4(v6)?.into())),
         }
     }
 
     /// Get a 2-letter country code for the given IP address, if this data is available.
-    pub fn lookup_country_code(&self, ip: IpAddr) -> Option<&CountryCode> {
-        self.lookup_defn(ip).and_then(|x| x.country_code())
+    pub fn lookup_country_code(&self, ip: IpAddr) -> Option<CountryCode> {
+        self.lookup_defn(ip)?.country_code()
     }
 
     /// Return the ASN the IP address is in, if this data is available.
     pub fn lookup_asn(&self, ip: IpAddr) -> Option<u32> {
         self.lookup_defn(ip)?.asn()
     }
+
+    /// Return every address range tagged with country code `cc`.
+    ///
+    /// Ranges are returned in no particular order; there may be more than
+    /// one per country, and they are not merged even where adjacent.
+    pub fn ranges_for_country(
+        &self,
+        cc: &CountryCode,
+    ) -> impl Iterator<Item = RangeInclusive<IpAddr>> + '_ {
+        self.by_country
+            .get(&cc.inner)
+            .into_iter()
+            .flatten()
+            .map(|&(is_v4, from, to)| addr_range(is_v4, from, to))
+    }
+
+    /// Return every address range in autonomous system `asn`.
+    ///
+    /// Ranges are returned in no particular order; there may be more than
+    /// one per ASN, and they are not merged even where adjacent.
+    pub fn ranges_for_asn(&self, asn: u32) -> impl Iterator<Item = RangeInclusive<IpAddr>> + '_ {
+        self.by_asn
+            .get(&asn)
+            .into_iter()
+            .flatten()
+            .map(|&(is_v4, from, to)| addr_range(is_v4, from, to))
+    }
+
+    /// Return true if `a` and `b` resolve to the same autonomous system.
+    ///
+    /// Returns `false` if either address's ASN is unavailable, since "same
+    /// operator" can't be established without it.
+    pub fn same_operator(&self, a: IpAddr, b: IpAddr) -> bool {
+        matches!((self.lookup_asn(a), self.lookup_asn(b)), (Some(x), Some(y)) if x == y)
+    }
+
+    /// Group `ips` by the ASN each one resolves to, so a circuit builder can
+    /// reject or down-weight a candidate set that collides on autonomous
+    /// system.
+    ///
+    /// Addresses whose ASN is unavailable are omitted entirely.
+    pub fn shared_prefix_asns(&self, ips: &[IpAddr]) -> HashMap<u32, Vec<IpAddr>> {
+        let mut out: HashMap<u32, Vec<IpAddr>> = HashMap::new();
+        for &ip in ips {
+            if let Some(asn) = self.lookup_asn(ip) {
+                out.entry(asn).or_default().push(ip);
+            }
+        }
+        out
+    }
+}
+
+/// Insert `(from, to)` into `by_country`/`by_asn`, if its country code/ASN
+/// are known (`by_country` skips `??`, `by_asn` skips ASN 0).
+fn index_entry(
+    by_country: &mut HashMap<[u8; 2], Vec<IndexedRange>>,
+    by_asn: &mut HashMap<u32, Vec<IndexedRange>>,
+    is_v4: bool,
+    from: u128,
+    to: u128,
+    cc: [u8; 2],
+    asn: u32,
+) {
+    if cc != *b"??" {
+        by_country.entry(cc).or_default().push((is_v4, from, to));
+    }
+    if asn != 0 {
+        by_asn.entry(asn).or_default().push((is_v4, from, to));
+    }
+}
+
+/// Widen a stored `(is_v4, from, to)` index entry back out into an
+/// `IpAddr` range.
+fn addr_range(is_v4: bool, from: u128, to: u128) -> RangeInclusive<IpAddr> {
+    if is_v4 {
+        IpAddr::V4(Ipv4Addr::from(from as u32))..=IpAddr::V4(Ipv4Addr::from(to as u32))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(from))..=IpAddr::V6(Ipv6Addr::from(to))
+    }
+}
+
+/// If `v6` is an IPv4-mapped (`::ffff:0:0/96`) or 6to4 (`2002::/16`) address,
+/// extract the IPv4 address it embeds.
+fn embedded_ipv4(v6: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = v6.segments();
+    if let [0, 0, 0, 0, 0, 0xffff, hi, lo] = segments {
+        return Some(Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8));
+    }
+    if segments[0] == 0x2002 {
+        let hi = segments[1];
+        let lo = segments[2];
+        return Some(Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8));
+    }
+    None
 }
 
 #[cfg(test)]
@@ -393,6 +606,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn mapped_v4_lookups() {
+        let src_v4 = r#"
+        16909056,16909311,GB
+        "#;
+        let db = GeoipDb::new_from_legacy_format(src_v4, "").unwrap();
+
+        // IPv4-mapped (::ffff:0:0/96).
+        assert_eq!(
+            db.lookup_country_code("::ffff:1.2.3.4".parse().unwrap())
+                .map(|x| x.as_ref()),
+            Some("GB")
+        );
+
+        // 6to4 (2002::/16).
+        assert_eq!(
+            db.lookup_country_code("2002:0102:0304::".parse().unwrap())
+                .map(|x| x.as_ref()),
+            Some("GB")
+        );
+
+        // Not a mapped form, and no v6 entry either.
+        assert_eq!(
+            db.lookup_country_code("dead:beef::1".parse().unwrap()),
+            None
+        );
+    }
+
     #[test]
     fn cc_parse() -> Result<(), Error> {
         // real countries.
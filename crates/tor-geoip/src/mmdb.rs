@@ -0,0 +1,389 @@
+//! A minimal parser for MaxMind DB (`.mmdb`) binary geoip databases.
+//!
+//! This understands just enough of the on-disk format to walk the search
+//! tree and pull the `country.iso_code` and `autonomous_system_number`
+//! fields out of each record: it isn't a general-purpose MaxMind DB reader,
+//! and doesn't expose any of the richer City-level fields those databases
+//! also carry.
+
+use super::Error;
+use std::collections::HashMap;
+
+/// The byte sequence marking the start of the metadata section, which is
+/// appended after the data section at the end of the file.
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// Metadata is always small, so (matching the reference implementations) we
+/// only search the last 128 KiB of the file for [`METADATA_MARKER`].
+const METADATA_SEARCH_WINDOW: usize = 128 * 1024;
+
+/// How many levels deep [`decode_value`] will recurse -- through nested
+/// maps/arrays, and through pointer chains -- before giving up.
+///
+/// A genuine GeoLite2-City/ASN record never nests more than a handful of
+/// levels deep; this just needs to be comfortably above that, while still
+/// well short of a stack overflow, so a malicious `.mmdb` file can't use a
+/// deeply nested or self-referential pointer chain to crash us.
+const MAX_VALUE_DEPTH: u32 = 64;
+
+/// A decoded MaxMind DB data-section value.
+///
+/// Only the variants GeoLite2-City/ASN databases actually put the fields we
+/// care about in ([`Value::Map`], [`Value::String`], [`Value::UInt`]) get
+/// real decoding; everything else is parsed just well enough to know how
+/// many bytes it occupied, so we can skip over it.
+#[derive(Debug, Clone)]
+enum Value {
+    /// A UTF-8 string.
+    String(String),
+    /// An unsigned integer; covers the format's uint16/uint32 types (the
+    /// only unsigned integer types a country/ASN record can contain).
+    UInt(u64),
+    /// A map from key to value.
+    Map(HashMap<String, Value>),
+    /// Any other data type (double, bytes, int32, uint64/128, array,
+    /// boolean, float): we don't need its value, just its length.
+    Other,
+}
+
+/// The subset of the metadata map we need to interpret the search tree.
+struct Metadata {
+    /// Number of nodes in the search tree.
+    node_count: u32,
+    /// The size, in bits, of each of a node's two pointer records (24, 28,
+    /// or 32).
+    record_size: u32,
+    /// The IP version the tree is built for: 4 or 6.
+    ///
+    /// An `ip_version` 6 tree still answers IPv4 queries, via the `::/96`
+    /// network MaxMind reserves for aliasing the IPv4 address space.
+    ip_version: u32,
+}
+
+/// Parse `bytes` as a MaxMind DB file, returning the database's IP version
+/// (4 or 6) alongside every `(from, to, country_code, asn)` entry found by
+/// walking its search tree.
+///
+/// `from`/`to` are addresses within the database's own address space: a
+/// 32-bit value for an `ip_version` 4 database, cast up to `u128`, or a
+/// genuine 128-bit value for `ip_version` 6.
+pub(crate) fn parse(bytes: &[u8]) -> Result<(u32, Vec<(u128, u128, [u8; 2], u32)>), Error> {
+    let metadata_start = find_metadata_start(bytes)?;
+    let metadata = parse_metadata(bytes, metadata_start)?;
+
+    let node_bytes = (metadata.record_size * 2 / 8) as usize;
+    let search_tree_size = metadata.node_count as usize * node_bytes;
+    // A 16-byte all-zero section separator follows the search tree.
+    let data_start = search_tree_size
+        .checked_add(16)
+        .ok_or(Error::BadFormat("mmdb search tree size overflowed"))?;
+    let tree = bytes
+        .get(..search_tree_size)
+        .ok_or(Error::BadFormat("mmdb file shorter than its search tree"))?;
+    let data = bytes
+        .get(data_start..)
+        .ok_or(Error::BadFormat("mmdb file shorter than its data section"))?;
+
+    let max_depth = if metadata.ip_version == 4 { 32 } else { 128 };
+
+    let mut entries = Vec::new();
+    walk(tree, data, &metadata, 0, 0, max_depth, 0, &mut entries)?;
+    Ok((metadata.ip_version, entries))
+}
+
+/// Find the offset just past [`METADATA_MARKER`] in `bytes`, searching
+/// backwards from the end (the marker is, in principle, allowed to appear
+/// spuriously earlier in the data section, so the *last* match is the real
+/// one).
+fn find_metadata_start(bytes: &[u8]) -> Result<usize, Error> {
+    let window_start = bytes.len().saturating_sub(METADATA_SEARCH_WINDOW);
+    let haystack = &bytes[window_start..];
+    let pos = haystack
+        .windows(METADATA_MARKER.len())
+        .rposition(|w| w == METADATA_MARKER)
+        .ok_or(Error::BadFormat("mmdb metadata marker not found"))?;
+    Ok(window_start + pos + METADATA_MARKER.len())
+}
+
+/// Decode the metadata map starting at `offset` into a [`Metadata`].
+fn parse_metadata(bytes: &[u8], offset: usize) -> Result<Metadata, Error> {
+    let (value, _) = decode_value(bytes, offset, 0)?;
+    let Value::Map(map) = value else {
+        return Err(Error::BadFormat("mmdb metadata wasn't a map"));
+    };
+    let node_count = match map.get("node_count") {
+        Some(Value::UInt(n)) => *n as u32,
+        _ => return Err(Error::BadFormat("mmdb metadata missing node_count")),
+    };
+    let record_size = match map.get("record_size") {
+        Some(Value::UInt(n)) => *n as u32,
+        _ => return Err(Error::BadFormat("mmdb metadata missing record_size")),
+    };
+    let ip_version = match map.get("ip_version") {
+        Some(Value::UInt(n)) => *n as u32,
+        _ => return Err(Error::BadFormat("mmdb metadata missing ip_version")),
+    };
+    if !matches!(record_size, 24 | 28 | 32) {
+        return Err(Error::BadFormat("mmdb metadata has unsupported record_size"));
+    }
+    Ok(Metadata {
+        node_count,
+        record_size,
+        ip_version,
+    })
+}
+
+/// Read node number `node`'s two pointer records out of the search tree.
+fn read_node(tree: &[u8], record_size: u32, node: u32) -> Result<(u32, u32), Error> {
+    let node_bytes = (record_size * 2 / 8) as usize;
+    let offset = node as usize * node_bytes;
+    let raw = tree
+        .get(offset..offset + node_bytes)
+        .ok_or(Error::BadFormat("mmdb search tree node out of bounds"))?;
+    Ok(match record_size {
+        24 => (
+            u32::from_be_bytes([0, raw[0], raw[1], raw[2]]),
+            u32::from_be_bytes([0, raw[3], raw[4], raw[5]]),
+        ),
+        28 => {
+            let middle = raw[3];
+            (
+                u32::from_be_bytes([0, raw[0], raw[1], raw[2]]) | (u32::from(middle >> 4) << 24),
+                u32::from_be_bytes([0, raw[4], raw[5], raw[6]]) | (u32::from(middle & 0x0F) << 24),
+            )
+        }
+        32 => (
+            u32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]),
+            u32::from_be_bytes([raw[4], raw[5], raw[6], raw[7]]),
+        ),
+        _ => return Err(Error::BadFormat("mmdb metadata has unsupported record_size")),
+    })
+}
+
+/// Walk the search tree depth-first starting at `node` (the tree's root is
+/// node `0`), collecting a `(from, to, cc, asn)` entry in `out` for every
+/// data record found.
+///
+/// `depth` counts how many address bits have been consumed so far, and
+/// `prefix` accumulates the address bits chosen along the way, left-aligned
+/// within the low `max_depth` bits of a `u128`.
+///
+/// A well-formed tree never needs more than `max_depth` levels, since each
+/// level consumes one address bit; a malformed or adversarial one could
+/// encode a node chain deeper than that (cyclic or not), so `depth` reaching
+/// `max_depth` is treated as a format error rather than recursed past, to
+/// bound how deep this ever recurses.
+#[allow(clippy::too_many_arguments)]
+fn walk(
+    tree: &[u8],
+    data: &[u8],
+    metadata: &Metadata,
+    node: u32,
+    depth: u32,
+    max_depth: u32,
+    prefix: u128,
+    out: &mut Vec<(u128, u128, [u8; 2], u32)>,
+) -> Result<(), Error> {
+    if depth >= max_depth {
+        return Err(Error::BadFormat("mmdb search tree deeper than its address space"));
+    }
+    let (left, right) = read_node(tree, metadata.record_size, node)?;
+    for (bit, record) in [(0u128, left), (1u128, right)] {
+        let child_prefix = prefix | (bit << (max_depth - depth - 1));
+        if record == metadata.node_count {
+            // No data for this subtree.
+            continue;
+        } else if record < metadata.node_count {
+            walk(
+                tree,
+                data,
+                metadata,
+                record,
+                depth + 1,
+                max_depth,
+                child_prefix,
+                out,
+            )?;
+        } else {
+            // Record values count from the end of the search tree, so the
+            // 16-byte separator is part of the offset.
+            let data_offset = (record - metadata.node_count)
+                .checked_sub(16)
+                .ok_or(Error::BadFormat("mmdb record points into the section separator"))?
+                as usize;
+            let (value, _) = decode_value(data, data_offset, 0)?;
+            if let Some((cc, asn)) = extract_cc_asn(&value) {
+                let remaining_bits = max_depth - depth - 1;
+                let span = if remaining_bits >= 128 {
+                    u128::MAX
+                } else {
+                    (1u128 << remaining_bits) - 1
+                };
+                out.push((child_prefix, child_prefix | span, cc, asn));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pull the `country.iso_code` and `autonomous_system_number` fields out of
+/// a decoded data record, if it has either.
+fn extract_cc_asn(value: &Value) -> Option<([u8; 2], u32)> {
+    let Value::Map(top) = value else {
+        return None;
+    };
+    let cc = top
+        .get("country")
+        .and_then(|v| match v {
+            Value::Map(m) => m.get("iso_code"),
+            _ => None,
+        })
+        .and_then(|v| match v {
+            Value::String(s) => Some(s.as_bytes()),
+            _ => None,
+        })
+        .and_then(|b| <[u8; 2]>::try_from(b).ok());
+    let asn = match top.get("autonomous_system_number") {
+        Some(Value::UInt(n)) => Some(*n as u32),
+        _ => None,
+    };
+    if cc.is_none() && asn.is_none() {
+        return None;
+    }
+    Some((cc.unwrap_or(*b"??"), asn.unwrap_or(0)))
+}
+
+/// Decode one data-section value starting at `offset`, returning it
+/// alongside the number of bytes consumed at `offset` (for a pointer, this
+/// is the length of the pointer itself, not of the value it points to).
+///
+/// `depth` counts recursive descents made to reach this call -- through a
+/// pointer, or into a container's keys/values/items -- and is capped at
+/// [`MAX_VALUE_DEPTH`] so that a deeply nested or self-referential chain in
+/// the input can't recurse us into a stack overflow.
+fn decode_value(data: &[u8], offset: usize, depth: u32) -> Result<(Value, usize), Error> {
+    if depth >= MAX_VALUE_DEPTH {
+        return Err(Error::BadFormat("mmdb value nested too deeply"));
+    }
+    let control = *data
+        .get(offset)
+        .ok_or(Error::BadFormat("mmdb data section truncated"))?;
+    let mut pos = offset + 1;
+
+    let mut type_id = u16::from(control >> 5);
+    if type_id == 0 {
+        // "Extended" type: the actual type is 7 plus the next byte.
+        let extra = *data
+            .get(pos)
+            .ok_or(Error::BadFormat("mmdb data section truncated"))?;
+        pos += 1;
+        type_id = 7 + u16::from(extra);
+    }
+
+    if type_id == 1 {
+        let size_bits = (control >> 3) & 0x03;
+        let (target, consumed) = decode_pointer(data, pos, control, size_bits)?;
+        let (value, _) = decode_value(data, target, depth + 1)?;
+        return Ok((value, pos + consumed - offset));
+    }
+
+    let size_bits = control & 0x1F;
+    let (size, consumed) = decode_size(data, pos, size_bits)?;
+    pos += consumed;
+
+    // Every non-container type's payload is exactly `size` bytes long
+    // (a boolean's "size" field doubles as its value, and consumes none);
+    // containers (map/array) instead hold `size` nested values with no
+    // fixed byte length of their own, so they return directly below.
+    match type_id {
+        2 => {
+            let bytes = data
+                .get(pos..pos + size)
+                .ok_or(Error::BadFormat("mmdb data section truncated"))?;
+            let s = std::str::from_utf8(bytes)
+                .map_err(|_| Error::BadFormat("mmdb string wasn't valid utf-8"))?;
+            Ok((Value::String(s.to_owned()), pos + size - offset))
+        }
+        5 | 6 => {
+            let v = read_uint(data, pos, size)?;
+            Ok((Value::UInt(v), pos + size - offset))
+        }
+        7 => {
+            let mut map = HashMap::with_capacity(size);
+            for _ in 0..size {
+                let (key, key_len) = decode_value(data, pos, depth + 1)?;
+                pos += key_len;
+                let Value::String(key) = key else {
+                    return Err(Error::BadFormat("mmdb map key wasn't a string"));
+                };
+                let (val, val_len) = decode_value(data, pos, depth + 1)?;
+                pos += val_len;
+                map.insert(key, val);
+            }
+            Ok((Value::Map(map), pos - offset))
+        }
+        11 => {
+            for _ in 0..size {
+                let (_, item_len) = decode_value(data, pos, depth + 1)?;
+                pos += item_len;
+            }
+            Ok((Value::Other, pos - offset))
+        }
+        14 => Ok((Value::Other, pos - offset)),
+        _ => Ok((Value::Other, pos + size - offset)),
+    }
+}
+
+/// Decode a pointer's target data-section offset, starting at `pos` (just
+/// past the control byte, which is passed in separately for its low bits).
+fn decode_pointer(data: &[u8], pos: usize, control: u8, size_bits: u8) -> Result<(usize, usize), Error> {
+    let get = |n: usize| -> Result<usize, Error> {
+        data.get(pos..pos + n)
+            .ok_or(Error::BadFormat("mmdb data section truncated"))
+            .map(|b| b.iter().fold(0usize, |acc, &byte| (acc << 8) | byte as usize))
+    };
+    Ok(match size_bits {
+        0 => (((control & 0x07) as usize) << 8 | get(1)?, 1),
+        1 => ((((control & 0x07) as usize) << 16 | get(2)?) + 2048, 2),
+        2 => ((((control & 0x07) as usize) << 24 | get(3)?) + 526336, 3),
+        _ => (get(4)?, 4),
+    })
+}
+
+/// Decode a value's size field, which may spill into up to three
+/// additional bytes for large values.
+fn decode_size(data: &[u8], pos: usize, size_bits: u8) -> Result<(usize, usize), Error> {
+    Ok(match size_bits {
+        0..=28 => (size_bits as usize, 0),
+        29 => (
+            29 + *data
+                .get(pos)
+                .ok_or(Error::BadFormat("mmdb data section truncated"))? as usize,
+            1,
+        ),
+        30 => {
+            let b = data
+                .get(pos..pos + 2)
+                .ok_or(Error::BadFormat("mmdb data section truncated"))?;
+            (285 + usize::from(u16::from_be_bytes([b[0], b[1]])), 2)
+        }
+        _ => {
+            let b = data
+                .get(pos..pos + 3)
+                .ok_or(Error::BadFormat("mmdb data section truncated"))?;
+            (
+                65821 + (usize::from(b[0]) << 16 | usize::from(b[1]) << 8 | usize::from(b[2])),
+                3,
+            )
+        }
+    })
+}
+
+/// Read a big-endian unsigned integer of `size` bytes (up to 8) starting at
+/// `pos`.
+fn read_uint(data: &[u8], pos: usize, size: usize) -> Result<u64, Error> {
+    let bytes = data
+        .get(pos..pos + size)
+        .ok_or(Error::BadFormat("mmdb data section truncated"))?;
+    Ok(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | u64::from(b)))
+}
@@ -0,0 +1,155 @@
+//! Packed, zero-copy binary representation of a geoip range table.
+//!
+//! The crate's `build.rs` packs the legacy-format CSV databases into this
+//! layout at compile time, so [`GeoipDb::new_embedded`](
+//! super::GeoipDb::new_embedded) can `include_bytes!` the result and
+//! `bytemuck`-cast it straight into `&[RawEntryV4]`/`&[RawEntryV6]` slices,
+//! with no parsing or per-row allocation at startup.
+//! [`GeoipDb::new_from_legacy_format`](super::GeoipDb::new_from_legacy_format)
+//! builds the same sorted, non-overlapping representation at runtime, so
+//! both paths share [`RangeTable::lookup`].
+
+use super::NetDefn;
+use std::borrow::Cow;
+
+/// A single packed IPv4 range entry.
+///
+/// The struct is `repr(C, packed)` so a byte slice can be `bytemuck`-cast
+/// directly into `&[RawEntryV4]`, which means its fields are read back in
+/// the host's native endianness rather than a format forced by this type.
+/// `build.rs` always writes them little-endian, so this only works out on a
+/// little-endian host; there's no documented big-endian target for Arti, so
+/// that's accepted rather than paying for per-field `from_le_bytes` decoding
+/// on every lookup.
+#[repr(C, packed)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct RawEntryV4 {
+    /// First address in the range (inclusive).
+    pub(crate) from: u32,
+    /// Last address in the range (inclusive).
+    pub(crate) to: u32,
+    /// The two-letter country code, or `??` if unknown.
+    pub(crate) cc: [u8; 2],
+    /// The ASN, or 0 if unknown (ASN 0 is reserved and never assigned).
+    pub(crate) asn: u32,
+}
+
+/// The IPv6 equivalent of [`RawEntryV4`]; see its doc comment for the
+/// little-endian-host assumption both share.
+#[repr(C, packed)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct RawEntryV6 {
+    /// First address in the range (inclusive).
+    pub(crate) from: u128,
+    /// Last address in the range (inclusive).
+    pub(crate) to: u128,
+    /// The two-letter country code, or `??` if unknown.
+    pub(crate) cc: [u8; 2],
+    /// The ASN, or 0 if unknown.
+    pub(crate) asn: u32,
+}
+
+/// A packed range-table entry, generic over the address width.
+///
+/// Implemented for [`RawEntryV4`] (keyed by `u32`) and [`RawEntryV6`] (keyed
+/// by `u128`), so [`RangeTable::lookup`] only needs to be written once.
+pub(crate) trait RangeEntry: Copy {
+    /// The address type this entry's range is keyed by.
+    type Addr: Ord + Copy;
+    /// The first address in the range (inclusive).
+    fn start(&self) -> Self::Addr;
+    /// The last address in the range (inclusive).
+    fn end(&self) -> Self::Addr;
+    /// The two-letter country code, or `??` if unknown.
+    fn cc(&self) -> [u8; 2];
+    /// The ASN, or 0 if unknown.
+    fn asn(&self) -> u32;
+}
+
+impl RangeEntry for RawEntryV4 {
+    type Addr = u32;
+    fn start(&self) -> u32 {
+        self.from
+    }
+    fn end(&self) -> u32 {
+        self.to
+    }
+    fn cc(&self) -> [u8; 2] {
+        self.cc
+    }
+    fn asn(&self) -> u32 {
+        self.asn
+    }
+}
+
+impl RangeEntry for RawEntryV6 {
+    type Addr = u128;
+    fn start(&self) -> u128 {
+        self.from
+    }
+    fn end(&self) -> u128 {
+        self.to
+    }
+    fn cc(&self) -> [u8; 2] {
+        self.cc
+    }
+    fn asn(&self) -> u32 {
+        self.asn
+    }
+}
+
+/// A sorted, non-overlapping table of range entries, queryable by binary
+/// search.
+///
+/// This is the representation both the embedded binary blob (borrowed,
+/// zero-copy) and [`GeoipDb::new_from_legacy_format`](
+/// super::GeoipDb::new_from_legacy_format) (owned, parsed at runtime)
+/// produce, so there is only one lookup implementation to maintain.
+pub(crate) struct RangeTable<E: 'static> {
+    /// The entries, sorted by [`RangeEntry::start`], with no two entries
+    /// overlapping.
+    entries: Cow<'static, [E]>,
+}
+
+impl<E: RangeEntry> RangeTable<E> {
+    /// Wrap an already-sorted `'static` slice of entries with no copying.
+    pub(crate) fn borrowed(entries: &'static [E]) -> Self {
+        RangeTable {
+            entries: Cow::Borrowed(entries),
+        }
+    }
+
+    /// Take ownership of a `Vec` of entries, which must already be sorted by
+    /// [`RangeEntry::start`].
+    pub(crate) fn owned(entries: Vec<E>) -> Self {
+        RangeTable {
+            entries: Cow::Owned(entries),
+        }
+    }
+
+    /// Iterate over every entry in the table, in `start()` order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = E> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// Find the entry (if any) whose range contains `addr`.
+    pub(crate) fn lookup(&self, addr: E::Addr) -> Option<NetDefn> {
+        let idx = self.entries.partition_point(|e| e.start() <= addr);
+        if idx == 0 {
+            return None;
+        }
+        let entry = self.entries[idx - 1];
+        if addr > entry.end() {
+            return None;
+        }
+        Some(NetDefn::from_raw(entry.cc(), entry.asn()))
+    }
+}
+
+impl<E: RangeEntry> Default for RangeTable<E> {
+    fn default() -> Self {
+        RangeTable {
+            entries: Cow::Owned(Vec::new()),
+        }
+    }
+}
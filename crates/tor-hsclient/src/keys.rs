@@ -1,13 +1,9 @@
 //! Hidden service (onion service) client key management functionality
 
-// TODO HS what layer should be responsible for finding and dispatching keys?
-// I think it should be as high as possible, so keys should be passed into
-// the hs connector for each connection.  Otherwise there would have to be an
-// HsKeyProvider trait here, and error handling gets complicated.
-
+use std::collections::HashMap;
 use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use tor_hscrypto::pk::{HsClientDescEncSecretKey, HsClientIntroAuthKeypair, HsId};
 use tor_keymgr::{ArtiPath, ArtiPathComponent, CTorPath, KeySpecifier};
@@ -140,7 +136,16 @@ impl HsClientSecretKeysBuilder {
 ///
 /// Distinguishes different "clients" or "users" of this Arti instance,
 /// so that they can have different sets of HS client authentication keys.
-#[derive(Clone, Debug, derive_more::Display, derive_more::Into, derive_more::AsRef)]
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    derive_more::Display,
+    derive_more::Into,
+    derive_more::AsRef,
+)]
 pub struct HsClientSpecifier(ArtiPathComponent);
 
 impl HsClientSpecifier {
@@ -194,6 +199,135 @@ impl KeySpecifier for HsClientSecretKeySpecifier {
     }
 
     fn ctor_path(&self) -> Option<CTorPath> {
-        todo!()
+        // C-Tor has no notion of per-client onion-service authentication
+        // keys stored this way; these keys are Arti-only.
+        None
+    }
+}
+
+/// A source of [`HsClientSecretKeys`] for a particular client and hidden
+/// service, looked up on demand.
+///
+/// Unlike building an [`HsClientSecretKeys`] up front with
+/// [`HsClientSecretKeysBuilder`], an `HsClientKeyProvider` is consulted once
+/// per connection attempt, so it can resolve keys lazily (for example,
+/// reading them from disk only when they're actually needed), and can be
+/// told to forget what it has cached when the underlying keys change.
+pub trait HsClientKeyProvider: Send + Sync {
+    /// Return the keys to use for a connection from `client_id` to `hs_id`.
+    ///
+    /// If no keys are on record for this client and service, this returns
+    /// [`HsClientSecretKeys::none`]: that's not an error, it just means the
+    /// connection will be made without any Tor-protocol-level client
+    /// authentication.
+    ///
+    /// Two calls for the same `client_id`/`hs_id` pair return
+    /// `HsClientSecretKeys` values that compare equal (see
+    /// [`HsClientSecretKeys`]'s sharing semantics), so that connections for
+    /// the same client and service continue to share circuits.
+    fn get_keys(
+        &self,
+        client_id: &HsClientSpecifier,
+        hs_id: HsId,
+    ) -> tor_keymgr::Result<HsClientSecretKeys>;
+
+    /// Discard any cached keys for `client_id`/`hs_id`.
+    ///
+    /// The next call to [`get_keys`](Self::get_keys) for that pair will
+    /// re-read the keys from the underlying store, picking up any key that
+    /// was added, rotated, or removed since the last lookup.
+    fn invalidate(&self, client_id: &HsClientSpecifier, hs_id: HsId);
+}
+
+/// An [`HsClientKeyProvider`] that resolves keys from an on-disk keystore,
+/// using the same [`KeySpecifier`]/[`ArtiPath`] scheme
+/// (`client/{client_id}/{hs_id}/{role}`) as [`HsClientSecretKeySpecifier`].
+///
+/// Keys are loaded the first time they're needed for a given
+/// `(HsClientSpecifier, HsId)` pair, then kept in an in-memory cache; call
+/// [`invalidate`](HsClientKeyProvider::invalidate) after modifying the
+/// on-disk keystore (adding, rotating, or removing an authorization key) to
+/// force the next lookup to re-read it.
+pub struct FsHsClientKeyProvider {
+    /// The keystore we resolve [`HsClientSecretKeySpecifier`]s against.
+    keymgr: Arc<tor_keymgr::KeyMgr>,
+    /// Keys we've already resolved, keyed by client and service.
+    cache: Mutex<HashMap<(HsClientSpecifier, HsId), HsClientSecretKeys>>,
+}
+
+impl FsHsClientKeyProvider {
+    /// Create a new provider that resolves keys via `keymgr`.
+    pub fn new(keymgr: Arc<tor_keymgr::KeyMgr>) -> Self {
+        Self {
+            keymgr,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load the keys for `client_id`/`hs_id` from `self.keymgr`, without
+    /// consulting or populating the cache.
+    fn load_keys(
+        &self,
+        client_id: &HsClientSpecifier,
+        hs_id: HsId,
+    ) -> tor_keymgr::Result<HsClientSecretKeys> {
+        let mut builder = HsClientSecretKeysBuilder::default();
+
+        let desc_enc_spec = HsClientSecretKeySpecifier::new(
+            client_id.clone(),
+            hs_id,
+            HsClientKeyRole::DescEnc,
+        );
+        if let Some(ks) = self.keymgr.get::<HsClientDescEncSecretKey>(&desc_enc_spec)? {
+            builder.ks_hsc_desc_enc(ks);
+        }
+
+        let intro_auth_spec = HsClientSecretKeySpecifier::new(
+            client_id.clone(),
+            hs_id,
+            HsClientKeyRole::IntroAuth,
+        );
+        if let Some(ks) = self
+            .keymgr
+            .get::<HsClientIntroAuthKeypair>(&intro_auth_spec)?
+        {
+            builder.ks_hsc_intro_auth(ks);
+        }
+
+        Ok(builder
+            .build()
+            .expect("HsClientSecretKeysBuilder::build is infallible"))
+    }
+}
+
+impl HsClientKeyProvider for FsHsClientKeyProvider {
+    fn get_keys(
+        &self,
+        client_id: &HsClientSpecifier,
+        hs_id: HsId,
+    ) -> tor_keymgr::Result<HsClientSecretKeys> {
+        let cache_key = (client_id.clone(), hs_id);
+        if let Some(keys) = self
+            .cache
+            .lock()
+            .expect("HsClientKeyProvider cache poisoned")
+            .get(&cache_key)
+        {
+            return Ok(keys.clone());
+        }
+
+        let keys = self.load_keys(client_id, hs_id)?;
+        self.cache
+            .lock()
+            .expect("HsClientKeyProvider cache poisoned")
+            .insert(cache_key, keys.clone());
+        Ok(keys)
+    }
+
+    fn invalidate(&self, client_id: &HsClientSpecifier, hs_id: HsId) {
+        self.cache
+            .lock()
+            .expect("HsClientKeyProvider cache poisoned")
+            .remove(&(client_id.clone(), hs_id));
     }
 }
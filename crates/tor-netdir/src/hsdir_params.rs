@@ -22,6 +22,9 @@
 use std::time::{Duration, SystemTime};
 
 use crate::{params::NetParameters, Error, Result};
+use aes::Aes128;
+use cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use ctr::Ctr64BE;
 use time::{OffsetDateTime, UtcOffset};
 use tor_hscrypto::time::TimePeriod;
 use tor_netdoc::doc::netstatus::{Lifetime, MdConsensus, SharedRandVal};
@@ -77,12 +80,7 @@ pub(crate) fn compute_ring_parameters(
     params: &NetParameters,
 ) -> Result<(HsRingParams, Vec<HsRingParams>)> {
     let srvs = extract_srvs(consensus)?;
-    let tp_length: Duration = params.hsdir_timeperiod_length.try_into().map_err(|_| {
-        Error::InvalidConsensus("Minutes in hsdir timeperiod could not be converted to a Duration")
-    })?;
-    let offset = voting_period(consensus.lifetime())? * VOTING_PERIODS_IN_OFFSET;
-    let cur_period = TimePeriod::new(tp_length, consensus.lifetime().valid_after(), offset)
-        .expect("Consensus valid-after did not fall in a time period");
+    let cur_period = cur_time_period(consensus, params)?;
     let cur_period_start = cur_period
         .range()
         .ok_or(Error::InvalidConsensus(
@@ -99,21 +97,252 @@ pub(crate) fn compute_ring_parameters(
 
     // When computing secondary rings, we don't try so many fallback operations:
     // if they aren't available, they aren't available.
+    //
+    // We walk outward from `cur_period` in both directions, including every
+    // period whose range overlaps the union of currently-valid SRV
+    // intervals, instead of hard-coding exactly one period on each side:
+    // under a non-default `hsdir_interval`, time periods can be shorter
+    // than SRV intervals, so more than one neighboring period may still
+    // need to be reachable during a transition.
     let mut other_rings = Vec::new();
-    for period in [cur_period.prev(), cur_period.next()].iter().flatten() {
-        if let Some(period_range) = period.range() {
+    if let (Some(envelope_start), Some(envelope_end)) = (
+        srvs.iter().map(|(_, r)| r.start).min(),
+        srvs.iter().map(|(_, r)| r.end).max(),
+    ) {
+        // Walk backward from `cur_period`, stopping as soon as a candidate
+        // period's range no longer overlaps the valid-SRV envelope.
+        let mut candidate = cur_period.prev();
+        while let Some(period) = candidate {
+            let Some(period_range) = period.range() else {
+                break;
+            };
+            if period_range.start >= envelope_end || period_range.end <= envelope_start {
+                break;
+            }
             if let Some(srv) = find_srv_for_time(&srvs[..], period_range.start) {
                 other_rings.push(HsRingParams {
-                    time_period: *period,
+                    time_period: period,
                     shared_rand: srv,
                 });
             }
+            candidate = period.prev();
+        }
+
+        // And forward, likewise.
+        let mut candidate = cur_period.next();
+        while let Some(period) = candidate {
+            let Some(period_range) = period.range() else {
+                break;
+            };
+            if period_range.start >= envelope_end || period_range.end <= envelope_start {
+                break;
+            }
+            if let Some(srv) = find_srv_for_time(&srvs[..], period_range.start) {
+                other_rings.push(HsRingParams {
+                    time_period: period,
+                    shared_rand: srv,
+                });
+            }
+            candidate = period.next();
         }
     }
+    other_rings.sort_by_key(|r| r.time_period.range().map(|r| r.start));
 
     Ok((main_ring, other_rings))
 }
 
+/// Return the next time at which the `HsRingParams` computed by
+/// [`compute_ring_parameters`] for `consensus` would change.
+///
+/// "Current" in this module is always relative to a consensus, not the
+/// wall clock, so there's no fixed voting schedule a caller can consult to
+/// know when to recompute. This instead derives that answer directly from
+/// the installed consensus, as the minimum of:
+///
+///   * the end of the current time period (`range().end`);
+///   * the end of the current SRV's validity interval, from
+///     [`extract_srvs`]; and
+///   * the end of the next (secondary) time period, i.e. the point at which
+///     the window of periods `compute_ring_parameters` treats as secondary
+///     shifts forward.
+///
+/// A service or client can use this to schedule its next ring-parameter
+/// recomputation (and any consequent descriptor re-upload) precisely,
+/// instead of polling.
+pub(crate) fn next_recompute_time(
+    consensus: &MdConsensus,
+    params: &NetParameters,
+) -> Result<SystemTime> {
+    let srvs = extract_srvs(consensus)?;
+    let cur_period = cur_time_period(consensus, params)?;
+    let cur_range = cur_period.range().ok_or(Error::InvalidConsensus(
+        "HsDir time period in consensus could not be represented as a SystemTime range.",
+    ))?;
+
+    let mut next = cur_range.end;
+
+    if let Some((_, srv_range)) = srvs.iter().find(|(_, r)| r.contains(&cur_range.start)) {
+        next = next.min(srv_range.end);
+    }
+
+    if let Some(next_period_range) = cur_period.next().and_then(|p| p.range()) {
+        next = next.min(next_period_range.end);
+    }
+
+    Ok(next)
+}
+
+/// Key identifying the consensus a [`RingParamsCache`] entry was computed
+/// from, so that installing a new consensus invalidates the cache
+/// automatically.
+///
+/// Two distinct consensuses can't share a valid-after/fresh-until pair, so
+/// comparing those is enough to tell whether a cached entry still matches.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct RingParamsCacheKey {
+    /// The consensus's valid-after time.
+    valid_after: SystemTime,
+    /// The consensus's fresh-until time.
+    fresh_until: SystemTime,
+}
+
+impl RingParamsCacheKey {
+    /// Compute the cache key for `consensus`.
+    fn for_consensus(consensus: &MdConsensus) -> Self {
+        let lifetime = consensus.lifetime();
+        RingParamsCacheKey {
+            valid_after: lifetime.valid_after(),
+            fresh_until: lifetime.fresh_until(),
+        }
+    }
+}
+
+/// A memoized [`compute_ring_parameters`] result for a single consensus.
+///
+/// `NetDir` holds one of these alongside the consensus it was computed
+/// from, so that repeatedly resolving HsDir ring positions against the same
+/// consensus doesn't re-derive the `(HsRingParams, Vec<HsRingParams>)`
+/// bundle — and the `SrvInfo` ranges `extract_srvs` builds to get there —
+/// from scratch every time. The cache is keyed on the consensus's own
+/// lifetime (see [`RingParamsCacheKey`]), so installing a new consensus
+/// invalidates it automatically; it does not need to be cleared by hand.
+pub(crate) struct RingParamsCache {
+    /// The consensus this cache was computed from.
+    key: RingParamsCacheKey,
+    /// The SRVs extracted from that consensus, kept around so that
+    /// [`Self::srv_for_time`] lookups for arbitrary target times stay cheap.
+    srvs: Vec<SrvInfo>,
+    /// The ring parameters for the current time period.
+    main_ring: HsRingParams,
+    /// The ring parameters for every secondary time period.
+    secondary_rings: Vec<HsRingParams>,
+}
+
+impl RingParamsCache {
+    /// Return the cached ring parameters for `consensus`, recomputing them
+    /// first if `*cache` is empty or was computed from a different
+    /// consensus.
+    pub(crate) fn get_or_compute<'a>(
+        cache: &'a mut Option<RingParamsCache>,
+        consensus: &MdConsensus,
+        params: &NetParameters,
+    ) -> Result<&'a RingParamsCache> {
+        let key = RingParamsCacheKey::for_consensus(consensus);
+        let stale = !matches!(cache, Some(c) if c.key == key);
+        if stale {
+            let srvs = extract_srvs(consensus)?;
+            let (main_ring, secondary_rings) = compute_ring_parameters(consensus, params)?;
+            *cache = Some(RingParamsCache {
+                key,
+                srvs,
+                main_ring,
+                secondary_rings,
+            });
+        }
+        Ok(cache.as_ref().expect("just populated"))
+    }
+
+    /// Return the ring parameters for the current time period.
+    pub(crate) fn main_ring(&self) -> &HsRingParams {
+        &self.main_ring
+    }
+
+    /// Return the ring parameters for every secondary time period.
+    pub(crate) fn secondary_rings(&self) -> &[HsRingParams] {
+        &self.secondary_rings
+    }
+
+    /// Return the SRV that was most recent at `when`, according to the
+    /// cached consensus.
+    pub(crate) fn srv_for_time(&self, when: SystemTime) -> Option<SharedRandVal> {
+        find_srv_for_time(&self.srvs, when)
+    }
+}
+
+/// Compute the time period that contains a consensus's valid-after time.
+fn cur_time_period(consensus: &MdConsensus, params: &NetParameters) -> Result<TimePeriod> {
+    let tp_length: Duration = params.hsdir_timeperiod_length.try_into().map_err(|_| {
+        Error::InvalidConsensus("Minutes in hsdir timeperiod could not be converted to a Duration")
+    })?;
+    let offset = voting_period(consensus.lifetime())? * VOTING_PERIODS_IN_OFFSET;
+    Ok(
+        TimePeriod::new(tp_length, consensus.lifetime().valid_after(), offset)
+            .expect("Consensus valid-after did not fall in a time period"),
+    )
+}
+
+/// Where a consensus's valid-after time falls, relative to the daily
+/// time-period-rotation and SRV-rotation schedule.
+///
+/// See [`time_period_state`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum TpSrvPhase {
+    /// The consensus's valid-after falls in the normal window: the most
+    /// recent time-period rotation (if any, today) happened no later than
+    /// the most recent SRV rotation (always at the start of the UTC day).
+    Normal,
+    /// The consensus's valid-after falls in the window between a
+    /// time-period rotation and the following SRV rotation.
+    ///
+    /// Per rend-spec-v3 §2.2.3, a service publishing in this window should
+    /// also publish under the *next* time period's ring, since clients
+    /// computed with a consensus just after this boundary will already be
+    /// looking at the next period.
+    InBetween,
+}
+
+/// Report whether `consensus`'s valid-after falls in the normal time-period
+/// window, or in the narrower in-between window that opens when the
+/// time-period rotates but the SRV has not rotated yet.
+///
+/// The SRV becomes current at the start of the UTC day (offset 0), while the
+/// time period boundary is offset from the epoch by
+/// [`VOTING_PERIODS_IN_OFFSET`] voting periods (12 hours, by default). A
+/// consensus is "in between" when its valid-after falls after that
+/// time-period rotation but before the following day's SRV rotation.
+///
+/// This is computed entirely from `consensus`'s own valid-after time, not
+/// from any wall-clock voting schedule, so that (for example) a consensus
+/// timestamped 23:00 but fetched at 00:08 the next day is still evaluated
+/// as of 23:00, and does not flip phase prematurely.
+pub(crate) fn time_period_state(consensus: &MdConsensus, params: &NetParameters) -> Result<TpSrvPhase> {
+    let valid_after = consensus.lifetime().valid_after();
+    let cur_period = cur_time_period(consensus, params)?;
+    let cur_period_start = cur_period
+        .range()
+        .ok_or(Error::InvalidConsensus(
+            "HsDir time period in consensus could not be represented as a SystemTime range.",
+        ))?
+        .start;
+    let day_start = start_of_day_containing(valid_after);
+
+    Ok(if cur_period_start > day_start {
+        TpSrvPhase::InBetween
+    } else {
+        TpSrvPhase::Normal
+    })
+}
+
 /// Compute the "Disaster SRV" for a given time period.
 ///
 /// This SRV is used if the authorities do not list any shared random value for
@@ -131,7 +360,7 @@ fn disaster_srv(period: TimePeriod) -> SharedRandVal {
 
 /// Helper type: A `SharedRandVal`, and the time range over which it is the most
 /// recent.
-type SrvInfo = (SharedRandVal, std::ops::Range<SystemTime>);
+pub(crate) type SrvInfo = (SharedRandVal, std::ops::Range<SystemTime>);
 
 /// Given a list of SrvInfo, return the SharedRandVal (if any) that is the most
 /// recent SRV at `when`.
@@ -141,9 +370,24 @@ fn find_srv_for_time(info: &[SrvInfo], when: SystemTime) -> Option<SharedRandVal
         .map(|(srv, _)| *srv)
 }
 
+/// Return the start time of the SRV interval that applies to `period`, given
+/// the `SrvInfo` list produced by [`extract_srvs`].
+///
+/// This is the same timestamp that [`compute_ring_parameters`] uses
+/// internally (via [`find_srv_for_time`]) to decide which SRV backs a given
+/// time period's ring. It's exposed so that descriptor-publication code can
+/// compute "seconds since SRV start" for an [`OpeRevisionCounter`] plaintext
+/// without duplicating that lookup.
+pub(crate) fn srv_start_time(srvs: &[SrvInfo], period: TimePeriod) -> Option<SystemTime> {
+    let period_start = period.range()?.start;
+    srvs.iter()
+        .find(|(_, range)| range.contains(&period_start))
+        .map(|(_, range)| range.start)
+}
+
 /// Return every SRV from a consensus, along with a duration over which it is
 /// most recent SRV.
-fn extract_srvs(consensus: &MdConsensus) -> Result<Vec<SrvInfo>> {
+pub(crate) fn extract_srvs(consensus: &MdConsensus) -> Result<Vec<SrvInfo>> {
     let mut v = Vec::new();
     let consensus_ts = consensus.lifetime().valid_after();
     let srv_interval = srv_interval(consensus)?;
@@ -207,6 +451,98 @@ fn start_of_day_containing(t: SystemTime) -> SystemTime {
         .into()
 }
 
+/// Number of AES-CTR keystream words between precomputed checkpoints in an
+/// [`OpeRevisionCounter`].
+///
+/// A query first seeks to the nearest checkpoint at or before its target
+/// offset, then sums only the remaining words from there. This bounds the
+/// per-query keystream generation to at most this many words, instead of
+/// replaying the whole stream from zero on every call.
+const OPE_CHECKPOINT_INTERVAL: u32 = 8192;
+
+/// An order-preserving encryption of a small plaintext integer, used to
+/// build onion-service descriptor revision counters that increase
+/// monotonically without revealing the exact upload time.
+///
+/// Per rend-spec-v3 §2.2.2, a descriptor's revision counter should be
+/// derived from the number of seconds since the start of its SRV interval
+/// (see [`srv_start_time`]), encrypted under order-preserving encryption so
+/// that HsDirs can compare counters to reject stale uploads, without the
+/// plaintext offset leaking how recently the descriptor was generated.
+///
+/// The encryption is a keyed prefix sum over an AES-CTR keystream: seed
+/// AES-128-CTR with the per-descriptor key and a zero counter, interpret the
+/// keystream as a sequence of little-endian `u16` words `w_0, w_1, …`, and
+/// define `encrypt(n) = Σ_{i=0}^{n-1} (w_i + 1)`. Since every term is at
+/// least 1, this is strictly increasing in `n`, and the result fits in a
+/// `u64` for any plaintext up to a full time-period length (about 86 400
+/// seconds, so a sum of at most about 5.7×10⁹).
+///
+/// The `key` must be unique per (blinded onion service key, time period): as
+/// with any OPE scheme, reusing a key across descriptors would let an
+/// observer compare their counters directly.
+pub(crate) struct OpeRevisionCounter {
+    /// The per-descriptor AES-128 key.
+    key: [u8; 16],
+    /// `checkpoints[i]` is `encrypt(i * OPE_CHECKPOINT_INTERVAL)`.
+    checkpoints: Vec<u64>,
+    /// The largest plaintext this counter will encrypt.
+    max_plaintext: u32,
+}
+
+impl OpeRevisionCounter {
+    /// Construct a new `OpeRevisionCounter` using `key`, able to encrypt any
+    /// plaintext from 0 up to and including `max_plaintext`.
+    pub(crate) fn new(key: [u8; 16], max_plaintext: u32) -> Self {
+        let n_checkpoints = (max_plaintext / OPE_CHECKPOINT_INTERVAL) as usize + 1;
+        let mut checkpoints = Vec::with_capacity(n_checkpoints);
+        let mut cipher = Self::keystream(&key);
+        let mut sum: u64 = 0;
+        let mut word = [0_u8; 2];
+        checkpoints.push(0);
+        for _ in 1..n_checkpoints {
+            for _ in 0..OPE_CHECKPOINT_INTERVAL {
+                cipher.apply_keystream(&mut word);
+                sum += u16::from_le_bytes(word) as u64 + 1;
+            }
+            checkpoints.push(sum);
+        }
+        OpeRevisionCounter {
+            key,
+            checkpoints,
+            max_plaintext,
+        }
+    }
+
+    /// Return a fresh AES-CTR keystream for `key`, counter starting at zero.
+    fn keystream(key: &[u8; 16]) -> Ctr64BE<Aes128> {
+        Ctr64BE::<Aes128>::new(key.into(), &[0_u8; 16].into())
+    }
+
+    /// Encrypt `n`, the number of seconds since the descriptor's SRV
+    /// interval began.
+    ///
+    /// Returns `None` if `n` is beyond the maximum plaintext this counter
+    /// was constructed to handle.
+    pub(crate) fn encrypt(&self, n: u32) -> Option<u64> {
+        if n > self.max_plaintext {
+            return None;
+        }
+        let checkpoint_idx = (n / OPE_CHECKPOINT_INTERVAL) as usize;
+        let checkpoint_n = checkpoint_idx as u32 * OPE_CHECKPOINT_INTERVAL;
+        let mut sum = self.checkpoints[checkpoint_idx];
+
+        let mut cipher = Self::keystream(&self.key);
+        cipher.seek(u64::from(checkpoint_n) * 2);
+        let mut word = [0_u8; 2];
+        for _ in checkpoint_n..n {
+            cipher.apply_keystream(&mut word);
+            sum += u16::from_le_bytes(word) as u64 + 1;
+        }
+        Some(sum)
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -387,6 +723,181 @@ mod test {
         assert_eq!(None, find_srv_for_time(&srvs, t("1985-10-25T12:00:30Z")));
     }
 
+    #[test]
+    fn tp_srv_phase() {
+        // With the legacy schedule (1-day time periods offset 12h from the
+        // epoch), the time period rotates at noon UTC and the SRV rotates
+        // at midnight UTC. So we're "in between" from noon to midnight.
+        let before_noon = example_consensus_builder()
+            .lifetime(
+                Lifetime::new(
+                    t("1985-10-25T07:00:00Z"),
+                    t("1985-10-25T08:00:00Z"),
+                    t("1985-10-25T10:00:00Z"),
+                )
+                .unwrap(),
+            )
+            .testing_consensus()
+            .unwrap();
+        let netparams = NetParameters::from_map(before_noon.params());
+        assert_eq!(
+            time_period_state(&before_noon, &netparams).unwrap(),
+            TpSrvPhase::Normal
+        );
+
+        let after_noon = example_consensus_builder()
+            .lifetime(
+                Lifetime::new(
+                    t("1985-10-25T13:00:00Z"),
+                    t("1985-10-25T14:00:00Z"),
+                    t("1985-10-25T16:00:00Z"),
+                )
+                .unwrap(),
+            )
+            .testing_consensus()
+            .unwrap();
+        let netparams = NetParameters::from_map(after_noon.params());
+        assert_eq!(
+            time_period_state(&after_noon, &netparams).unwrap(),
+            TpSrvPhase::InBetween
+        );
+
+        // A consensus timestamped 23:00 is still "in between" even if we
+        // imagine evaluating it late (we only ever look at its own
+        // valid-after, never the wall clock).
+        let late_pre_midnight = example_consensus_builder()
+            .lifetime(
+                Lifetime::new(
+                    t("1985-10-25T23:00:00Z"),
+                    t("1985-10-26T00:00:00Z"),
+                    t("1985-10-26T02:00:00Z"),
+                )
+                .unwrap(),
+            )
+            .testing_consensus()
+            .unwrap();
+        let netparams = NetParameters::from_map(late_pre_midnight.params());
+        assert_eq!(
+            time_period_state(&late_pre_midnight, &netparams).unwrap(),
+            TpSrvPhase::InBetween
+        );
+    }
+
+    #[test]
+    fn srv_start() {
+        let consensus = example_consensus_builder()
+            .shared_rand_prev(7, SRV1.into(), Some(t("1985-10-25T00:00:00Z")))
+            .shared_rand_cur(7, SRV2.into(), Some(t("1985-10-25T06:00:05Z")))
+            .testing_consensus()
+            .unwrap();
+        let srvs = extract_srvs(&consensus).unwrap();
+
+        let period_using_prev =
+            TimePeriod::new(d("2 hours"), t("1985-10-25T03:00:00Z"), d("12 hours")).unwrap();
+        assert_eq!(
+            srv_start_time(&srvs, period_using_prev),
+            Some(t("1985-10-25T00:00:00Z"))
+        );
+
+        let period_using_cur =
+            TimePeriod::new(d("2 hours"), t("1985-10-25T08:00:00Z"), d("12 hours")).unwrap();
+        assert_eq!(
+            srv_start_time(&srvs, period_using_cur),
+            Some(t("1985-10-25T06:00:05Z"))
+        );
+    }
+
+    #[test]
+    fn ope_revision_counter() {
+        let counter = OpeRevisionCounter::new([7_u8; 16], 86_400);
+
+        // Strictly increasing.
+        let mut prev = counter.encrypt(0).unwrap();
+        for n in [1, 2, 100, 8191, 8192, 8193, 40_000, 86_399, 86_400] {
+            let cur = counter.encrypt(n).unwrap();
+            assert!(cur > prev, "encrypt({n}) = {cur} did not exceed {prev}");
+            prev = cur;
+        }
+
+        // Deterministic for a given key.
+        let counter2 = OpeRevisionCounter::new([7_u8; 16], 86_400);
+        assert_eq!(counter.encrypt(12_345), counter2.encrypt(12_345));
+
+        // Different keys give different ciphertexts.
+        let counter3 = OpeRevisionCounter::new([8_u8; 16], 86_400);
+        assert_ne!(counter.encrypt(12_345), counter3.encrypt(12_345));
+
+        // Beyond the configured maximum is rejected.
+        assert_eq!(counter.encrypt(86_401), None);
+    }
+
+    #[test]
+    fn ring_params_cache() {
+        let consensus = example_consensus_builder().testing_consensus().unwrap();
+        let netparams = NetParameters::from_map(consensus.params());
+
+        let mut cache = None;
+        let cached = RingParamsCache::get_or_compute(&mut cache, &consensus, &netparams).unwrap();
+        let (main, secondary) = compute_ring_parameters(&consensus, &netparams).unwrap();
+        assert_eq!(cached.main_ring().time_period, main.time_period);
+        assert_eq!(cached.secondary_rings().len(), secondary.len());
+        assert_eq!(
+            cached.srv_for_time(t("1985-10-25T07:00:00Z")),
+            Some(SRV2.into())
+        );
+
+        // A second call against the same consensus leaves the cache keyed
+        // on that same consensus.
+        let key_before = cache.as_ref().unwrap().key.clone();
+        RingParamsCache::get_or_compute(&mut cache, &consensus, &netparams).unwrap();
+        assert_eq!(cache.as_ref().unwrap().key, key_before);
+
+        // A different consensus invalidates the cache.
+        let consensus2 = example_consensus_builder()
+            .lifetime(
+                Lifetime::new(
+                    t("1985-10-26T07:00:00Z"),
+                    t("1985-10-26T08:00:00Z"),
+                    t("1985-10-26T10:00:00Z"),
+                )
+                .unwrap(),
+            )
+            .testing_consensus()
+            .unwrap();
+        let netparams2 = NetParameters::from_map(consensus2.params());
+        RingParamsCache::get_or_compute(&mut cache, &consensus2, &netparams2).unwrap();
+        assert_ne!(cache.as_ref().unwrap().key, key_before);
+    }
+
+    #[test]
+    fn next_recompute() {
+        // Simple legacy schedule: 1-day periods offset 12h, SRVs good for a
+        // full day starting at midnight.
+        let consensus = example_consensus_builder().testing_consensus().unwrap();
+        let netparams = NetParameters::from_map(consensus.params());
+        // Consensus valid-after is 1985-10-25T07:00:00Z, so the current
+        // period started at 1985-10-24T12:00:00Z and ends at
+        // 1985-10-25T12:00:00Z; the SRV in effect (SRV1) is good from
+        // 1985-10-24T00:00:00Z to 1985-10-25T00:00:00Z, which ends sooner.
+        assert_eq!(
+            next_recompute_time(&consensus, &netparams).unwrap(),
+            t("1985-10-25T00:00:00Z")
+        );
+
+        // With explicit SRV timestamps that outlive the time period, the
+        // period boundary becomes the limiting factor instead.
+        let consensus2 = example_consensus_builder()
+            .shared_rand_prev(7, SRV1.into(), Some(t("1985-10-23T00:00:00Z")))
+            .shared_rand_cur(7, SRV2.into(), Some(t("1985-10-26T00:00:00Z")))
+            .testing_consensus()
+            .unwrap();
+        let netparams2 = NetParameters::from_map(consensus2.params());
+        assert_eq!(
+            next_recompute_time(&consensus2, &netparams2).unwrap(),
+            t("1985-10-25T12:00:00Z")
+        );
+    }
+
     #[test]
     fn disaster() {
         use digest::Digest;
@@ -450,16 +961,30 @@ mod test {
         );
         assert_eq!(cur.shared_rand.as_ref(), &SRV2);
 
-        assert_eq!(secondary.len(), 2);
+        // With a 2-hour time period but a 5-hour SRV interval, the window of
+        // periods overlapping the currently-valid SRVs (00:00..10:00) is
+        // wider than just the immediate neighbors of `cur` (06:00..08:00):
+        // it reaches all the way back to the start of SRV1's validity.
+        assert_eq!(secondary.len(), 4);
         assert_eq!(
             secondary[0].time_period,
-            TimePeriod::new(d("2 hours"), t("1985-10-25T05:00:00Z"), d("12 hours")).unwrap()
+            TimePeriod::new(d("2 hours"), t("1985-10-25T00:00:00Z"), d("12 hours")).unwrap()
         );
         assert_eq!(secondary[0].shared_rand.as_ref(), &SRV1);
         assert_eq!(
             secondary[1].time_period,
+            TimePeriod::new(d("2 hours"), t("1985-10-25T02:00:00Z"), d("12 hours")).unwrap()
+        );
+        assert_eq!(secondary[1].shared_rand.as_ref(), &SRV1);
+        assert_eq!(
+            secondary[2].time_period,
+            TimePeriod::new(d("2 hours"), t("1985-10-25T05:00:00Z"), d("12 hours")).unwrap()
+        );
+        assert_eq!(secondary[2].shared_rand.as_ref(), &SRV1);
+        assert_eq!(
+            secondary[3].time_period,
             TimePeriod::new(d("2 hours"), t("1985-10-25T09:00:00Z"), d("12 hours")).unwrap()
         );
-        assert_eq!(secondary[1].shared_rand.as_ref(), &SRV2);
+        assert_eq!(secondary[3].shared_rand.as_ref(), &SRV2);
     }
 }
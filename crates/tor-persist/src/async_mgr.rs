@@ -0,0 +1,98 @@
+//! An async mirror of [`StateMgr`], for use from async Arti code.
+
+use crate::format::Format;
+use crate::{fs::FsStateMgr, LockStatus, Result, StateMgr};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// An async mirror of [`StateMgr`].
+///
+/// `StateMgr`'s own methods are synchronous: they do blocking filesystem
+/// I/O and take an OS-level advisory lock, so calling them directly from
+/// an async task would stall the whole reactor thread. Every method here
+/// instead moves that blocking work onto a dedicated thread and only
+/// awaits the result, so it's safe to call from within an async reactor.
+///
+/// # Locking discipline
+///
+/// This crate denies `clippy::await_holding_lock`. Every implementation
+/// here upholds that guarantee by acquiring, using, and dropping its lock
+/// guard *entirely inside* the blocking closure it hands off: the guard
+/// never crosses an `.await`.
+#[async_trait]
+pub trait AsyncStateMgr: StateMgr {
+    /// As [`StateMgr::load`], but asynchronous.
+    async fn load_async<D>(&self, key: &str) -> Result<Option<D>>
+    where
+        D: DeserializeOwned + Send + 'static;
+
+    /// As [`StateMgr::store`], but asynchronous.
+    async fn store_async<S>(&self, key: &str, val: &S) -> Result<()>
+    where
+        S: Serialize + Sync;
+
+    /// As [`StateMgr::try_lock`], but asynchronous.
+    async fn try_lock_async(&self) -> Result<LockStatus>;
+
+    /// As [`StateMgr::unlock`], but asynchronous.
+    async fn unlock_async(&self) -> Result<()>;
+}
+
+#[async_trait]
+impl<F: Format> AsyncStateMgr for FsStateMgr<F> {
+    async fn load_async<D>(&self, key: &str) -> Result<Option<D>>
+    where
+        D: DeserializeOwned + Send + 'static,
+    {
+        let mgr = self.clone();
+        let key = key.to_owned();
+        run_blocking(move || mgr.load(&key)).await
+    }
+
+    async fn store_async<S>(&self, key: &str, val: &S) -> Result<()>
+    where
+        S: Serialize + Sync,
+    {
+        // Serialization is CPU-bound, not I/O-bound, so it happens here on
+        // the calling task; only the resulting bytes, which is all the
+        // blocking job needs, cross over to the dedicated thread.
+        let mut bytes = Vec::new();
+        F::serialize_into(val, &mut bytes)?;
+
+        let mgr = self.clone();
+        let key = key.to_owned();
+        run_blocking(move || mgr.store_bytes(&key, &bytes)).await
+    }
+
+    async fn try_lock_async(&self) -> Result<LockStatus> {
+        let mgr = self.clone();
+        run_blocking(move || mgr.try_lock()).await
+    }
+
+    async fn unlock_async(&self) -> Result<()> {
+        let mgr = self.clone();
+        run_blocking(move || mgr.unlock()).await
+    }
+}
+
+/// Run `f` on a dedicated OS thread, and asynchronously await its result.
+///
+/// This is the "blocking thread pool" that [`AsyncStateMgr`]'s methods
+/// dispatch to. This crate doesn't otherwise depend on any particular
+/// async runtime, so rather than assume a `tokio`- or `async-std`-flavored
+/// `spawn_blocking` is available, each call here just spawns its own
+/// thread; the lock guard and file handle used inside `f` are created,
+/// used, and dropped on that thread, never touching the calling task.
+async fn run_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        // This send can only fail if the receiver was dropped, meaning
+        // nobody is still waiting on the result.
+        let _ = tx.send(f());
+    });
+    rx.await.expect("blocking thread panicked before finishing")
+}
@@ -0,0 +1,153 @@
+//! Filesystem-based implementation of [`StateMgr`].
+
+use crate::format::{Format, JsonFormat};
+use crate::{Error, LockStatus, Result, StateMgr};
+use fs_mistrust::Mistrust;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Implementation of [`StateMgr`] that stores state as flat files on disk,
+/// one file per key, encoded with a pluggable [`Format`] (JSON by default).
+///
+/// # Locking
+///
+/// Before calling [`StateMgr::store`], a caller must successfully call
+/// [`StateMgr::try_lock`]; this acquires an advisory lock on a `lock` file
+/// in the state directory, so that at most one `FsStateMgr` can write to a
+/// given directory at a time, even across processes. Other `FsStateMgr`s
+/// pointed at the same directory can always `load`.
+#[derive(Clone, Debug)]
+pub struct FsStateMgr<F: Format = JsonFormat> {
+    /// The shared, lock-protected state behind this handle.
+    inner: Arc<FsStateMgrInner>,
+    /// Marker for the format this store reads and writes.
+    _format: PhantomData<F>,
+}
+
+/// The shared state behind an [`FsStateMgr`], independent of its format.
+#[derive(Debug)]
+struct FsStateMgrInner {
+    /// The directory we store state files in.
+    statepath: PathBuf,
+    /// The advisory lock we hold, if any.
+    lockfile: Mutex<Option<fslock::LockFile>>,
+}
+
+impl FsStateMgr<JsonFormat> {
+    /// Construct a new `FsStateMgr` storing JSON-encoded state at `path`,
+    /// after checking that `path`'s permissions meet `mistrust`'s
+    /// requirements.
+    pub fn from_path_and_mistrust<P: AsRef<Path>>(path: P, mistrust: &Mistrust) -> Result<Self> {
+        Self::from_path_and_mistrust_with_format(path, mistrust)
+    }
+}
+
+impl<F: Format> FsStateMgr<F> {
+    /// As [`FsStateMgr::from_path_and_mistrust`], but store state encoded
+    /// with the format `F` instead of JSON.
+    ///
+    /// Files written under this format get the extension [`Format::EXTENSION`]
+    /// instead of `.json`, so stores using different formats never collide
+    /// on disk even when pointed at the same directory.
+    pub fn from_path_and_mistrust_with_format<P: AsRef<Path>>(
+        path: P,
+        mistrust: &Mistrust,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        mistrust.check_directory(path)?;
+        fs::create_dir_all(path)?;
+        Ok(FsStateMgr {
+            inner: Arc::new(FsStateMgrInner {
+                statepath: path.to_path_buf(),
+                lockfile: Mutex::new(None),
+            }),
+            _format: PhantomData,
+        })
+    }
+
+    /// Return the path we'd use to store `key`.
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.inner
+            .statepath
+            .join(format!("{}.{}", key, F::EXTENSION))
+    }
+
+    /// Return the path to our lock file.
+    fn lockfilename(&self) -> PathBuf {
+        self.inner.statepath.join("lock")
+    }
+}
+
+impl<F: Format> StateMgr for FsStateMgr<F> {
+    fn load<D>(&self, key: &str) -> Result<Option<D>>
+    where
+        D: DeserializeOwned,
+    {
+        let path = self.path_for(key);
+        match fs::read(&path) {
+            Ok(data) => Ok(Some(F::deserialize_owned(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store<S>(&self, key: &str, val: &S) -> Result<()>
+    where
+        S: Serialize,
+    {
+        let mut bytes = Vec::new();
+        F::serialize_into(val, &mut bytes)?;
+        self.store_bytes(key, &bytes)
+    }
+
+    fn can_store(&self) -> bool {
+        self.inner
+            .lockfile
+            .lock()
+            .expect("lock poisoned")
+            .is_some()
+    }
+
+    fn try_lock(&self) -> Result<LockStatus> {
+        let mut guard = self.inner.lockfile.lock().expect("lock poisoned");
+        if guard.is_some() {
+            return Ok(LockStatus::AlreadyHeld);
+        }
+        let mut lockfile = fslock::LockFile::open(&self.lockfilename())?;
+        if lockfile.try_lock()? {
+            *guard = Some(lockfile);
+            Ok(LockStatus::NewlyAcquired)
+        } else {
+            Ok(LockStatus::NoLock)
+        }
+    }
+
+    fn unlock(&self) -> Result<()> {
+        *self.inner.lockfile.lock().expect("lock poisoned") = None;
+        Ok(())
+    }
+}
+
+impl<F: Format> FsStateMgr<F> {
+    /// Write already-encoded `bytes` to the file for `key`, atomically.
+    ///
+    /// Factored out of [`StateMgr::store`] so that
+    /// [`AsyncStateMgr`](crate::AsyncStateMgr) can serialize on the calling
+    /// task and hand off only the already-encoded bytes to its blocking job.
+    pub(crate) fn store_bytes(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        if !self.can_store() {
+            return Err(Error::NoLock);
+        }
+        let path = self.path_for(key);
+        let tmp_path = path.with_extension(format!("{}.tmp", F::EXTENSION));
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! Typed handles onto a single key within a [`StateMgr`](crate::StateMgr).
+
+use crate::{Result, StateMgr};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// A handle to a single, typed value stored at a fixed key within some
+/// [`StateMgr`](crate::StateMgr).
+///
+/// This lets callers work with a single piece of persistent state without
+/// needing to carry around the state manager and the key together.
+pub trait StorageHandle<T> {
+    /// Try to load the value from the store.
+    ///
+    /// Return `None` if no such value exists.
+    fn load(&self) -> Result<Option<T>>;
+
+    /// Try to save `val` to the store, replacing any previous value.
+    fn store(&self, val: &T) -> Result<()>;
+}
+
+/// A reference-counted, type-erased [`StorageHandle`].
+///
+/// This is the type actually returned by
+/// [`StateMgr::create_handle`](crate::StateMgr::create_handle).
+pub type DynStorageHandle<T> = Arc<dyn StorageHandle<T> + Send + Sync>;
+
+/// The concrete [`StorageHandle`] implementation backing
+/// [`StateMgr::create_handle`](crate::StateMgr::create_handle): a state
+/// manager plus the key it should use.
+pub(crate) struct StorageHandleImpl<M, T> {
+    /// The underlying state manager.
+    mgr: M,
+    /// The key this handle reads and writes.
+    key: String,
+    /// Marker for the value type this handle stores.
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<M, T> StorageHandleImpl<M, T> {
+    /// Create a new `StorageHandleImpl` that uses `mgr` to store values at `key`.
+    pub(crate) fn new(mgr: M, key: String) -> Self {
+        StorageHandleImpl {
+            mgr,
+            key,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<M, T> StorageHandle<T> for StorageHandleImpl<M, T>
+where
+    M: StateMgr,
+    T: Serialize + DeserializeOwned,
+{
+    fn load(&self) -> Result<Option<T>> {
+        self.mgr.load(&self.key)
+    }
+
+    fn store(&self, val: &T) -> Result<()> {
+        self.mgr.store(&self.key, val)
+    }
+}
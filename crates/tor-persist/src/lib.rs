@@ -41,11 +41,16 @@
 #![allow(clippy::let_unit_value)] // This can reasonably be done for explicitness
 //! <!-- @@ end lint list maintained by maint/add_warning @@ -->
 
+#[cfg(not(target_arch = "wasm32"))]
+mod async_mgr;
 #[cfg(not(target_arch = "wasm32"))]
 mod fs;
+mod format;
 mod handle;
+mod migrate;
 #[cfg(feature = "testing")]
 mod testing;
+mod tagged;
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::sync::Arc;
@@ -53,10 +58,15 @@ use std::sync::Arc;
 /// Wrapper type for Results returned from this crate.
 type Result<T> = std::result::Result<T, crate::Error>;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use async_mgr::AsyncStateMgr;
 #[cfg(not(target_arch = "wasm32"))]
 pub use fs::FsStateMgr;
+pub use format::{CborFormat, Format, JsonFormat};
 pub use handle::{DynStorageHandle, StorageHandle};
+pub use migrate::{MigrationFn, Migrations, VersionedHandle};
 pub use serde_json::Value as JsonValue;
+pub use tagged::{TaggedHandle, TaggedRecord, TaggedRecordRegistry};
 #[cfg(feature = "testing")]
 pub use testing::TestingStateMgr;
 
@@ -104,6 +114,31 @@ pub trait StateMgr: Clone {
     /// again. If no locks were held, do nothing.
     fn unlock(&self) -> Result<()>;
 
+    /// As [`StateMgr::try_lock`], but block (for up to `timeout`) instead of
+    /// giving up immediately if another holder currently has the lock.
+    ///
+    /// Returns [`Error::Contended`] if `timeout` elapses without the lock
+    /// becoming available. This lets a newly-starting Arti instance wait a
+    /// little while for a previous instance to finish up, rather than
+    /// either failing immediately or silently staying read-only.
+    fn lock_blocking(&self, timeout: std::time::Duration) -> Result<LockStatus> {
+        /// How long to sleep between polling attempts.
+        const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let status = self.try_lock()?;
+            if status.held() {
+                return Ok(status);
+            }
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::Contended);
+            }
+            std::thread::sleep(POLL_INTERVAL.min(remaining));
+        }
+    }
+
     /// Make a new [`StorageHandle`] to store values of particular type
     /// at a particular key.
     fn create_handle<T>(self, key: impl Into<String>) -> DynStorageHandle<T>
@@ -157,13 +192,18 @@ pub enum Error {
     #[error("Storage not locked")]
     NoLock,
 
-    /// Problem when serializing JSON data.
-    #[error("JSON serialization error")]
-    Serialize(#[source] Arc<serde_json::Error>),
+    /// Timed out in [`StateMgr::lock_blocking`] while waiting for another
+    /// holder to release the lock.
+    #[error("Timed out waiting for persistent state lock")]
+    Contended,
+
+    /// Problem when serializing data with a [`Format`].
+    #[error("Serialization error")]
+    Serialize(#[source] Arc<dyn std::error::Error + Send + Sync + 'static>),
 
-    /// Problem when deserializing JSON data.
-    #[error("JSON serialization error")]
-    Deserialize(#[source] Arc<serde_json::Error>),
+    /// Problem when deserializing data with a [`Format`].
+    #[error("Deserialization error")]
+    Deserialize(#[source] Arc<dyn std::error::Error + Send + Sync + 'static>),
 }
 
 impl tor_error::HasKind for Error {
@@ -179,6 +219,7 @@ impl tor_error::HasKind for Error {
                 K::PersistentStateAccessFailed
             }
             E::NoLock          => K::BadApiUsage,
+            E::Contended       => K::PersistentStateContended,
             E::Serialize(..)   => K::Internal,
             E::Deserialize(..) => K::PersistentStateCorrupted,
         }
@@ -191,13 +232,13 @@ impl From<std::io::Error> for Error {
     }
 }
 
-/// Error conversion for JSON errors; use only when loading
-fn load_error(e: serde_json::Error) -> Error {
+/// Error conversion for a [`Format`]'s deserialization errors; use only when loading
+fn load_error(e: impl std::error::Error + Send + Sync + 'static) -> Error {
     Error::Deserialize(Arc::new(e))
 }
 
-/// Error conversion for JSON errors; use only when storing
-fn store_error(e: serde_json::Error) -> Error {
+/// Error conversion for a [`Format`]'s serialization errors; use only when storing
+fn store_error(e: impl std::error::Error + Send + Sync + 'static) -> Error {
     Error::Serialize(Arc::new(e))
 }
 
@@ -0,0 +1,141 @@
+//! Versioned state, with declarative migrations layered on [`Futureproof`].
+//!
+//! [`Futureproof`] only captures "did this parse as `T`, or not" -- it has
+//! no way to upgrade an older on-disk shape into the current one. The
+//! types here add that: every stored value is wrapped in an envelope
+//! carrying an explicit schema version, and a [`Migrations`] registry maps
+//! each older version to the step that brings it forward one version at a
+//! time.
+
+use crate::{load_error, store_error, Futureproof, JsonValue, Result, StateMgr};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+/// A single migration step: given a value at some schema version, produce
+/// the equivalent value at the next version up.
+pub type MigrationFn = fn(JsonValue) -> Result<JsonValue>;
+
+/// A registry of migration steps that can bring an older on-disk
+/// representation of `T` forward to its current schema version.
+pub struct Migrations<T> {
+    /// The schema version that `T` currently deserializes as.
+    current_version: u32,
+    /// Migration steps, keyed by the version they migrate *from*.
+    steps: BTreeMap<u32, MigrationFn>,
+    /// Marker for the type these migrations produce.
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Migrations<T> {
+    /// Create a new `Migrations` registry for a type whose current schema
+    /// version is `current_version`.
+    pub fn new(current_version: u32) -> Self {
+        Migrations {
+            current_version,
+            steps: BTreeMap::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Register `step` as the migration to apply to a value stored at
+    /// `from_version`, producing the equivalent value at `from_version + 1`.
+    pub fn add_step(&mut self, from_version: u32, step: MigrationFn) -> &mut Self {
+        self.steps.insert(from_version, step);
+        self
+    }
+}
+
+/// The on-disk representation of a single [`VersionedHandle`] value: an
+/// explicit schema version plus the value itself, encoded as JSON so that
+/// migrations can inspect and rewrite it structurally.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct VersionedEnvelope {
+    /// The schema version `value` is encoded at.
+    version: u32,
+    /// The stored value, at schema version `version`.
+    value: JsonValue,
+}
+
+/// A handle onto a single key holding a value of type `T`, whose
+/// on-disk schema can evolve over time via a [`Migrations`] registry.
+pub struct VersionedHandle<M, T> {
+    /// The underlying state manager.
+    mgr: M,
+    /// The key this handle reads and writes.
+    key: String,
+    /// The migrations needed to bring an older stored value up to date.
+    migrations: Migrations<T>,
+}
+
+impl<M: StateMgr, T: Serialize + DeserializeOwned> VersionedHandle<M, T> {
+    /// Create a new `VersionedHandle` that uses `mgr` to store a `T` at
+    /// `key`, using `migrations` to read older schema versions.
+    pub fn new(mgr: M, key: impl Into<String>, migrations: Migrations<T>) -> Self {
+        VersionedHandle {
+            mgr,
+            key: key.into(),
+            migrations,
+        }
+    }
+
+    /// Store `val`, tagged with the current schema version.
+    pub fn store(&self, val: &T) -> Result<()> {
+        let value = serde_json::to_value(val).map_err(store_error)?;
+        let envelope = VersionedEnvelope {
+            version: self.migrations.current_version,
+            value,
+        };
+        self.mgr.store(&self.key, &envelope)
+    }
+
+    /// Try to load the value at this handle's key, migrating it forward to
+    /// the current schema version if it's older.
+    ///
+    /// Returns `None` if no value is stored. Returns
+    /// [`Futureproof::Unknown`], rather than an error, for a value tagged
+    /// newer than the current schema version: that's forward compatibility
+    /// with a future Arti, not corruption. Returns
+    /// [`crate::Error::Deserialize`] -- which maps to
+    /// [`PersistentStateCorrupted`](tor_error::ErrorKind::PersistentStateCorrupted)
+    /// -- only if a migration that should apply is missing or fails, or if
+    /// the fully-migrated value still doesn't parse as `T`.
+    pub fn load(&self) -> Result<Option<Futureproof<T>>> {
+        let envelope: Option<VersionedEnvelope> = self.mgr.load(&self.key)?;
+        let Some(mut envelope) = envelope else {
+            return Ok(None);
+        };
+
+        if envelope.version > self.migrations.current_version {
+            return Ok(Some(Futureproof::Unknown(envelope.value)));
+        }
+
+        while envelope.version < self.migrations.current_version {
+            let step = self
+                .migrations
+                .steps
+                .get(&envelope.version)
+                .ok_or_else(|| load_error(MissingMigrationStep(envelope.version)))?;
+            envelope.value = step(envelope.value)?;
+            envelope.version += 1;
+        }
+
+        match serde_json::from_value(envelope.value) {
+            Ok(val) => Ok(Some(Futureproof::Understandable(val))),
+            Err(e) => Err(load_error(e)),
+        }
+    }
+}
+
+/// Error: no migration step was registered for a version we needed to
+/// migrate away from.
+#[derive(Debug)]
+struct MissingMigrationStep(u32);
+
+impl std::fmt::Display for MissingMigrationStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no migration registered for schema version {}", self.0)
+    }
+}
+
+impl std::error::Error for MissingMigrationStep {}
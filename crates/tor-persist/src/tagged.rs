@@ -0,0 +1,145 @@
+//! A heterogeneous, type-tagged value store within a single key.
+//!
+//! [`StateMgr::create_handle`](crate::StateMgr::create_handle) ties a
+//! [`StorageHandle`](crate::StorageHandle) to one concrete `T`. The types
+//! here instead let a single key hold records of any registered type,
+//! tagged with a stable name so the right `Deserialize` impl can be found
+//! again on load -- in the style of the `typetag`/`erased-serde` crates.
+
+use crate::{load_error, store_error, Futureproof, JsonValue, Result, StateMgr};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A record that can be stored in a [`TaggedHandle`] alongside records of
+/// other types.
+///
+/// Implementors also need an entry in the [`TaggedRecordRegistry`] passed
+/// to the handle, so that a stored value's tag can be mapped back to the
+/// right `Deserialize` impl on load.
+pub trait TaggedRecord: erased_serde::Serialize + Send + Sync {
+    /// A stable name for this record's type, persisted alongside its
+    /// payload so it can be recognized again later -- including by an
+    /// older version of Arti that doesn't know this type at all.
+    fn type_tag(&self) -> &'static str;
+}
+
+erased_serde::serialize_trait_object!(TaggedRecord);
+
+/// A function that can deserialize one registered record type from an
+/// erased deserializer, returning it as a type-erased [`TaggedRecord`].
+type DeserializeFn = fn(&mut dyn erased_serde::Deserializer<'_>) -> erased_serde::Result<Box<dyn TaggedRecord>>;
+
+/// A registry mapping type tags to the `Deserialize` impls that can read
+/// them back.
+///
+/// Callers build one of these (typically once, at startup) listing every
+/// record type they expect to find under a given key, then pass it to
+/// [`TaggedHandle::load`].
+#[derive(Clone, Default)]
+pub struct TaggedRecordRegistry {
+    /// The registered deserialization functions, keyed by type tag.
+    entries: HashMap<&'static str, DeserializeFn>,
+}
+
+impl TaggedRecordRegistry {
+    /// Create a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `T` under `tag`, so that [`TaggedHandle::load`] can
+    /// recognize and deserialize records of this type.
+    pub fn register<T>(&mut self, tag: &'static str)
+    where
+        T: TaggedRecord + DeserializeOwned + 'static,
+    {
+        self.entries.insert(tag, |deserializer| {
+            let val: T = erased_serde::deserialize(deserializer)?;
+            Ok(Box::new(val) as Box<dyn TaggedRecord>)
+        });
+    }
+}
+
+/// The on-disk representation of a single [`TaggedHandle`] value: a type
+/// tag plus its JSON-encoded payload.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct TaggedEnvelope {
+    /// The stored record's [`TaggedRecord::type_tag`].
+    tag: String,
+    /// The stored record's serialized payload.
+    payload: JsonValue,
+}
+
+/// A handle onto a single key that can hold records of any type
+/// registered in a [`TaggedRecordRegistry`].
+///
+/// Unlike [`StorageHandle`](crate::StorageHandle), this handle isn't
+/// parameterized by a single `T`: each stored value carries its own type
+/// tag, so a newer Arti can write a record type an older Arti has never
+/// heard of, and the older Arti will hand it back as
+/// [`Futureproof::Unknown`] on load instead of failing outright.
+pub struct TaggedHandle<M> {
+    /// The underlying state manager.
+    mgr: M,
+    /// The key this handle reads and writes.
+    key: String,
+    /// The registry used to recognize stored records on load.
+    registry: Arc<TaggedRecordRegistry>,
+}
+
+impl<M: StateMgr> TaggedHandle<M> {
+    /// Create a new `TaggedHandle` that uses `mgr` to store tagged records
+    /// at `key`, recognizing the types listed in `registry` on load.
+    pub fn new(mgr: M, key: impl Into<String>, registry: Arc<TaggedRecordRegistry>) -> Self {
+        TaggedHandle {
+            mgr,
+            key: key.into(),
+            registry,
+        }
+    }
+
+    /// Store `record`, tagged with its [`TaggedRecord::type_tag`],
+    /// replacing any previous value at this handle's key.
+    pub fn store(&self, record: &dyn TaggedRecord) -> Result<()> {
+        let mut buf = Vec::new();
+        {
+            let mut json_ser = serde_json::Serializer::new(&mut buf);
+            let mut erased_ser = <dyn erased_serde::Serializer>::erase(&mut json_ser);
+            record
+                .erased_serialize(&mut erased_ser)
+                .map_err(store_error)?;
+        }
+        let payload: JsonValue = serde_json::from_slice(&buf).map_err(store_error)?;
+        let envelope = TaggedEnvelope {
+            tag: record.type_tag().to_string(),
+            payload,
+        };
+        self.mgr.store(&self.key, &envelope)
+    }
+
+    /// Try to load the record at this handle's key.
+    ///
+    /// Returns `None` if no record is stored. Returns
+    /// [`Futureproof::Unknown`] if a record is stored but its type tag
+    /// isn't in this handle's registry, rather than treating that as an
+    /// error: this lets an older Arti skip over record kinds introduced
+    /// by a newer one.
+    pub fn load(&self) -> Result<Option<Futureproof<Box<dyn TaggedRecord>>>> {
+        let envelope: Option<TaggedEnvelope> = self.mgr.load(&self.key)?;
+        let Some(envelope) = envelope else {
+            return Ok(None);
+        };
+        match self.registry.entries.get(envelope.tag.as_str()) {
+            Some(deserialize_fn) => {
+                let mut json_de = serde_json::Deserializer::from_str(
+                    &serde_json::to_string(&envelope.payload).map_err(load_error)?,
+                );
+                let mut erased_de = <dyn erased_serde::Deserializer>::erase(&mut json_de);
+                let record = deserialize_fn(&mut erased_de).map_err(load_error)?;
+                Ok(Some(Futureproof::Understandable(record)))
+            }
+            None => Ok(Some(Futureproof::Unknown(envelope.payload))),
+        }
+    }
+}
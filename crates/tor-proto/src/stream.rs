@@ -7,16 +7,21 @@
 //!
 //! # Limitations
 //!
-//! There is no fairness, rate-limiting, or flow control.
+//! There is still no fairness scheduler for streams multiplexed on the same
+//! circuit: whichever stream's writer wins the race gets serviced first.
+//! [`StreamRateLimit`] provides a token-bucket building block for per-stream
+//! throughput caps, but nothing in this module consults it yet.
 
 mod data;
 mod params;
 mod raw;
+mod rate_limit;
 mod resolve;
 
 pub use data::DataStream;
 pub use params::StreamParameters;
 pub use raw::RawCellStream;
+pub use rate_limit::StreamRateLimit;
 pub use resolve::ResolveStream;
 
 pub use tor_cell::relaycell::msg::IpVersionPreference;
@@ -0,0 +1,122 @@
+//! A token-bucket rate limiter used to cap per-stream throughput.
+//!
+//! [`StreamRateLimit`] isn't wired into any stream type yet; for now it's a
+//! building block that circuit code can use directly.
+
+use std::time::{Duration, Instant};
+
+use tor_rtcompat::SleepProvider;
+
+/// A token bucket, used to smooth out a stream's outgoing throughput.
+///
+/// The bucket holds up to `capacity` bytes worth of tokens, and refills at
+/// `refill_rate` bytes/sec; a write of `n` bytes consumes `n` tokens, waiting
+/// for enough to accrue first if there aren't currently enough available.
+///
+/// A bucket with no configured limit (the default) never waits.
+#[derive(Debug, Clone)]
+pub struct StreamRateLimit {
+    /// The maximum number of tokens the bucket can hold, i.e. the largest
+    /// burst we'll allow before throttling kicks in.
+    ///
+    /// `None` means "unlimited": no throttling is applied at all.
+    capacity: Option<u64>,
+    /// How many bytes/sec the bucket refills at.
+    ///
+    /// Only meaningful while `capacity` is `Some`; the two are configured
+    /// together.
+    refill_rate: u64,
+    /// The number of tokens currently available.
+    tokens: u64,
+    /// The last time we refilled the bucket, according to whichever
+    /// [`SleepProvider`] we were last polled with.
+    ///
+    /// `None` until the first call to [`refill`](Self::refill), so that we
+    /// never need a real-clock reading to construct or reconfigure a
+    /// limiter: everything that actually measures elapsed time goes through
+    /// the injected clock passed to [`take`](Self::take).
+    last_refill: Option<Instant>,
+}
+
+impl StreamRateLimit {
+    /// Create a new, unlimited rate limiter.
+    ///
+    /// Call [`set_limit`](Self::set_limit) to actually bound throughput.
+    pub fn unlimited() -> Self {
+        StreamRateLimit {
+            capacity: None,
+            refill_rate: 0,
+            tokens: 0,
+            last_refill: None,
+        }
+    }
+
+    /// Configure this limiter to allow bursts of up to `max_burst` bytes,
+    /// refilling at `max_rate` bytes/sec.
+    ///
+    /// The bucket starts full, so the first burst is allowed immediately.
+    pub fn set_limit(&mut self, max_rate: u64, max_burst: u64) {
+        self.capacity = Some(max_burst);
+        self.refill_rate = max_rate;
+        self.tokens = max_burst;
+        self.last_refill = None;
+    }
+
+    /// Remove any configured limit, reverting to unlimited throughput.
+    pub fn clear_limit(&mut self) {
+        self.capacity = None;
+    }
+
+    /// Return true if this limiter has no configured limit.
+    pub fn is_unlimited(&self) -> bool {
+        self.capacity.is_none()
+    }
+
+    /// Refill the bucket based on how much time has passed since we last did
+    /// so, without exceeding `capacity`.
+    fn refill(&mut self, capacity: u64, now: Instant) {
+        // On the very first refill there's nothing to compare `now`
+        // against yet; treat it as no time having passed rather than
+        // reading a real-clock `Instant::now()`, so every duration we ever
+        // compute comes from the same `SleepProvider` the caller gave us.
+        let elapsed = match self.last_refill {
+            Some(last) => now.saturating_duration_since(last),
+            None => Duration::ZERO,
+        };
+        // Tokens accrue at `refill_rate` bytes/sec; this can't overflow a
+        // u64 for any elapsed duration we'd plausibly see between polls.
+        let accrued = (elapsed.as_secs_f64() * self.refill_rate as f64) as u64;
+        self.tokens = self.tokens.saturating_add(accrued).min(capacity);
+        self.last_refill = Some(now);
+    }
+
+    /// Wait until `n` bytes' worth of tokens are available, then consume
+    /// them.
+    ///
+    /// If this limiter is unlimited, returns immediately.
+    pub async fn take<R: SleepProvider>(&mut self, rt: &R, n: u64) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        // A request larger than the bucket's capacity would otherwise never
+        // succeed; cap it so a single oversized write doesn't deadlock.
+        let n = n.min(capacity);
+        loop {
+            let now = rt.now();
+            self.refill(capacity, now);
+            if self.tokens >= n {
+                self.tokens -= n;
+                return;
+            }
+            let shortfall = n - self.tokens;
+            let wait_secs = shortfall as f64 / self.refill_rate.max(1) as f64;
+            rt.sleep(Duration::from_secs_f64(wait_secs)).await;
+        }
+    }
+}
+
+impl Default for StreamRateLimit {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
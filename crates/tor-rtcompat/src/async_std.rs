@@ -0,0 +1,179 @@
+//! Entry points for use with async-std runtimes.
+#[cfg(feature = "native-tls")]
+use crate::impls::native_tls::NativeTlsProvider;
+use crate::impls::async_std::AsyncStdRuntimeHandle as Handle;
+
+use crate::{CompoundRuntime, RealCoarseTimeProvider, SpawnBlocking};
+use std::io::Result as IoResult;
+
+#[cfg(feature = "rustls")]
+use crate::impls::rustls::RustlsProvider;
+use crate::impls::async_std::net::TcpStream;
+
+/// A [`Runtime`] built around an async-std executor, and `native_tls`.
+///
+/// # Limitations
+///
+/// Unlike the Tokio handles, this type doesn't wrap a pre-existing runtime
+/// object: async-std doesn't expose one to wrap, so every instance just
+/// shares the implicit global async-std executor.
+#[derive(Clone)]
+#[cfg(feature = "native-tls")]
+pub struct AsyncStdNativeTlsRuntime {
+    /// The actual [`CompoundRuntime`] that implements this.
+    inner: HandleInner,
+}
+
+/// Implementation type for an AsyncStdNativeTlsRuntime.
+#[cfg(feature = "native-tls")]
+type HandleInner =
+    CompoundRuntime<Handle, Handle, Handle, NativeTlsProvider<TcpStream>, Handle, Handle, RealCoarseTimeProvider>;
+
+/// A [`Runtime`] built around an async-std executor, and `rustls`.
+#[derive(Clone)]
+#[cfg(feature = "rustls")]
+pub struct AsyncStdRustlsRuntime {
+    /// The actual [`CompoundRuntime`] that implements this.
+    inner: RustlsHandleInner,
+}
+
+/// Implementation type for an AsyncStdRustlsRuntime.
+#[cfg(feature = "rustls")]
+type RustlsHandleInner =
+    CompoundRuntime<Handle, Handle, Handle, RustlsProvider<TcpStream>, Handle, Handle, RealCoarseTimeProvider>;
+
+/// The runtime that we prefer to use, out of all the async-std runtimes
+/// compiled into this crate.
+///
+/// If `native-tls` is enabled, this is [`AsyncStdNativeTlsRuntime`];
+/// otherwise (so long as `rustls` is enabled) it's
+/// [`AsyncStdRustlsRuntime`].
+#[cfg(feature = "native-tls")]
+pub type PreferredRuntime = AsyncStdNativeTlsRuntime;
+
+/// The runtime that we prefer to use, out of all the async-std runtimes
+/// compiled into this crate.
+///
+/// See the other definition of `PreferredRuntime` for more information.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub type PreferredRuntime = AsyncStdRustlsRuntime;
+
+#[cfg(feature = "native-tls")]
+crate::opaque::implement_opaque_runtime! {
+    AsyncStdNativeTlsRuntime { inner : HandleInner }
+}
+
+#[cfg(feature = "rustls")]
+crate::opaque::implement_opaque_runtime! {
+    AsyncStdRustlsRuntime { inner : RustlsHandleInner }
+}
+
+#[cfg(feature = "native-tls")]
+impl AsyncStdNativeTlsRuntime {
+    /// Create a new [`AsyncStdNativeTlsRuntime`].
+    ///
+    /// Since async-std doesn't expose a runtime object of its own to wrap,
+    /// every `AsyncStdNativeTlsRuntime` shares the same implicit global
+    /// async-std executor; this just builds a fresh handle onto it.
+    pub fn create() -> IoResult<Self> {
+        let h = Handle::new();
+        let coarse = RealCoarseTimeProvider::default();
+        let _ = coarse.launch_refresh_task(&h);
+        Ok(AsyncStdNativeTlsRuntime {
+            inner: CompoundRuntime::new(
+                h.clone(),
+                h.clone(),
+                h.clone(),
+                NativeTlsProvider::default(),
+                h.clone(),
+                h,
+                coarse,
+            ),
+        })
+    }
+
+    /// Return an [`AsyncStdNativeTlsRuntime`] for the currently running
+    /// async-std executor.
+    ///
+    /// Since async-std has no notion of "the current runtime" the way
+    /// Tokio does, this is equivalent to [`AsyncStdNativeTlsRuntime::create`].
+    pub fn current() -> IoResult<Self> {
+        Self::create()
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl AsyncStdRustlsRuntime {
+    /// Create a new [`AsyncStdRustlsRuntime`].
+    ///
+    /// Since async-std doesn't expose a runtime object of its own to wrap,
+    /// every `AsyncStdRustlsRuntime` shares the same implicit global
+    /// async-std executor; this just builds a fresh handle onto it.
+    pub fn create() -> IoResult<Self> {
+        let h = Handle::new();
+        let coarse = RealCoarseTimeProvider::default();
+        let _ = coarse.launch_refresh_task(&h);
+        Ok(AsyncStdRustlsRuntime {
+            inner: CompoundRuntime::new(
+                h.clone(),
+                h.clone(),
+                h.clone(),
+                RustlsProvider::default(),
+                h.clone(),
+                h,
+                coarse,
+            ),
+        })
+    }
+
+    /// Return an [`AsyncStdRustlsRuntime`] for the currently running
+    /// async-std executor.
+    ///
+    /// Since async-std has no notion of "the current runtime" the way
+    /// Tokio does, this is equivalent to [`AsyncStdRustlsRuntime::create`].
+    pub fn current() -> IoResult<Self> {
+        Self::create()
+    }
+}
+
+/// Return a new [`PreferredRuntime`] wrapping the async-std executor, so
+/// callers don't need to pick a TLS backend themselves.
+pub fn create() -> IoResult<PreferredRuntime> {
+    PreferredRuntime::create()
+}
+
+/// Return a [`PreferredRuntime`] for the currently running async-std
+/// executor, so callers don't need to pick a TLS backend themselves.
+pub fn current() -> IoResult<PreferredRuntime> {
+    PreferredRuntime::current()
+}
+
+/// Run a test function using a freshly created async-std runtime.
+///
+/// # Panics
+///
+/// Panics if we can't create an async-std runtime.
+#[cfg(feature = "native-tls")]
+pub fn test_with_runtime<P, F, O>(func: P) -> O
+where
+    P: FnOnce(AsyncStdNativeTlsRuntime) -> F,
+    F: futures::Future<Output = O>,
+{
+    let runtime = AsyncStdNativeTlsRuntime::create().expect("Failed to create an async-std runtime");
+    runtime.clone().block_on(func(runtime))
+}
+
+/// Run a test function using a freshly created async-std runtime.
+///
+/// # Panics
+///
+/// Panics if we can't create an async-std runtime.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub fn test_with_runtime<P, F, O>(func: P) -> O
+where
+    P: FnOnce(AsyncStdRustlsRuntime) -> F,
+    F: futures::Future<Output = O>,
+{
+    let runtime = AsyncStdRustlsRuntime::create().expect("Failed to create an async-std runtime");
+    runtime.clone().block_on(func(runtime))
+}
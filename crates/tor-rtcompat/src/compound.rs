@@ -1,12 +1,17 @@
 //! Define a [`CompoundRuntime`] part that can be built from several component
 //! pieces.
 
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    ffi::OsStr,
+    net::SocketAddr,
+    sync::{atomic::AtomicU64, atomic::Ordering, Arc},
+    time::Duration,
+};
 
 use crate::traits::*;
 use async_trait::async_trait;
 use educe::Educe;
-use futures::{future::FutureObj, task::Spawn};
+use futures::{future::FutureObj, task::Spawn, task::SpawnExt};
 use std::io::Result as IoResult;
 use std::time::{Instant, SystemTime};
 
@@ -14,24 +19,27 @@ use std::time::{Instant, SystemTime};
 ///
 /// The `SpawnR` component should implements [`Spawn`] and [`BlockOn`];
 /// the `SleepR` component should implement [`SleepProvider`]; the `TcpR`
-/// component should implement [`TcpProvider`]; and the `TlsR` component should
-/// implement [`TlsProvider`].
+/// component should implement [`TcpProvider`]; the `TlsR` component should
+/// implement [`TlsProvider`]; the `UdpR` component should implement
+/// [`UdpProvider`]; the `ProcR` component should implement
+/// [`ProcessProvider`]; and the `CoarseR` component should implement
+/// [`CoarseTimeProvider`].
 ///
 /// You can use this structure to create new runtimes in two ways: either by
 /// overriding a single part of an existing runtime, or by building an entirely
 /// new runtime from pieces.
 #[derive(Educe)]
 #[educe(Clone)] // #[derive(Clone)] wrongly infers Clone bounds on the generic parameters
-pub struct CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR> {
+pub struct CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> {
     /// The actual collection of Runtime objects.
     ///
     /// We wrap this in an Arc rather than requiring that each item implement
     /// Clone, though we could change our minds later on.
-    inner: Arc<Inner<SpawnR, SleepR, TcpR, TlsR, UdpR>>,
+    inner: Arc<Inner<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>>,
 }
 
 /// A collection of objects implementing that traits that make up a [`Runtime`]
-struct Inner<SpawnR, SleepR, TcpR, TlsR, UdpR> {
+struct Inner<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> {
     /// A `Spawn` and `BlockOn` implementation.
     spawn: SpawnR,
     /// A `SleepProvider` implementation.
@@ -42,11 +50,25 @@ struct Inner<SpawnR, SleepR, TcpR, TlsR, UdpR> {
     tls: TlsR,
     /// A `UdpProvider` implementation
     udp: UdpR,
+    /// A `ProcessProvider` implementation, used to spawn pluggable-transport
+    /// child processes.
+    proc: ProcR,
+    /// A `CoarseTimeProvider` implementation.
+    coarse: CoarseR,
 }
 
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR> CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR> {
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> {
     /// Construct a new CompoundRuntime from its components.
-    pub fn new(spawn: SpawnR, sleep: SleepR, tcp: TcpR, tls: TlsR, udp: UdpR) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        spawn: SpawnR,
+        sleep: SleepR,
+        tcp: TcpR,
+        tls: TlsR,
+        udp: UdpR,
+        proc: ProcR,
+        coarse: CoarseR,
+    ) -> Self {
         CompoundRuntime {
             inner: Arc::new(Inner {
                 spawn,
@@ -54,12 +76,15 @@ impl<SpawnR, SleepR, TcpR, TlsR, UdpR> CompoundRuntime<SpawnR, SleepR, TcpR, Tls
                 tcp,
                 tls,
                 udp,
+                proc,
+                coarse,
             }),
         }
     }
 }
 
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR> Spawn for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR>
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> Spawn
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
 where
     SpawnR: Spawn,
 {
@@ -69,13 +94,16 @@ where
     }
 }
 
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR> BlockOn for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR>
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> BlockOn
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
 where
     SpawnR: BlockOn,
     SleepR: Clone + Send + Sync + 'static,
     TcpR: Clone + Send + Sync + 'static,
     TlsR: Clone + Send + Sync + 'static,
     UdpR: Clone + Send + Sync + 'static,
+    ProcR: Clone + Send + Sync + 'static,
+    CoarseR: Clone + Send + Sync + 'static,
 {
     #[inline]
     fn block_on<F: futures::Future>(&self, future: F) -> F::Output {
@@ -83,14 +111,16 @@ where
     }
 }
 
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR> SleepProvider
-    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR>
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> SleepProvider
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
 where
     SleepR: SleepProvider,
     SpawnR: Clone + Send + Sync + 'static,
     TcpR: Clone + Send + Sync + 'static,
     TlsR: Clone + Send + Sync + 'static,
     UdpR: Clone + Send + Sync + 'static,
+    ProcR: Clone + Send + Sync + 'static,
+    CoarseR: Clone + Send + Sync + 'static,
 {
     type SleepFuture = SleepR::SleepFuture;
 
@@ -111,8 +141,8 @@ where
 }
 
 #[async_trait]
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR> TcpProvider
-    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR>
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> TcpProvider
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
 where
     TcpR: TcpProvider,
     SpawnR: Send + Sync + 'static,
@@ -120,6 +150,8 @@ where
     TcpR: Send + Sync + 'static,
     TlsR: Send + Sync + 'static,
     UdpR: Send + Sync + 'static,
+    ProcR: Send + Sync + 'static,
+    CoarseR: Send + Sync + 'static,
 {
     type TcpStream = TcpR::TcpStream;
 
@@ -136,14 +168,16 @@ where
     }
 }
 
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR, S> TlsProvider<S>
-    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR>
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR, S> TlsProvider<S>
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
 where
     TcpR: TcpProvider,
     TlsR: TlsProvider<S>,
     SleepR: Clone + Send + Sync + 'static,
     SpawnR: Clone + Send + Sync + 'static,
     UdpR: Clone + Send + Sync + 'static,
+    ProcR: Clone + Send + Sync + 'static,
+    CoarseR: Clone + Send + Sync + 'static,
 {
     type Connector = TlsR::Connector;
     type TlsStream = TlsR::TlsStream;
@@ -154,8 +188,8 @@ where
     }
 }
 
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR> std::fmt::Debug
-    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR>
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> std::fmt::Debug
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("CompoundRuntime").finish_non_exhaustive()
@@ -163,8 +197,8 @@ impl<SpawnR, SleepR, TcpR, TlsR, UdpR> std::fmt::Debug
 }
 
 #[async_trait]
-impl<SpawnR, SleepR, TcpR, TlsR, UdpR> UdpProvider
-    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR>
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> UdpProvider
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
 where
     UdpR: UdpProvider,
     SpawnR: Send + Sync + 'static,
@@ -172,6 +206,8 @@ where
     TcpR: Send + Sync + 'static,
     TlsR: Send + Sync + 'static,
     UdpR: Send + Sync + 'static,
+    ProcR: Send + Sync + 'static,
+    CoarseR: Send + Sync + 'static,
 {
     type UdpSocket = UdpR::UdpSocket;
 
@@ -180,3 +216,183 @@ where
         self.inner.udp.bind(addr).await
     }
 }
+
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> ProcessProvider
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
+where
+    ProcR: ProcessProvider,
+    SpawnR: Send + Sync + 'static,
+    SleepR: Send + Sync + 'static,
+    TcpR: Send + Sync + 'static,
+    TlsR: Send + Sync + 'static,
+    UdpR: Send + Sync + 'static,
+    CoarseR: Send + Sync + 'static,
+{
+    type Child = ProcR::Child;
+    type Command = ProcR::Command;
+
+    #[inline]
+    fn new_command(&self, program: &OsStr) -> Self::Command {
+        self.inner.proc.new_command(program)
+    }
+}
+
+impl<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR> CoarseTimeProvider
+    for CompoundRuntime<SpawnR, SleepR, TcpR, TlsR, UdpR, ProcR, CoarseR>
+where
+    CoarseR: CoarseTimeProvider,
+    SpawnR: Send + Sync + 'static,
+    SleepR: Send + Sync + 'static,
+    TcpR: Send + Sync + 'static,
+    TlsR: Send + Sync + 'static,
+    UdpR: Send + Sync + 'static,
+    ProcR: Send + Sync + 'static,
+{
+    #[inline]
+    fn coarse_now(&self) -> CoarseInstant {
+        self.inner.coarse.coarse_now()
+    }
+}
+
+/// A cheap-to-compare monotonic timestamp, as returned by
+/// [`CoarseTimeProvider::coarse_now`].
+///
+/// A `CoarseInstant` is a millisecond count since its provider's baseline,
+/// so comparing or subtracting two of them is plain integer arithmetic. The
+/// tradeoff is precision -- a `CoarseInstant` can lag the true time by up
+/// to whatever refresh granularity its [`CoarseTimeProvider`] was built with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CoarseInstant {
+    /// Milliseconds elapsed since our provider's baseline `Instant`.
+    millis_since_baseline: u64,
+}
+
+impl CoarseInstant {
+    /// Return how much time has elapsed between `earlier` and `self`,
+    /// or `Duration::ZERO` if `earlier` is actually later than `self`.
+    pub fn saturating_duration_since(&self, earlier: CoarseInstant) -> Duration {
+        Duration::from_millis(
+            self.millis_since_baseline
+                .saturating_sub(earlier.millis_since_baseline),
+        )
+    }
+}
+
+/// A trait for runtime components that can report a [`CoarseInstant`].
+///
+/// This is a separate, optional capability from [`SleepProvider::now`]:
+/// `coarse_now` returns a cached, lower-precision timestamp, for code on a
+/// hot path that only needs approximate "has about X ms passed?" semantics
+/// (most of Arti's internal timeout bookkeeping).
+pub trait CoarseTimeProvider {
+    /// Return the current coarse timestamp.
+    ///
+    /// The result is monotonic, and is guaranteed to be within this
+    /// provider's documented refresh granularity of the real elapsed time.
+    fn coarse_now(&self) -> CoarseInstant;
+}
+
+/// Granularity at which a [`RealCoarseTimeProvider`] refreshes its cached
+/// timestamp, if none is given explicitly. Chosen arbitrarily: coarse
+/// enough that most reads never touch the clock, fine enough that it's well
+/// under any timeout Arti actually cares about.
+const DEFAULT_COARSE_GRANULARITY: Duration = Duration::from_millis(250);
+
+/// Shared state for a [`RealCoarseTimeProvider`], kept behind an `Arc` so
+/// that clones, and any background refresh task, all observe the same
+/// cached timestamp.
+struct CoarseTimeInner {
+    /// The instant against which every [`CoarseInstant`] is measured.
+    baseline: Instant,
+    /// Milliseconds elapsed since `baseline`, as of the last refresh.
+    millis: AtomicU64,
+    /// How stale `millis` is allowed to get before a read refreshes it.
+    granularity: Duration,
+}
+
+impl CoarseTimeInner {
+    /// Recompute and store the current millisecond count, returning it.
+    fn refresh(&self) -> u64 {
+        let millis = u64::try_from(self.baseline.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.millis.store(millis, Ordering::Relaxed);
+        millis
+    }
+}
+
+/// A real [`CoarseTimeProvider`], backed by an [`AtomicU64`] holding
+/// milliseconds elapsed since a fixed [`Instant`] baseline.
+///
+/// The cached value can be kept fresh in either of two ways, which can be
+/// combined: call [`RealCoarseTimeProvider::launch_refresh_task`] to spawn a
+/// lightweight background task that ticks every `granularity`, or simply
+/// rely on [`RealCoarseTimeProvider::coarse_now`]'s lazy refresh, which
+/// re-reads the clock itself whenever the cached value has gone stale.
+///
+/// Every call to `coarse_now` reads the clock once, to check whether the
+/// cached value is older than `granularity`; a stale value costs a second
+/// clock read and a store. The background task does not remove the first
+/// read: it only makes the second one less frequent.
+#[derive(Clone)]
+pub struct RealCoarseTimeProvider {
+    /// The shared cached timestamp.
+    inner: Arc<CoarseTimeInner>,
+}
+
+impl Default for RealCoarseTimeProvider {
+    fn default() -> Self {
+        Self::with_granularity(DEFAULT_COARSE_GRANULARITY)
+    }
+}
+
+impl RealCoarseTimeProvider {
+    /// Construct a new `RealCoarseTimeProvider` that lazily refreshes its
+    /// cached timestamp at most once per `granularity`.
+    pub fn with_granularity(granularity: Duration) -> Self {
+        RealCoarseTimeProvider {
+            inner: Arc::new(CoarseTimeInner {
+                baseline: Instant::now(),
+                millis: AtomicU64::new(0),
+                granularity,
+            }),
+        }
+    }
+
+    /// Spawn a background task on `spawn` that refreshes our cached
+    /// timestamp roughly every `granularity`, so that most calls to
+    /// [`coarse_now`](CoarseTimeProvider::coarse_now) never need to take the
+    /// lazy-refresh path themselves.
+    ///
+    /// This is optional: without it, `coarse_now` still keeps itself
+    /// reasonably fresh on its own, just with more calls that have to
+    /// refresh the cached value themselves.
+    pub fn launch_refresh_task<R>(&self, runtime: &R) -> Result<(), futures::task::SpawnError>
+    where
+        R: Spawn + SleepProvider + Clone + Send + Sync + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        let granularity = inner.granularity;
+        let sleeper = runtime.clone();
+        runtime.spawn(async move {
+            loop {
+                sleeper.sleep(granularity).await;
+                inner.refresh();
+            }
+        })
+    }
+}
+
+impl CoarseTimeProvider for RealCoarseTimeProvider {
+    fn coarse_now(&self) -> CoarseInstant {
+        let mut millis = self.inner.millis.load(Ordering::Relaxed);
+        let last_known = self.inner.baseline + Duration::from_millis(millis);
+        if last_known.elapsed() > self.inner.granularity {
+            // Nobody has refreshed recently enough: do it ourselves. This
+            // keeps `coarse_now` accurate even when no background refresh
+            // task is running, at the cost of a second clock read.
+            millis = self.inner.refresh();
+        }
+        CoarseInstant {
+            millis_since_baseline: millis,
+        }
+    }
+}
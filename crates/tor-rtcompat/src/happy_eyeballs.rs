@@ -0,0 +1,143 @@
+//! A dual-stack, RFC 8305 ("Happy Eyeballs v2") connect combinator.
+//!
+//! [`connect_happy_eyeballs`] lets a caller holding both A and AAAA records
+//! race connection attempts across them instead of serializing the attempts
+//! and eating a full connect timeout on each dead address before trying the
+//! next.
+
+use crate::{Runtime, SleepProvider, TcpProvider};
+use futures::stream::FuturesUnordered;
+use futures::FutureExt as _;
+use futures::StreamExt as _;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The default delay between launching successive connection attempts, per
+/// RFC 8305's recommendation.
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// The smallest delay [`connect_happy_eyeballs_with_delay`] will honor
+/// between successive connection attempts.
+pub const MIN_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(100);
+
+/// Interleave `addrs` by address family: the first IPv6 address, then the
+/// first IPv4 address, alternating from there, with any leftover addresses
+/// of one family appended once the other runs out.
+///
+/// This is the attempt order RFC 8305 §4 recommends.
+fn interleave_by_family(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut v6 = addrs.iter().copied().filter(SocketAddr::is_ipv6);
+    let mut v4 = addrs.iter().copied().filter(SocketAddr::is_ipv4);
+    let mut out = Vec::with_capacity(addrs.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (Some(a), Some(b)) => {
+                out.push(a);
+                out.push(b);
+            }
+            (Some(a), None) => {
+                out.push(a);
+                out.extend(v6);
+                break;
+            }
+            (None, Some(b)) => {
+                out.push(b);
+                out.extend(v4);
+                break;
+            }
+            (None, None) => break,
+        }
+    }
+    out
+}
+
+/// Connect to one of `addrs`, racing attempts RFC-8305-style with the
+/// default [`DEFAULT_CONNECTION_ATTEMPT_DELAY`] stagger.
+///
+/// See [`connect_happy_eyeballs_with_delay`] for the full behavior.
+pub async fn connect_happy_eyeballs<R: Runtime>(
+    rt: &R,
+    addrs: &[SocketAddr],
+) -> IoResult<R::TcpStream> {
+    connect_happy_eyeballs_with_delay(rt, addrs, DEFAULT_CONNECTION_ATTEMPT_DELAY).await
+}
+
+/// Connect to one of `addrs`, racing attempts RFC-8305-style.
+///
+/// `addrs` are interleaved by address family (see [`interleave_by_family`])
+/// and tried one at a time: after launching an attempt, we wait up to
+/// `delay` (clamped to [`MIN_CONNECTION_ATTEMPT_DELAY`]) via
+/// [`SleepProvider::sleep`] before launching the next one *without*
+/// cancelling the attempts already in flight. The first attempt to connect
+/// wins, and every other in-flight attempt is dropped. A failed attempt
+/// immediately launches the next candidate rather than waiting out the
+/// delay.
+///
+/// Returns an error if `addrs` is empty, or the last error seen if every
+/// candidate failed to connect.
+///
+/// Because the stagger is driven by [`SleepProvider::sleep`], this whole
+/// race is deterministically testable under mock time (e.g.
+/// `MockSleepRuntime::wait_for`).
+pub async fn connect_happy_eyeballs_with_delay<R: Runtime>(
+    rt: &R,
+    addrs: &[SocketAddr],
+    delay: Duration,
+) -> IoResult<R::TcpStream> {
+    if addrs.is_empty() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "no addresses to connect to",
+        ));
+    }
+    let delay = delay.max(MIN_CONNECTION_ATTEMPT_DELAY);
+
+    let mut remaining = interleave_by_family(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err: Option<IoError> = None;
+
+    let first = remaining.next().expect("interleaved list of a non-empty slice is non-empty");
+    attempts.push(async move { rt.connect(&first).await });
+
+    loop {
+        if remaining.len() == 0 {
+            // No more candidates to launch; just wait out whichever
+            // attempts are still in flight.
+            match attempts.next().await {
+                Some(Ok(stream)) => return Ok(stream),
+                Some(Err(e)) => last_err = Some(e),
+                None => break,
+            }
+            continue;
+        }
+
+        let stagger = rt.sleep(delay);
+        futures::pin_mut!(stagger);
+        futures::select_biased! {
+            res = attempts.next() => match res {
+                Some(Ok(stream)) => return Ok(stream),
+                Some(Err(e)) => {
+                    last_err = Some(e);
+                    // Don't wait out the rest of the stagger delay: a
+                    // failure should trigger the next candidate right away.
+                    if let Some(addr) = remaining.next() {
+                        attempts.push(async move { rt.connect(&addr).await });
+                    }
+                }
+                None => unreachable!("attempts is non-empty while we hold the loop invariant"),
+            },
+            _ = stagger.fuse() => {
+                let addr = remaining.next().expect("checked non-empty above");
+                attempts.push(async move { rt.connect(&addr).await });
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        IoError::new(
+            ErrorKind::Other,
+            "all happy-eyeballs connection attempts failed",
+        )
+    }))
+}
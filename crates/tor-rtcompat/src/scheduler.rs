@@ -39,6 +39,20 @@ pub struct TaskSchedule<R: SleepProvider> {
     /// This is used to avoid having to create a `SleepFuture` with zero duration,
     /// which is potentially a bit wasteful.
     instant_fire: bool,
+    /// If set, this schedule fires repeatedly, every `interval`; see [`TaskSchedule::fire_every`].
+    interval: Option<Duration>,
+    /// The deadline the most recent recurring firing was scheduled for.
+    ///
+    /// Each subsequent deadline is computed by adding `interval` to this
+    /// one, rather than to the time we happen to be polled at, so a
+    /// recurring schedule doesn't drift over time.
+    next_deadline: Option<Instant>,
+    /// If set, firings requested via [`TaskHandle::fire`] or
+    /// [`TaskHandle::fire_at`] are coalesced to happen no more than once
+    /// every `min_interval`; see [`TaskSchedule::throttle`].
+    min_interval: Option<Duration>,
+    /// The last time this schedule actually fired, used to enforce `min_interval`.
+    last_fired: Option<Instant>,
 }
 
 /// A handle used to control a [`TaskSchedule`].
@@ -59,6 +73,10 @@ impl<R: SleepProvider> TaskSchedule<R> {
                 rt,
                 // Start off ready.
                 instant_fire: true,
+                interval: None,
+                next_deadline: None,
+                min_interval: None,
+                last_fired: None,
             },
             TaskHandle { tx },
         )
@@ -75,6 +93,54 @@ impl<R: SleepProvider> TaskSchedule<R> {
         self.instant_fire = true;
         self.sleep = None;
     }
+
+    /// Arrange to fire repeatedly, every `interval`, starting `interval`
+    /// from now.
+    ///
+    /// Each firing's deadline is computed by adding `interval` to the
+    /// *previous* deadline, rather than to the time we happen to be polled
+    /// at, so the schedule doesn't accumulate drift. If the schedule falls
+    /// more than one `interval` behind (for example, because the task
+    /// wasn't polled for a while), the deadline skips forward by whole
+    /// multiples of `interval` rather than queuing up a backlog of
+    /// catch-up firings.
+    pub fn fire_every(&mut self, interval: Duration) {
+        self.interval = Some(interval);
+        self.next_deadline = Some(self.rt.now() + interval);
+        self.instant_fire = false;
+        self.sleep = Some(Box::pin(self.rt.sleep(interval)));
+    }
+
+    /// Coalesce firings requested via [`TaskHandle::fire`] or
+    /// [`TaskHandle::fire_at`] so that they happen no more often than once
+    /// every `min_interval`.
+    ///
+    /// If such a firing is requested sooner than `min_interval` after the
+    /// last one, it's delayed until `min_interval` has elapsed since the
+    /// last firing, rather than happening immediately; several requests
+    /// like that in a row collapse into a single firing at that deadline.
+    ///
+    /// This doesn't affect [`TaskSchedule::fire`], [`TaskSchedule::fire_in`],
+    /// or [`TaskSchedule::fire_every`], which are driven by this same task
+    /// rather than by a remote handle.
+    pub fn throttle(&mut self, min_interval: Duration) {
+        self.min_interval = Some(min_interval);
+    }
+}
+
+/// Return the next deadline after `prev_deadline`, stepping forward by whole
+/// multiples of `interval` so that a schedule which has fallen behind `now`
+/// catches up to a single deadline rather than firing once per missed
+/// interval.
+fn next_recurring_deadline(prev_deadline: Instant, interval: Duration, now: Instant) -> Instant {
+    if prev_deadline > now || interval.is_zero() {
+        return prev_deadline + interval;
+    }
+    let overdue = now.saturating_duration_since(prev_deadline);
+    // Skip forward by however many whole intervals we're overdue, plus one
+    // to land back in the future.
+    let skips = (overdue.as_nanos() / interval.as_nanos()) as u32 + 1;
+    prev_deadline + interval * skips
 }
 
 impl TaskHandle {
@@ -107,14 +173,12 @@ impl<R: SleepProvider> TaskScheduleP<'_, R> {
     fn handle_command(&mut self, cmd: SchedulerCommand) {
         match cmd {
             SchedulerCommand::Fire => {
-                *self.instant_fire = true;
-                *self.sleep = None;
+                let now = self.rt.now();
+                self.arm_at(now, now);
             }
             SchedulerCommand::FireAt(instant) => {
                 let now = self.rt.now();
-                let dur = instant.saturating_duration_since(now);
-                *self.instant_fire = false;
-                *self.sleep = Some(Box::pin(self.rt.sleep(dur)));
+                self.arm_at(instant, now);
             }
             SchedulerCommand::Cancel => {
                 *self.instant_fire = false;
@@ -122,6 +186,39 @@ impl<R: SleepProvider> TaskScheduleP<'_, R> {
             }
         }
     }
+
+    /// Arm the schedule to fire at `requested`, clamping it to respect
+    /// `min_interval` (see [`TaskSchedule::throttle`]) if that's set.
+    fn arm_at(&mut self, requested: Instant, now: Instant) {
+        let earliest_allowed = match (*self.min_interval, *self.last_fired) {
+            (Some(min_interval), Some(last_fired)) => last_fired + min_interval,
+            _ => now,
+        };
+        let target = requested.max(earliest_allowed);
+        if target <= now {
+            *self.instant_fire = true;
+            *self.sleep = None;
+        } else {
+            *self.instant_fire = false;
+            *self.sleep = Some(Box::pin(self.rt.sleep(target.saturating_duration_since(now))));
+        }
+    }
+
+    /// Record that the schedule is about to yield a firing, updating
+    /// bookkeeping used by recurring mode and throttling mode.
+    fn note_fired(&mut self) {
+        let now = self.rt.now();
+        *self.last_fired = Some(now);
+        if let Some(interval) = *self.interval {
+            let prev_deadline = (*self.next_deadline).unwrap_or(now);
+            let next_deadline = next_recurring_deadline(prev_deadline, interval, now);
+            *self.next_deadline = Some(next_deadline);
+            *self.instant_fire = false;
+            *self.sleep = Some(Box::pin(
+                self.rt.sleep(next_deadline.saturating_duration_since(now)),
+            ));
+        }
+    }
 }
 
 impl<R: SleepProvider> Stream for TaskSchedule<R> {
@@ -140,6 +237,7 @@ impl<R: SleepProvider> Stream for TaskSchedule<R> {
         }
         if *this.instant_fire {
             *this.instant_fire = false;
+            this.note_fired();
             return Poll::Ready(Some(()));
         }
         if this
@@ -149,6 +247,7 @@ impl<R: SleepProvider> Stream for TaskSchedule<R> {
             .unwrap_or(false)
         {
             *this.sleep = None;
+            this.note_fired();
             return Poll::Ready(Some(()));
         }
         Poll::Pending
@@ -283,4 +382,68 @@ mod test {
             assert!(sch.next().now_or_never().is_none());
         });
     }
+
+    #[test]
+    fn it_fires_repeatedly() {
+        test_with_all_runtimes!(|rt| async move {
+            let (mut sch, _hdl) = TaskSchedule::new(rt.clone());
+            assert!(sch.next().now_or_never().is_some());
+
+            sch.fire_every(Duration::from_millis(50));
+
+            assert!(sch.next().now_or_never().is_none());
+            assert!(sch.next().await.is_some());
+            assert!(sch.next().now_or_never().is_none());
+            assert!(sch.next().await.is_some());
+            assert!(sch.next().now_or_never().is_none());
+        });
+    }
+
+    #[test]
+    fn it_catches_up_without_a_backlog() {
+        test_with_all_runtimes!(|rt| async move {
+            let (mut sch, _hdl) = TaskSchedule::new(rt.clone());
+            assert!(sch.next().now_or_never().is_some());
+
+            sch.fire_every(Duration::from_millis(50));
+
+            // Let several intervals' worth of time pass without polling; a
+            // drifting/backlogged schedule would have queued up multiple
+            // firings, but `fire_every` should only ever yield one at a time.
+            rt.sleep(Duration::from_millis(220)).await;
+
+            assert!(sch.next().now_or_never().is_some());
+            assert!(sch.next().now_or_never().is_none());
+        });
+    }
+
+    #[test]
+    fn it_throttles_bursts_of_firings() {
+        test_with_all_runtimes!(|rt| async move {
+            let (mut sch, hdl) = TaskSchedule::new(rt.clone());
+            assert!(sch.next().now_or_never().is_some());
+
+            sch.throttle(Duration::from_millis(100));
+
+            // A burst of requests in quick succession should coalesce into a
+            // single firing, deferred until `min_interval` has elapsed.
+            assert!(hdl.fire());
+            assert!(hdl.fire());
+            assert!(hdl.fire());
+
+            assert!(sch.next().now_or_never().is_none());
+            assert!(sch.next().await.is_some());
+            assert!(sch.next().now_or_never().is_none());
+
+            // A firing requested immediately after the last one should again
+            // be deferred, rather than happening right away.
+            assert!(hdl.fire());
+            assert!(sch.next().now_or_never().is_none());
+
+            rt.sleep(Duration::from_millis(50)).await;
+            assert!(sch.next().now_or_never().is_none());
+
+            assert!(sch.next().await.is_some());
+        });
+    }
 }
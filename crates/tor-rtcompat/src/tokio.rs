@@ -1,8 +1,9 @@
 //! Entry points for use with Tokio runtimes.
+#[cfg(feature = "native-tls")]
 use crate::impls::native_tls::NativeTlsProvider;
 use crate::impls::tokio::TokioRuntimeHandle as Handle;
 
-use crate::{CompoundRuntime, SpawnBlocking};
+use crate::{CompoundRuntime, RealCoarseTimeProvider, SpawnBlocking};
 use std::io::{Error as IoError, ErrorKind, Result as IoResult};
 
 #[cfg(feature = "rustls")]
@@ -17,13 +18,16 @@ use crate::impls::tokio::net::TcpStream;
 /// implementations for Tokio's time, net, and io facilities, but we have
 /// no good way to check that when creating this object.
 #[derive(Clone)]
+#[cfg(feature = "native-tls")]
 pub struct TokioNativeTlsRuntime {
     /// The actual [`CompoundRuntime`] that implements this.
     inner: HandleInner,
 }
 
 /// Implementation type for a TokioRuntimeHandle.
-type HandleInner = CompoundRuntime<Handle, Handle, Handle, NativeTlsProvider<TcpStream>>;
+#[cfg(feature = "native-tls")]
+type HandleInner =
+    CompoundRuntime<Handle, Handle, Handle, NativeTlsProvider<TcpStream>, Handle, Handle, RealCoarseTimeProvider>;
 
 /// A [`Runtime`] built around a Handle to a tokio runtime, and `rustls`.
 #[derive(Clone)]
@@ -35,8 +39,28 @@ pub struct TokioRustlsRuntime {
 
 /// Implementation for a TokioRuntimeRustlsHandle
 #[cfg(feature = "rustls")]
-type RustlsHandleInner = CompoundRuntime<Handle, Handle, Handle, RustlsProvider<TcpStream>>;
+type RustlsHandleInner =
+    CompoundRuntime<Handle, Handle, Handle, RustlsProvider<TcpStream>, Handle, Handle, RealCoarseTimeProvider>;
 
+/// The runtime that we prefer to use, out of all the runtimes compiled into
+/// this crate.
+///
+/// If `native-tls` is enabled, this is [`TokioNativeTlsRuntime`]; otherwise
+/// (so long as `rustls` is enabled) it's [`TokioRustlsRuntime`]. This lets a
+/// caller who doesn't care which TLS backend is in use just write
+/// `PreferredRuntime::create()` (or `current()`) without any `cfg` juggling
+/// of their own.
+#[cfg(feature = "native-tls")]
+pub type PreferredRuntime = TokioNativeTlsRuntime;
+
+/// The runtime that we prefer to use, out of all the runtimes compiled into
+/// this crate.
+///
+/// See the other definition of `PreferredRuntime` for more information.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub type PreferredRuntime = TokioRustlsRuntime;
+
+#[cfg(feature = "native-tls")]
 crate::opaque::implement_opaque_runtime! {
     TokioNativeTlsRuntime { inner : HandleInner }
 }
@@ -46,11 +70,22 @@ crate::opaque::implement_opaque_runtime! {
     TokioRustlsRuntime { inner : RustlsHandleInner }
 }
 
+#[cfg(feature = "native-tls")]
 impl From<tokio_crate::runtime::Handle> for TokioNativeTlsRuntime {
     fn from(h: tokio_crate::runtime::Handle) -> Self {
         let h = Handle::new(h);
+        let coarse = RealCoarseTimeProvider::default();
+        let _ = coarse.launch_refresh_task(&h);
         TokioNativeTlsRuntime {
-            inner: CompoundRuntime::new(h.clone(), h.clone(), h, NativeTlsProvider::default()),
+            inner: CompoundRuntime::new(
+                h.clone(),
+                h.clone(),
+                h.clone(),
+                NativeTlsProvider::default(),
+                h.clone(),
+                h,
+                coarse,
+            ),
         }
     }
 }
@@ -59,12 +94,23 @@ impl From<tokio_crate::runtime::Handle> for TokioNativeTlsRuntime {
 impl From<tokio_crate::runtime::Handle> for TokioRustlsRuntime {
     fn from(h: tokio_crate::runtime::Handle) -> Self {
         let h = Handle::new(h);
+        let coarse = RealCoarseTimeProvider::default();
+        let _ = coarse.launch_refresh_task(&h);
         TokioRustlsRuntime {
-            inner: CompoundRuntime::new(h.clone(), h.clone(), h, RustlsProvider::default()),
+            inner: CompoundRuntime::new(
+                h.clone(),
+                h.clone(),
+                h.clone(),
+                RustlsProvider::default(),
+                h.clone(),
+                h,
+                coarse,
+            ),
         }
     }
 }
 
+#[cfg(feature = "native-tls")]
 impl TokioNativeTlsRuntime {
     /// Create a new [`TokioNativeTlsRuntime`].
     ///
@@ -74,8 +120,20 @@ impl TokioNativeTlsRuntime {
     /// If you want to use a currently running runtime instead, call
     /// [`TokioNativeTlsRuntime::current()`].
     pub fn create() -> IoResult<Self> {
-        crate::impls::tokio::create_runtime().map(|r| TokioNativeTlsRuntime {
-            inner: CompoundRuntime::new(r.clone(), r.clone(), r, NativeTlsProvider::default()),
+        crate::impls::tokio::create_runtime().map(|r| {
+            let coarse = RealCoarseTimeProvider::default();
+            let _ = coarse.launch_refresh_task(&r);
+            TokioNativeTlsRuntime {
+                inner: CompoundRuntime::new(
+                    r.clone(),
+                    r.clone(),
+                    r.clone(),
+                    NativeTlsProvider::default(),
+                    r.clone(),
+                    r,
+                    coarse,
+                ),
+            }
         })
     }
 
@@ -107,8 +165,20 @@ impl TokioRustlsRuntime {
     /// If you want to use a currently running runtime instead, call
     /// [`TokioRustlsRuntime::current()`].
     pub fn create() -> IoResult<Self> {
-        crate::impls::tokio::create_runtime().map(|r| TokioRustlsRuntime {
-            inner: CompoundRuntime::new(r.clone(), r.clone(), r, RustlsProvider::default()),
+        crate::impls::tokio::create_runtime().map(|r| {
+            let coarse = RealCoarseTimeProvider::default();
+            let _ = coarse.launch_refresh_task(&r);
+            TokioRustlsRuntime {
+                inner: CompoundRuntime::new(
+                    r.clone(),
+                    r.clone(),
+                    r.clone(),
+                    RustlsProvider::default(),
+                    r.clone(),
+                    r,
+                    coarse,
+                ),
+            }
         })
     }
 
@@ -135,11 +205,30 @@ fn current_handle() -> std::io::Result<tokio_crate::runtime::Handle> {
     tokio_crate::runtime::Handle::try_current().map_err(|e| IoError::new(ErrorKind::Other, e))
 }
 
+/// Return a new [`PreferredRuntime`] wrapping a freshly created Tokio
+/// runtime, so callers don't need to pick a TLS backend themselves.
+///
+/// See [`PreferredRuntime`] for more information.
+pub fn create() -> IoResult<PreferredRuntime> {
+    PreferredRuntime::create()
+}
+
+/// Return a [`PreferredRuntime`] wrapping the currently running Tokio
+/// runtime, so callers don't need to pick a TLS backend themselves.
+///
+/// See [`PreferredRuntime`] for more information, including the usage note
+/// in [`TokioNativeTlsRuntime::current()`] about when it's appropriate to
+/// call this function.
+pub fn current() -> IoResult<PreferredRuntime> {
+    PreferredRuntime::current()
+}
+
 /// Run a test function using a freshly created tokio runtime.
 ///
 /// # Panics
 ///
 /// Panics if we can't create a tokio runtime.
+#[cfg(feature = "native-tls")]
 pub fn test_with_runtime<P, F, O>(func: P) -> O
 where
     P: FnOnce(TokioNativeTlsRuntime) -> F,
@@ -148,3 +237,18 @@ where
     let runtime = TokioNativeTlsRuntime::create().expect("Failed to create a tokio runtime");
     runtime.clone().block_on(func(runtime))
 }
+
+/// Run a test function using a freshly created tokio runtime.
+///
+/// # Panics
+///
+/// Panics if we can't create a tokio runtime.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub fn test_with_runtime<P, F, O>(func: P) -> O
+where
+    P: FnOnce(TokioRustlsRuntime) -> F,
+    F: futures::Future<Output = O>,
+{
+    let runtime = TokioRustlsRuntime::create().expect("Failed to create a tokio runtime");
+    runtime.clone().block_on(func(runtime))
+}
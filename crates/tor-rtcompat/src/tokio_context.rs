@@ -0,0 +1,141 @@
+//! A runtime combinator for embedders who want to spawn tasks on their own
+//! executor while still using Tokio's `net` and `time` facilities.
+//!
+//! Arti's TCP and timer providers need a running Tokio reactor underneath
+//! them (they're built on Tokio's own `net`/`time` types), but some
+//! embedders want their tasks to actually run on a different executor -- a
+//! plain thread pool, `async-std`, or anything else that implements
+//! [`Spawn`] and [`BlockOn`]. [`TokioContextRuntime`] bridges the two: it
+//! spawns onto the caller's executor, but makes sure the stored Tokio
+//! [`Handle`](tokio_crate::runtime::Handle) is entered around every poll of
+//! a spawned future, so that Tokio's reactor-lookup machinery finds a
+//! reactor no matter which thread actually does the polling.
+
+#[cfg(feature = "native-tls")]
+use crate::impls::native_tls::NativeTlsProvider;
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+use crate::impls::rustls::RustlsProvider;
+use crate::impls::tokio::net::TcpStream;
+use crate::impls::tokio::TokioRuntimeHandle as Handle;
+use crate::traits::*;
+use crate::{CompoundRuntime, RealCoarseTimeProvider};
+
+use futures::{
+    future::FutureObj,
+    task::{Spawn, SpawnError},
+};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The TLS provider our [`TokioContextRuntime`] uses for its outer HTTPS/TLS
+/// layer, chosen the same way the plain Tokio entry points choose theirs.
+#[cfg(feature = "native-tls")]
+type ContextTlsProvider = NativeTlsProvider<TcpStream>;
+/// See the other definition of `ContextTlsProvider`.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+type ContextTlsProvider = RustlsProvider<TcpStream>;
+
+/// A [`Spawn`] implementation that wraps every future it spawns so that a
+/// stored Tokio [`Handle`](tokio_crate::runtime::Handle) gets entered around
+/// each poll.
+#[derive(Clone)]
+struct EnteringSpawn<S> {
+    /// The executor we actually spawn onto.
+    spawner: S,
+    /// The Tokio handle to enter before polling each spawned future.
+    handle: tokio_crate::runtime::Handle,
+}
+
+impl<S: Spawn> Spawn for EnteringSpawn<S> {
+    fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        let entered = EnteredFuture {
+            handle: self.handle.clone(),
+            inner: future,
+        };
+        self.spawner.spawn_obj(FutureObj::new(Box::pin(entered)))
+    }
+}
+
+impl<S: BlockOn> BlockOn for EnteringSpawn<S> {
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        let _guard = self.handle.enter();
+        self.spawner.block_on(future)
+    }
+}
+
+/// A future that enters a Tokio [`Handle`](tokio_crate::runtime::Handle)
+/// around every poll of an inner future, analogous to `tokio-util`'s
+/// `TokioContext` adapter.
+struct EnteredFuture<F> {
+    /// The handle to enter before each poll.
+    handle: tokio_crate::runtime::Handle,
+    /// The wrapped future.
+    inner: F,
+}
+
+impl<F: std::future::Future + Unpin> std::future::Future for EnteredFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let _guard = this.handle.enter();
+        Pin::new(&mut this.inner).poll(cx)
+    }
+}
+
+/// Implementation type for a [`TokioContextRuntime`].
+type ContextInner<S> = CompoundRuntime<
+    EnteringSpawn<S>,
+    Handle,
+    Handle,
+    ContextTlsProvider,
+    Handle,
+    Handle,
+    RealCoarseTimeProvider,
+>;
+
+/// A [`Runtime`] that spawns onto a caller-provided executor `S`, while
+/// using Tokio's own `net`, `time`, and `udp` facilities (by way of a
+/// caller-provided [`Handle`](tokio_crate::runtime::Handle)) for everything
+/// else.
+///
+/// Build one with [`TokioContextRuntime::new`].
+#[derive(Clone)]
+pub struct TokioContextRuntime<S> {
+    /// The actual [`CompoundRuntime`] that implements this.
+    inner: ContextInner<S>,
+}
+
+crate::opaque::implement_opaque_runtime! {
+    TokioContextRuntime<S> { inner : ContextInner<S> }
+}
+
+impl<S> TokioContextRuntime<S>
+where
+    S: Spawn + BlockOn + Clone + Send + Sync + 'static,
+{
+    /// Create a new [`TokioContextRuntime`] that spawns tasks on
+    /// `executor_runtime`, while entering `tokio_handle` around every poll
+    /// so that Arti's Tokio-backed TCP streams and timers still find a
+    /// reactor.
+    pub fn new(executor_runtime: S, tokio_handle: tokio_crate::runtime::Handle) -> Self {
+        let net = Handle::new(tokio_handle.clone());
+        let coarse = RealCoarseTimeProvider::default();
+        let _ = coarse.launch_refresh_task(&net);
+        let spawn = EnteringSpawn {
+            spawner: executor_runtime,
+            handle: tokio_handle,
+        };
+        TokioContextRuntime {
+            inner: CompoundRuntime::new(
+                spawn,
+                net.clone(),
+                net.clone(),
+                ContextTlsProvider::default(),
+                net.clone(),
+                net,
+                coarse,
+            ),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Simulated network impairment: latency, jitter, bandwidth caps, and loss.
+//!
+//! [`LinkParams`] describes how "bad" a simulated link is; [`ImpairedStream`]
+//! applies those parameters to an underlying mock stream by scheduling
+//! delivery of written bytes through a [`SleepProvider`], so that the whole
+//! simulation stays deterministic when paired with mock time.
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::SpawnExt;
+use pin_project::pin_project;
+use std::io::Result as IoResult;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tor_rtcompat::{Runtime, SleepProvider};
+
+/// Parameters describing a simulated, possibly-impaired network link.
+///
+/// Applied per-direction: each end of a mock connection impairs its own
+/// writes, and both ends use the `LinkParams` registered for the listening
+/// address.
+#[derive(Clone, Debug)]
+pub struct LinkParams {
+    /// Fixed propagation delay applied to every write.
+    pub latency: Duration,
+    /// Additional, randomized delay added on top of `latency`, uniformly
+    /// distributed between zero and this value.
+    pub jitter: Duration,
+    /// If set, caps throughput: writes are spread out so that no more than
+    /// this many bytes are "delivered" per second.
+    pub rate_bytes_per_sec: Option<u64>,
+    /// Probability (0.0 ..= 1.0) that a given write is dropped outright.
+    pub loss: f64,
+}
+
+impl Default for LinkParams {
+    fn default() -> Self {
+        LinkParams {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            rate_bytes_per_sec: None,
+            loss: 0.0,
+        }
+    }
+}
+
+impl LinkParams {
+    /// Return true if these parameters don't actually impair anything.
+    fn is_trivial(&self) -> bool {
+        self.latency.is_zero()
+            && self.jitter.is_zero()
+            && self.rate_bytes_per_sec.is_none()
+            && self.loss <= 0.0
+    }
+}
+
+/// Tracks, per direction, the `Instant` at which the last scheduled byte
+/// will have finished "arriving"; used to serialize bandwidth-capped
+/// delivery so that back-to-back writes queue up instead of overlapping.
+type LastRelease = Arc<Mutex<Instant>>;
+
+/// A stream wrapper that delays, throttles, and randomly drops writes made
+/// through it, according to a [`LinkParams`].
+///
+/// Reads are passed through to the inner stream unchanged: impairment is
+/// applied on the writing side, which is what delays the bytes becoming
+/// visible to whatever is reading the other end of the mock connection.
+#[pin_project]
+pub struct ImpairedStream<R: Runtime, S> {
+    /// The runtime used to schedule delayed delivery and to spawn the
+    /// background delivery tasks.
+    runtime: R,
+    /// The underlying, unimpaired stream.
+    #[pin]
+    inner: Arc<futures::lock::Mutex<S>>,
+    /// The parameters describing how this link should misbehave.
+    params: LinkParams,
+    /// When the last scheduled byte of this direction will finish arriving.
+    last_release: LastRelease,
+}
+
+impl<R: Runtime, S> ImpairedStream<R, S> {
+    /// Wrap `inner` so that writes through it are subject to `params`.
+    pub fn new(runtime: R, inner: S, params: LinkParams) -> Self {
+        let now = runtime.now();
+        ImpairedStream {
+            runtime,
+            inner: Arc::new(futures::lock::Mutex::new(inner)),
+            params,
+            last_release: Arc::new(Mutex::new(now)),
+        }
+    }
+}
+
+impl<R: Runtime, S: AsyncRead + Unpin> AsyncRead for ImpairedStream<R, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        let this = self.project();
+        match this.inner.try_lock() {
+            Some(mut guard) => Pin::new(&mut *guard).poll_read(cx, buf),
+            // Someone else (the delayed-delivery task) holds the lock; come back later.
+            None => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R, S> AsyncWrite for ImpairedStream<R, S>
+where
+    R: Runtime,
+    S: AsyncWrite + Unpin + Send + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        let this = self.project();
+        let len = buf.len();
+
+        if this.params.is_trivial() {
+            let inner = Arc::clone(this.inner);
+            let payload = buf.to_vec();
+            let _ = this.runtime.spawn(async move {
+                let mut guard = inner.lock().await;
+                let _ = futures::AsyncWriteExt::write_all(&mut *guard, &payload).await;
+            });
+            return Poll::Ready(Ok(len));
+        }
+
+        if this.params.loss > 0.0 && rand::random::<f64>() < this.params.loss {
+            // Dropped on the floor: the caller still sees it as "sent", just
+            // as a real, lossy link would.
+            return Poll::Ready(Ok(len));
+        }
+
+        let jitter = if this.params.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            this.params.jitter.mul_f64(rand::random::<f64>())
+        };
+        let transmit_time = match this.params.rate_bytes_per_sec {
+            Some(rate) if rate > 0 => Duration::from_secs_f64(len as f64 / rate as f64),
+            _ => Duration::ZERO,
+        };
+
+        let now = this.runtime.now();
+        let mut last_release = this.last_release.lock().expect("poisoned lock");
+        let earliest_start = std::cmp::max(now, *last_release);
+        let release_at = earliest_start + this.params.latency + jitter + transmit_time;
+        *last_release = release_at;
+        drop(last_release);
+
+        let delay = release_at.saturating_duration_since(now);
+        let inner = Arc::clone(this.inner);
+        let payload = buf.to_vec();
+        let runtime = this.runtime.clone();
+        let _ = this.runtime.spawn(async move {
+            if !delay.is_zero() {
+                runtime.sleep(delay).await;
+            }
+            let mut guard = inner.lock().await;
+            let _ = futures::AsyncWriteExt::write_all(&mut *guard, &payload).await;
+        });
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.project();
+        match this.inner.try_lock() {
+            Some(mut guard) => Pin::new(&mut *guard).poll_flush(cx),
+            None => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.project();
+        match this.inner.try_lock() {
+            Some(mut guard) => Pin::new(&mut *guard).poll_close(cx),
+            None => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! In-memory duplex byte streams used to connect mock TCP (and TLS-over-mock-TCP) peers.
+
+use futures::channel::mpsc;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::StreamExt;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// One end of an in-memory, duplex byte stream.
+///
+/// A `LocalStream` behaves like a TCP connection whose other end is another
+/// `LocalStream` living in the same process: bytes written to one side can be
+/// read from the other, in order, with no real network involved.  This is
+/// the concrete stream type that [`crate::net::MockNetProvider`] hands out
+/// from `connect()` and `listen()`.
+#[derive(Debug)]
+pub struct LocalStream {
+    /// Sender for bytes we write; the other endpoint reads from the matching receiver.
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    /// Receiver for bytes written by the other endpoint.
+    rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    /// Leftover bytes from the front of the queue that haven't been consumed yet.
+    pending: Vec<u8>,
+    /// Read position within `pending`.
+    pending_pos: usize,
+    /// Set by [`ResetHandle::reset`] to simulate a peer sending a TCP RST:
+    /// once set, both halves report `BrokenPipe` instead of a graceful EOF.
+    reset: Arc<AtomicBool>,
+}
+
+/// A handle that can sever a [`LocalStream`] out from under its owner,
+/// as if its peer had sent a TCP RST.
+///
+/// Obtained via [`LocalStream::reset_handle`] before the stream is handed
+/// off to a caller, and used by [`crate::net::MockNetProvider::reset`].
+#[derive(Clone, Debug)]
+pub(crate) struct ResetHandle {
+    /// The channel to close, so that pending and future writes fail immediately.
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    /// Shared flag, checked by the stream to turn a graceful EOF into `BrokenPipe`.
+    reset: Arc<AtomicBool>,
+}
+
+impl ResetHandle {
+    /// Tear the stream down: future reads and writes on either end report `BrokenPipe`.
+    pub(crate) fn reset(&self) {
+        self.reset.store(true, Ordering::SeqCst);
+        self.tx.close_channel();
+    }
+}
+
+impl LocalStream {
+    /// Return a [`ResetHandle`] that can later sever this connection.
+    pub(crate) fn reset_handle(&self) -> ResetHandle {
+        ResetHandle {
+            tx: self.tx.clone(),
+            reset: Arc::clone(&self.reset),
+        }
+    }
+
+    /// Create a connected pair of `LocalStream`s, as if one had `connect()`ed to the other.
+    pub fn pair() -> (LocalStream, LocalStream) {
+        let (tx_ab, rx_ab) = mpsc::unbounded();
+        let (tx_ba, rx_ba) = mpsc::unbounded();
+        let reset = Arc::new(AtomicBool::new(false));
+        (
+            LocalStream {
+                tx: tx_ab,
+                rx: rx_ba,
+                pending: Vec::new(),
+                pending_pos: 0,
+                reset: Arc::clone(&reset),
+            },
+            LocalStream {
+                tx: tx_ba,
+                rx: rx_ab,
+                pending: Vec::new(),
+                pending_pos: 0,
+                reset,
+            },
+        )
+    }
+
+    /// Return an error if this stream has been reset, else `None`.
+    fn check_reset(&self) -> Option<IoError> {
+        self.reset
+            .load(Ordering::SeqCst)
+            .then(|| IoError::new(ErrorKind::BrokenPipe, "connection reset by peer"))
+    }
+}
+
+impl AsyncRead for LocalStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        if let Some(err) = self.check_reset() {
+            return Poll::Ready(Err(err));
+        }
+        loop {
+            if self.pending_pos < self.pending.len() {
+                let n = std::cmp::min(buf.len(), self.pending.len() - self.pending_pos);
+                buf[..n].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + n]);
+                self.pending_pos += n;
+                return Poll::Ready(Ok(n));
+            }
+            match self.rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(chunk)) => {
+                    self.pending = chunk;
+                    self.pending_pos = 0;
+                }
+                Poll::Ready(None) => {
+                    return Poll::Ready(match self.check_reset() {
+                        Some(err) => Err(err),
+                        None => Ok(0),
+                    })
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for LocalStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        if let Some(err) = self.check_reset() {
+            return Poll::Ready(Err(err));
+        }
+        match self.tx.unbounded_send(buf.to_vec()) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(IoError::new(ErrorKind::BrokenPipe, "peer closed"))),
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        self.tx.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,470 @@
+//! A mock network, for impersonating the internet in tests.
+//!
+//! [`MockNetProvider`] keeps an in-memory table mapping [`SocketAddr`]s to
+//! listening sockets.  `connect()` and `bind()`/`listen()` calls consult that
+//! table instead of touching any real network, so that code under test can
+//! run fully offline and deterministically.
+
+use crate::io::{LocalStream, ResetHandle};
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::lock::Mutex as AsyncMutex;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tor_rtcompat::{TcpListener, TcpProvider, TlsProvider, UdpProvider, UdpSocket};
+
+/// A behavior to inject in place of a normal connection attempt.
+#[derive(Clone, Copy, Debug)]
+enum FaultKind {
+    /// `connect()` should fail immediately with the given [`ErrorKind`].
+    Refuse(ErrorKind),
+    /// `connect()` should return a future that never resolves, to exercise timeout logic.
+    Blackhole,
+}
+
+/// A fault registered on an address, and how many more connection attempts it applies to.
+#[derive(Clone, Copy, Debug)]
+struct Fault {
+    /// Which behavior to inject.
+    kind: FaultKind,
+    /// Remaining connection attempts this fault should apply to; `None` means "forever".
+    remaining: Option<u32>,
+}
+
+/// Shared, internal state for a [`MockNetProvider`].
+#[derive(Default, Debug)]
+struct Inner {
+    /// Listening TCP sockets, by bound address.
+    tcp_listeners: HashMap<SocketAddr, mpsc::UnboundedSender<(SocketAddr, LocalStream)>>,
+    /// Bound UDP sockets, by bound address.
+    udp_sockets: HashMap<SocketAddr, mpsc::UnboundedSender<(SocketAddr, Vec<u8>)>>,
+    /// Link impairment parameters, by the address they apply to.
+    ///
+    /// Looked up for both the dialed address on `connect()` and the bound
+    /// address on `listen()`; both are the listening address, so the
+    /// parameters set for it govern both directions of the link.
+    link_params: HashMap<SocketAddr, crate::impair::LinkParams>,
+    /// Faults to inject on `connect()` attempts to a given address.
+    faults: HashMap<SocketAddr, Fault>,
+    /// Reset handles for connections currently established to a given address, so that
+    /// `reset()` can sever them after the fact.
+    active_connections: HashMap<SocketAddr, Vec<ResetHandle>>,
+    /// Configuration shared by every [`MockTlsConnector`] this provider hands out.
+    tls_config: Arc<Mutex<MockTlsConfig>>,
+}
+
+/// A view of the network, as implemented entirely in memory.
+///
+/// Connections made through this provider never touch a real socket: every
+/// `connect()` is matched against a `listen()` or `bind()` registered
+/// earlier on a clone of the same `MockNetProvider`.
+#[derive(Clone, Debug, Default)]
+pub struct MockNetProvider {
+    /// The actual state of the mock network.
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MockNetProvider {
+    /// Create a new, empty `MockNetProvider`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the [`LinkParams`](crate::impair::LinkParams) that should govern traffic to or
+    /// from `addr`.
+    ///
+    /// These parameters apply to every connection dialed to `addr` via
+    /// `connect()`, and to every connection accepted by a listener bound to
+    /// `addr`.  Call this before the corresponding `connect`/`listen`/`bind`
+    /// so the parameters are in place when the connection is created.
+    pub fn set_link_params(&self, addr: SocketAddr, params: crate::impair::LinkParams) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner.link_params.insert(addr, params);
+    }
+
+    /// Return the configured [`LinkParams`](crate::impair::LinkParams) for `addr`, or the
+    /// default (unimpaired) parameters if none were set.
+    pub(crate) fn link_params_for(&self, addr: &SocketAddr) -> crate::impair::LinkParams {
+        let inner = self.inner.lock().expect("poisoned lock");
+        inner.link_params.get(addr).cloned().unwrap_or_default()
+    }
+
+    /// Make every future `connect()` to `addr` fail immediately with `ConnectionRefused`.
+    pub fn refuse(&self, addr: SocketAddr) {
+        self.refuse_with(addr, ErrorKind::ConnectionRefused);
+    }
+
+    /// Like [`refuse`](Self::refuse), but only for the next `n` connection attempts.
+    pub fn refuse_next_n(&self, addr: SocketAddr, n: u32) {
+        self.refuse_next_n_with(addr, ErrorKind::ConnectionRefused, n);
+    }
+
+    /// Make every future `connect()` to `addr` fail immediately with `kind`.
+    pub fn refuse_with(&self, addr: SocketAddr, kind: ErrorKind) {
+        self.set_fault(addr, FaultKind::Refuse(kind), None);
+    }
+
+    /// Like [`refuse_with`](Self::refuse_with), but only for the next `n` connection attempts.
+    pub fn refuse_next_n_with(&self, addr: SocketAddr, kind: ErrorKind, n: u32) {
+        self.set_fault(addr, FaultKind::Refuse(kind), Some(n));
+    }
+
+    /// Make every future `connect()` to `addr` return a future that never resolves,
+    /// as if `addr` were a well-known black hole on the internet.
+    pub fn blackhole(&self, addr: SocketAddr) {
+        self.set_fault(addr, FaultKind::Blackhole, None);
+    }
+
+    /// Like [`blackhole`](Self::blackhole), but only for the next `n` connection attempts.
+    pub fn blackhole_next_n(&self, addr: SocketAddr, n: u32) {
+        self.set_fault(addr, FaultKind::Blackhole, Some(n));
+    }
+
+    /// Stop injecting any fault on `addr`.
+    pub fn clear_fault(&self, addr: &SocketAddr) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner.faults.remove(addr);
+    }
+
+    /// Register `kind` as the fault to inject on `addr`, for `remaining` attempts
+    /// (or forever, if `None`).
+    fn set_fault(&self, addr: SocketAddr, kind: FaultKind, remaining: Option<u32>) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner.faults.insert(addr, Fault { kind, remaining });
+    }
+
+    /// Consult and consume one use of whatever fault is registered on `addr`, if any.
+    fn take_fault(&self, addr: &SocketAddr) -> Option<FaultKind> {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        let fault = *inner.faults.get(addr)?;
+        match fault.remaining {
+            Some(0) => {
+                inner.faults.remove(addr);
+                return None;
+            }
+            Some(1) => {
+                inner.faults.remove(addr);
+            }
+            Some(n) => {
+                inner
+                    .faults
+                    .insert(*addr, Fault { remaining: Some(n - 1), ..fault });
+            }
+            None => {}
+        }
+        Some(fault.kind)
+    }
+
+    /// Tear an existing connection to `addr` down mid-stream, as if the peer had reset it.
+    ///
+    /// Affects every connection to `addr` that's currently live; it has no effect on
+    /// connections made afterwards.
+    pub fn reset(&self, addr: &SocketAddr) {
+        let handles = {
+            let mut inner = self.inner.lock().expect("poisoned lock");
+            inner.active_connections.remove(addr).unwrap_or_default()
+        };
+        for handle in handles {
+            handle.reset();
+        }
+    }
+}
+
+/// A listener for incoming mock TCP connections, as returned by
+/// [`MockNetProvider::listen`].
+#[derive(Debug)]
+pub struct MockTcpListener {
+    /// The address we're listening on.
+    addr: SocketAddr,
+    /// Incoming `(peer_addr, stream)` pairs, sent by `connect()` callers.
+    incoming: AsyncMutex<mpsc::UnboundedReceiver<(SocketAddr, LocalStream)>>,
+}
+
+#[async_trait]
+impl TcpProvider for MockNetProvider {
+    type TcpStream = LocalStream;
+    type TcpListener = MockTcpListener;
+
+    async fn connect(&self, addr: &SocketAddr) -> IoResult<Self::TcpStream> {
+        match self.take_fault(addr) {
+            Some(FaultKind::Refuse(kind)) => {
+                return Err(IoError::new(
+                    kind,
+                    format!("connection to {} refused (fault injected)", addr),
+                ));
+            }
+            Some(FaultKind::Blackhole) => {
+                // Never resolves: simulates connecting to a well-known black hole.
+                futures::future::pending::<()>().await;
+                unreachable!("pending future resolved");
+            }
+            None => {}
+        }
+
+        let sender = {
+            let inner = self.inner.lock().expect("poisoned lock");
+            inner.tcp_listeners.get(addr).cloned()
+        };
+        let Some(sender) = sender else {
+            return Err(IoError::new(
+                ErrorKind::ConnectionRefused,
+                format!("no mock listener bound to {}", addr),
+            ));
+        };
+        let (here, there) = LocalStream::pair();
+        // We don't simulate ephemeral client ports; callers only care about the peer address.
+        sender
+            .unbounded_send((*addr, there))
+            .map_err(|_| IoError::new(ErrorKind::ConnectionRefused, "mock listener closed"))?;
+
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner
+            .active_connections
+            .entry(*addr)
+            .or_default()
+            .push(here.reset_handle());
+        Ok(here)
+    }
+
+    async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        if inner.tcp_listeners.contains_key(addr) {
+            return Err(IoError::new(
+                ErrorKind::AddrInUse,
+                format!("address {} already in use", addr),
+            ));
+        }
+        inner.tcp_listeners.insert(*addr, tx);
+        Ok(MockTcpListener {
+            addr: *addr,
+            incoming: AsyncMutex::new(rx),
+        })
+    }
+}
+
+#[async_trait]
+impl TcpListener for MockTcpListener {
+    type TcpStream = LocalStream;
+    type Incoming = futures::stream::BoxStream<'static, IoResult<(LocalStream, SocketAddr)>>;
+
+    async fn accept(&self) -> IoResult<(Self::TcpStream, SocketAddr)> {
+        let mut incoming = self.incoming.lock().await;
+        match incoming.next().await {
+            Some((peer, stream)) => Ok((stream, peer)),
+            None => Err(IoError::new(ErrorKind::BrokenPipe, "mock network shut down")),
+        }
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn incoming(self) -> Self::Incoming {
+        use futures::stream::{self, StreamExt as _};
+        stream::unfold(self, |listener| async move {
+            let item = listener.accept().await;
+            Some((item, listener))
+        })
+        .boxed()
+    }
+}
+
+/// The handshake result a [`MockTlsConnector`] should produce.
+///
+/// Lets tests drive TLS-dependent code paths (certificate checking,
+/// handshake-failure retries) without a real TLS stack underneath.
+#[derive(Clone, Debug, Default)]
+pub struct MockTlsConfig {
+    /// The "peer certificate" that completed handshakes should report.
+    peer_certificate: Option<Vec<u8>>,
+    /// If set, `connect_unvalidated` fails with this message instead of succeeding.
+    handshake_error: Option<String>,
+}
+
+impl MockTlsConfig {
+    /// Make future handshakes succeed and report `cert` as the peer certificate.
+    pub fn set_peer_certificate(&mut self, cert: Vec<u8>) {
+        self.handshake_error = None;
+        self.peer_certificate = Some(cert);
+    }
+    /// Make future handshakes fail with `msg`.
+    pub fn set_handshake_error(&mut self, msg: impl Into<String>) {
+        self.handshake_error = Some(msg.into());
+    }
+}
+
+impl MockNetProvider {
+    /// Return a handle to the configuration used by every [`MockTlsConnector`]
+    /// this provider hands out, so tests can set up a peer certificate or a
+    /// scripted handshake failure before connecting.
+    pub fn tls_config(&self) -> Arc<Mutex<MockTlsConfig>> {
+        Arc::clone(&self.inner.lock().expect("poisoned lock").tls_config)
+    }
+}
+
+/// A TLS connector for the mock network, generic over the underlying stream
+/// type `S`.
+///
+/// Per the `tor-rtcompat` contract, a runtime may need to provide
+/// `TlsProvider<S>` for any suitable stream `S`, not just its own TCP stream
+/// type (for example, a stream produced by a pluggable transport). This
+/// connector does no real encryption: it wraps `S` in [`MockTlsStream`] and
+/// reports whatever handshake outcome is configured via [`MockTlsConfig`].
+#[derive(Clone, Debug)]
+pub struct MockTlsConnector<S> {
+    /// The configuration shared with the [`MockNetProvider`] that created this connector.
+    config: Arc<Mutex<MockTlsConfig>>,
+    /// Remembers which stream type this connector is for.
+    _stream: std::marker::PhantomData<S>,
+}
+
+/// A "TLS" stream wrapping an arbitrary underlying stream `S`, with a
+/// configurable peer certificate.
+#[derive(Debug)]
+pub struct MockTlsStream<S> {
+    /// The wrapped, unencrypted stream.
+    inner: S,
+    /// The peer certificate to report, if any.
+    peer_certificate: Option<Vec<u8>>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MockTlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MockTlsStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+impl<S> tor_rtcompat::CertifiedConn for MockTlsStream<S> {
+    fn peer_certificate(&self) -> IoResult<Option<Vec<u8>>> {
+        Ok(self.peer_certificate.clone())
+    }
+}
+
+#[async_trait]
+impl<S> tor_rtcompat::TlsConnector<S> for MockTlsConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
+{
+    type Conn = MockTlsStream<S>;
+    async fn connect_unvalidated(&self, _sni_hostname: &str, stream: S) -> IoResult<Self::Conn> {
+        let config = self.config.lock().expect("poisoned lock");
+        if let Some(msg) = &config.handshake_error {
+            return Err(IoError::new(ErrorKind::Other, msg.clone()));
+        }
+        Ok(MockTlsStream {
+            inner: stream,
+            peer_certificate: config.peer_certificate.clone(),
+        })
+    }
+}
+
+impl<S> TlsProvider<S> for MockNetProvider
+where
+    S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
+{
+    type Connector = MockTlsConnector<S>;
+    type TlsStream = MockTlsStream<S>;
+
+    fn tls_connector(&self) -> Self::Connector {
+        MockTlsConnector {
+            config: self.tls_config(),
+            _stream: std::marker::PhantomData,
+        }
+    }
+}
+
+/// A mock UDP socket, bound to an address in a [`MockNetProvider`]'s address table.
+///
+/// Datagrams sent to a bound address are delivered to whichever `MockUdpSocket`
+/// is currently bound there; there is no real network in between.
+#[derive(Debug)]
+pub struct MockUdpSocket {
+    /// The address we're bound to.
+    local_addr: SocketAddr,
+    /// The provider's shared state, so that `send_to` can look up other sockets.
+    inner: Arc<Mutex<Inner>>,
+    /// Datagrams sent to us, as `(from_addr, payload)` pairs.
+    incoming: AsyncMutex<mpsc::UnboundedReceiver<(SocketAddr, Vec<u8>)>>,
+}
+
+#[async_trait]
+impl UdpSocket for MockUdpSocket {
+    async fn recv(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+        let mut incoming = self.incoming.lock().await;
+        match incoming.next().await {
+            Some((from, payload)) => {
+                let n = std::cmp::min(buf.len(), payload.len());
+                buf[..n].copy_from_slice(&payload[..n]);
+                Ok((n, from))
+            }
+            None => Err(IoError::new(ErrorKind::BrokenPipe, "mock network shut down")),
+        }
+    }
+
+    async fn send(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
+        let sender = {
+            let inner = self.inner.lock().expect("poisoned lock");
+            inner.udp_sockets.get(target).cloned()
+        };
+        let Some(sender) = sender else {
+            return Err(IoError::new(
+                ErrorKind::ConnectionRefused,
+                format!("no mock socket bound to {}", target),
+            ));
+        };
+        sender
+            .unbounded_send((self.local_addr, buf.to_vec()))
+            .map_err(|_| IoError::new(ErrorKind::ConnectionRefused, "peer socket closed"))?;
+        Ok(buf.len())
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[async_trait]
+impl UdpProvider for MockNetProvider {
+    type UdpSocket = MockUdpSocket;
+
+    async fn bind(&self, addr: &SocketAddr) -> IoResult<Self::UdpSocket> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        if inner.udp_sockets.contains_key(addr) {
+            return Err(IoError::new(
+                ErrorKind::AddrInUse,
+                format!("address {} already in use", addr),
+            ));
+        }
+        inner.udp_sockets.insert(*addr, tx);
+        drop(inner);
+        Ok(MockUdpSocket {
+            local_addr: *addr,
+            inner: Arc::clone(&self.inner),
+            incoming: AsyncMutex::new(rx),
+        })
+    }
+}
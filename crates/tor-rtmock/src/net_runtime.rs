@@ -3,8 +3,11 @@
 // TODO(nickm): This is mostly copy-paste from MockSleepRuntime.  If possible,
 // we should make it so that more code is more shared.
 
+use crate::impair::ImpairedStream;
 use crate::net::MockNetProvider;
-use tor_rtcompat::{BlockOn, Runtime, SleepProvider, TcpProvider, TlsProvider, UdpProvider};
+use tor_rtcompat::{
+    BlockOn, Runtime, SleepProvider, TcpListener as _, TcpProvider, TlsProvider, UdpProvider,
+};
 
 use crate::io::LocalStream;
 use async_trait::async_trait;
@@ -56,20 +59,69 @@ impl<R: Runtime> BlockOn for MockNetRuntime<R> {
 
 #[async_trait]
 impl<R: Runtime> TcpProvider for MockNetRuntime<R> {
-    type TcpStream = <MockNetProvider as TcpProvider>::TcpStream;
-    type TcpListener = <MockNetProvider as TcpProvider>::TcpListener;
+    type TcpStream = ImpairedStream<R, LocalStream>;
+    type TcpListener = MockListener<R>;
 
     async fn connect(&self, addr: &SocketAddr) -> IoResult<Self::TcpStream> {
-        self.net.connect(addr).await
+        let stream = self.net.connect(addr).await?;
+        let params = self.net.link_params_for(addr);
+        Ok(ImpairedStream::new(self.runtime.clone(), stream, params))
     }
     async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener> {
-        self.net.listen(addr).await
+        let inner = self.net.listen(addr).await?;
+        Ok(MockListener {
+            runtime: self.runtime.clone(),
+            net: self.net.clone(),
+            inner,
+        })
     }
 }
 
-impl<R: Runtime> TlsProvider<LocalStream> for MockNetRuntime<R> {
-    type Connector = <MockNetProvider as TlsProvider<LocalStream>>::Connector;
-    type TlsStream = <MockNetProvider as TlsProvider<LocalStream>>::TlsStream;
+/// A [`TcpListener`](tor_rtcompat::TcpListener) that wraps each accepted
+/// connection in an [`ImpairedStream`], applying whatever
+/// [`LinkParams`](crate::impair::LinkParams) are registered for this
+/// listener's bound address.
+#[derive(Debug)]
+pub struct MockListener<R: Runtime> {
+    /// The runtime used to schedule impairment on accepted streams.
+    runtime: R,
+    /// The provider these addresses' link parameters are registered on.
+    net: MockNetProvider,
+    /// The underlying, unimpaired listener.
+    inner: <MockNetProvider as TcpProvider>::TcpListener,
+}
+
+#[async_trait]
+impl<R: Runtime> tor_rtcompat::TcpListener for MockListener<R> {
+    type TcpStream = ImpairedStream<R, LocalStream>;
+    type Incoming = futures::stream::BoxStream<'static, IoResult<(Self::TcpStream, SocketAddr)>>;
+
+    async fn accept(&self) -> IoResult<(Self::TcpStream, SocketAddr)> {
+        let (stream, addr) = self.inner.accept().await?;
+        let params = self.net.link_params_for(&self.inner.local_addr()?);
+        Ok((ImpairedStream::new(self.runtime.clone(), stream, params), addr))
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn incoming(self) -> Self::Incoming {
+        use futures::stream::StreamExt as _;
+        futures::stream::unfold(self, |listener| async move {
+            let item = listener.accept().await;
+            Some((item, listener))
+        })
+        .boxed()
+    }
+}
+
+impl<R: Runtime, S> TlsProvider<S> for MockNetRuntime<R>
+where
+    S: futures::io::AsyncRead + futures::io::AsyncWrite + Send + Sync + Unpin + 'static,
+{
+    type Connector = <MockNetProvider as TlsProvider<S>>::Connector;
+    type TlsStream = <MockNetProvider as TlsProvider<S>>::TlsStream;
     fn tls_connector(&self) -> Self::Connector {
         self.net.tls_connector()
     }
@@ -77,12 +129,11 @@ impl<R: Runtime> TlsProvider<LocalStream> for MockNetRuntime<R> {
 
 #[async_trait]
 impl<R: Runtime> UdpProvider for MockNetRuntime<R> {
-    type UdpSocket = R::UdpSocket;
+    type UdpSocket = <MockNetProvider as UdpProvider>::UdpSocket;
 
     #[inline]
     async fn bind(&self, addr: &SocketAddr) -> IoResult<Self::UdpSocket> {
-        // TODO this should probably get delegated to MockNetProvider instead
-        self.runtime.bind(addr).await
+        self.net.bind(addr).await
     }
 }
 
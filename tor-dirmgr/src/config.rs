@@ -0,0 +1,38 @@
+//! Extra, rarely-used settings for building a [`DirMgr`](crate::DirMgr),
+//! kept separate from the common-case [`NetDirConfig`](crate::NetDirConfig)
+//! so that most callers never need to think about them.
+
+use std::sync::Arc;
+
+use crate::filter::FilterConfig;
+use crate::storage::DynStore;
+use crate::DownloadScheduleBuilder;
+use crate::NetworkConfigBuilder;
+
+/// Less-common settings that customize how a [`DirMgr`](crate::DirMgr) is
+/// built, beyond what's in its main configuration.
+///
+/// Every field here is optional, and an embedder that doesn't need any of
+/// these customizations can just use [`DirMgrExtensions::default`].
+#[derive(Default)]
+#[non_exhaustive]
+pub struct DirMgrExtensions {
+    /// A filter to apply to directory documents before they're used, for
+    /// testing purposes.
+    ///
+    /// Only meaningful when built with the `dirfilter` feature.
+    pub filter: FilterConfig,
+    /// An alternate storage backend for the directory cache, in place of
+    /// the on-disk store that would otherwise be built from the
+    /// configuration's `storage` section.
+    ///
+    /// Only meaningful when built with the `experimental-api` feature.
+    pub storage: Option<Arc<dyn DynStore + 'static>>,
+    /// Overrides to apply to the configuration's `network` section.
+    pub network_overrides: Option<NetworkConfigBuilder>,
+    /// A pre-obtained consensus directory to seed the directory cache with,
+    /// for an offline or air-gapped start.
+    pub seed_netdir: Option<tor_netdir::NetDir>,
+    /// Overrides for the directory cache's download-retry schedules.
+    pub download_schedule_overrides: Option<DownloadScheduleBuilder>,
+}
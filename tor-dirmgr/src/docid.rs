@@ -0,0 +1,107 @@
+//! A unified identifier for any document this crate can cache or fetch.
+//!
+//! Before this module existed, missing certs and missing microdescriptors
+//! each flowed through their own request builder and their own
+//! cache-lookup call, even though -- from the cache's point of view --
+//! they're just "some documents we want." [`DocId`] gives every kind of
+//! document a common name, and [`DocumentText`] gives the bytes that come
+//! back a common shape, which is what lets [`crate::storage::Store::lookup`]
+//! serve the certificate and microdescriptor phases of bootstrapping with a
+//! single cache-read path. The consensus itself is read through
+//! [`crate::storage::Store::latest_consensus`].
+//!
+//! Fetching documents from the network is not yet unified the same way:
+//! each phase still builds its own `tor_dirclient::request::*Request`
+//! directly.
+
+use crate::storage::InputString;
+
+use tor_llcrypto::pk::rsa::RSAIdentity;
+use tor_netdoc::doc::authcert::AuthCertKeyIds;
+use tor_netdoc::doc::microdesc::MDDigest;
+
+/// Which flavor of consensus a [`DocId::LatestConsensus`] refers to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub(crate) enum ConsensusFlavor {
+    /// The microdesc consensus flavor, the only one Arti builds circuits
+    /// from today.
+    Microdesc,
+    /// The "ns" consensus flavor, which lists full router descriptors
+    /// instead of microdescriptor digests.
+    Ns,
+}
+
+impl ConsensusFlavor {
+    /// Return the name this flavor is known by in Tor's directory
+    /// protocol, and in the cache's `Consensuses.flavor` column.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ConsensusFlavor::Microdesc => "microdesc",
+            ConsensusFlavor::Ns => "ns",
+        }
+    }
+
+    /// Return the blob `doctype` tag this flavor's consensus text is
+    /// stored under in the cache.
+    pub(crate) fn blob_doctype(&self) -> &'static str {
+        match self {
+            ConsensusFlavor::Microdesc => "mdcon",
+            ConsensusFlavor::Ns => "nscon",
+        }
+    }
+}
+
+/// An identifier for a single document we might want from our cache or
+/// from the network.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub(crate) enum DocId {
+    /// The most recent valid consensus of a given flavor.
+    LatestConsensus {
+        /// Which flavor of consensus we want.
+        flavor: ConsensusFlavor,
+    },
+    /// An authority certificate, identified by signing and identity key.
+    AuthCert(AuthCertKeyIds),
+    /// A single microdescriptor, identified by its digest.
+    Microdesc(MDDigest),
+}
+
+/// The text of a single document, tagged with the [`DocId`] it answers.
+///
+/// `text` is an [`InputString`] rather than a plain `String` so that a
+/// document we read straight off an uncompressed cache blob can stay
+/// mapped into memory all the way out to the caller, instead of being
+/// copied into a fresh buffer just to satisfy this struct's shape.
+pub(crate) struct DocumentText {
+    /// Which document this is.
+    pub(crate) id: DocId,
+    /// Its contents.
+    pub(crate) text: InputString,
+}
+
+impl DocumentText {
+    /// Construct a new `DocumentText` for `id`, with contents `text`.
+    pub(crate) fn new(id: DocId, text: InputString) -> Self {
+        DocumentText { id, text }
+    }
+}
+
+/// Where a document came from, so that a document which fails to parse
+/// or validate can be traced back to whoever gave it to us.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub(crate) enum DocSource {
+    /// Loaded from our local cache. A document from here that turns out
+    /// to be bad isn't a network-trust problem -- it's just stale or
+    /// corrupt -- so no circuit is blamed for it.
+    LocalCache,
+    /// Downloaded from a directory cache or authority.
+    DirServer {
+        /// The identity of the relay we fetched it from, if the response
+        /// told us which one handled the request.
+        id: Option<RSAIdentity>,
+    },
+}
+
@@ -0,0 +1,60 @@
+//! Types to let callers observe the progress of directory bootstrapping.
+//!
+//! [`DirMgr::subscribe`](crate::DirMgr::subscribe) hands out a `futures`
+//! stream of [`DirProgress`] values, so that an embedding application can
+//! show the user something better than "fetching directory information,
+//! please wait."
+
+/// How close a [`DirMgr`](crate::DirMgr) is to having a directory it can
+/// actually use.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Readiness {
+    /// We don't have enough information to build any circuits yet.
+    NotReady,
+    /// We have enough information to build _some_ circuits, but not (yet)
+    /// enough to match our target level of performance and anonymity.
+    UsableBarely,
+    /// We have enough directory information to operate normally.
+    Complete,
+}
+
+/// A snapshot of how far a bootstrap attempt has progressed.
+///
+/// Each of [`NoInformation`](crate::NoInformation),
+/// [`UnvalidatedDir`](crate::UnvalidatedDir), and
+/// [`PartialDir`](crate::PartialDir) can produce one of these via
+/// `describe_progress()`. [`DirMgr::fetch_directory`](crate::DirMgr::fetch_directory)
+/// publishes one each time it moves to a new state or finishes a download
+/// round, and `fetch_additional_microdescs` publishes one for each batch of
+/// microdescriptors it adds; [`DirMgr::load_directory`](crate::DirMgr::load_directory)
+/// publishes none.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct DirProgress {
+    /// True if we have a (not necessarily validated) consensus document.
+    pub consensus: bool,
+    /// The number of authority certificates we have, versus the number we
+    /// need in order to validate the consensus.
+    pub certs: (u16, u16),
+    /// The number of microdescriptors we've downloaded so far, versus the
+    /// total number we want.
+    pub microdescs: (usize, usize),
+}
+
+impl DirProgress {
+    /// Summarize this progress as a [`Readiness`].
+    pub fn readiness(&self) -> Readiness {
+        if !self.consensus || self.certs.0 < self.certs.1 {
+            return Readiness::NotReady;
+        }
+        let (have, total) = self.microdescs;
+        if total == 0 || have == 0 {
+            Readiness::NotReady
+        } else if have < total {
+            Readiness::UsableBarely
+        } else {
+            Readiness::Complete
+        }
+    }
+}
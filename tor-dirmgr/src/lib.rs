@@ -12,49 +12,82 @@
 #![deny(clippy::missing_docs_in_private_items)]
 
 pub mod authority;
-mod config;
+pub mod config;
+mod docid;
 mod docmeta;
 mod err;
+pub mod event;
+mod parallelism;
+mod reputation;
 mod retry;
-mod storage;
+mod state;
+pub mod storage;
 mod updater;
 
+use crate::docid::{ConsensusFlavor, DocId, DocSource, DocumentText};
+use crate::parallelism::AdaptiveParallelism;
+use crate::reputation::CacheReputation;
 use crate::docmeta::{AuthCertMeta, ConsensusMeta};
-use crate::retry::RetryDelay;
-use crate::storage::sqlite::SqliteStore;
+use crate::retry::DownloadSchedule;
+use crate::state::DirState;
+use crate::storage::DynStore;
 use tor_checkable::{ExternallySigned, SelfSigned, Timebound};
 use tor_circmgr::{CircMgr, DirInfo};
 use tor_netdir::{MDReceiver, NetDir, PartialNetDir};
 use tor_netdoc::doc::authcert::{AuthCert, AuthCertKeyIds};
 use tor_netdoc::doc::microdesc::{MDDigest, Microdesc, MicrodescReader};
+#[cfg(feature = "routerdesc")]
+use tor_netdoc::doc::routerdesc::{RdDigest, RouterDescReader};
 use tor_netdoc::doc::netstatus::{MDConsensus, UnvalidatedMDConsensus};
 use tor_netdoc::AllowAnnotations;
 
 use anyhow::{anyhow, Result};
 use async_rwlock::RwLock;
+use async_trait::async_trait;
 use futures::lock::Mutex;
 use futures::stream::StreamExt;
 use log::{debug, info};
 
 use std::collections::HashSet;
 use std::fmt::Debug;
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 pub use authority::Authority;
 pub use config::{NetDirConfig, NetDirConfigBuilder};
 pub use err::Error;
+pub use storage::{DynStore, MemoryStore, Store};
 pub use updater::DirectoryUpdater;
 
+/// How much a [`DirMgr`] entry point is allowed to rely on its local cache
+/// when building or refreshing a directory.
+///
+/// Before this existed, `fetch_directory` took a bare `use_cached_consensus:
+/// bool`, and `NoInformation::load` took an equally bare `pending: bool` --
+/// neither said anything about what should happen if the cache turned out
+/// not to be enough.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CacheUsage {
+    /// Only use documents we already have on disk; never touch the
+    /// network. If the cache isn't enough to build a complete directory,
+    /// the caller gets an error rather than a network fetch.
+    CacheOnly,
+    /// Use a cached consensus if it's still live; otherwise, fall back to
+    /// downloading a fresh one.
+    CacheOkay,
+    /// Ignore whatever's cached and always fetch a fresh consensus.
+    MustDownload,
+}
+
 /// A directory manager to download, fetch, and cache a Tor directory
 pub struct DirMgr {
     /// Configuration information: where to find directories, how to
     /// validate them, and so on.
     config: NetDirConfig,
-    /// Handle to our sqlite cache.
+    /// Handle to our directory cache.
     // XXXX I'd like to use an rwlock, but that's not feasible, since
     // rusqlite::Connection isn't Sync.
-    store: Mutex<SqliteStore>,
+    store: Mutex<DynStore>,
     /// Our latest sufficiently bootstrapped directory, if we have one.
     ///
     /// We use the RwLock so that we can give this out to a bunch of other
@@ -62,18 +95,58 @@ pub struct DirMgr {
     // XXXX-A1 I'd like this not to be an Option, or not to visibly be an
     // option once the NetDir is handed off to a user.
     netdir: RwLock<Option<Arc<NetDir>>>,
+    /// The most recently published bootstrap progress.
+    progress: StdRwLock<event::DirProgress>,
+    /// Senders for every outstanding [`DirMgr::subscribe`] stream.
+    subscribers: StdMutex<Vec<futures::channel::mpsc::UnboundedSender<event::DirProgress>>>,
 }
 
 impl DirMgr {
-    /// Construct a DirMgr from a NetDirConfig.
+    /// Construct a DirMgr from a NetDirConfig, caching directory documents
+    /// on disk in a `SqliteStore`.
     pub fn from_config(config: NetDirConfig) -> Result<Self> {
-        let store = Mutex::new(config.open_sqlite_store()?);
-        let netdir = RwLock::new(None);
-        Ok(DirMgr {
+        let store: DynStore = Box::new(config.open_sqlite_store()?);
+        Ok(Self::from_config_and_store(config, store))
+    }
+
+    /// Construct a DirMgr from a NetDirConfig and an already-built
+    /// [`DynStore`], bypassing the on-disk cache `from_config` uses by
+    /// default.
+    ///
+    /// This is what lets an embedder hand in a [`storage::MemoryStore`]
+    /// (or any other [`storage::Store`] implementation) for an
+    /// ephemeral, RAM-only client, or for tests that shouldn't touch disk.
+    pub fn from_config_and_store(config: NetDirConfig, store: DynStore) -> Self {
+        DirMgr {
             config,
-            store,
-            netdir,
-        })
+            store: Mutex::new(store),
+            netdir: RwLock::new(None),
+            progress: StdRwLock::new(event::DirProgress::default()),
+            subscribers: StdMutex::new(Vec::new()),
+        }
+    }
+
+    /// Return a stream that yields a [`event::DirProgress`] every time this
+    /// `DirMgr`'s bootstrap progress advances.
+    ///
+    /// The stream immediately yields the current progress, so a caller
+    /// doesn't have to race to subscribe before the first update happens.
+    pub fn subscribe(&self) -> impl futures::Stream<Item = event::DirProgress> {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let current = self.progress.read().expect("lock poisoned").clone();
+        // If the receiver is already gone, there's nothing to clean up: the
+        // channel will simply be dropped along with `tx` below.
+        let _ = tx.unbounded_send(current);
+        self.subscribers.lock().expect("lock poisoned").push(tx);
+        rx
+    }
+
+    /// Record `progress` as our current bootstrap status, and notify every
+    /// live subscriber from [`DirMgr::subscribe`].
+    fn publish_progress(&self, progress: event::DirProgress) {
+        *self.progress.write().expect("lock poisoned") = progress.clone();
+        let mut subscribers = self.subscribers.lock().expect("lock poisoned");
+        subscribers.retain(|tx| tx.unbounded_send(progress.clone()).is_ok());
     }
 
     /// Load the latest non-pending non-expired directory from the
@@ -85,7 +158,7 @@ impl DirMgr {
 
         let noinfo = NoInformation::new();
 
-        let mut unval = match noinfo.load(false, &self.config, store).await? {
+        let mut unval = match noinfo.load(CacheUsage::CacheOnly, &self.config, store).await? {
             NextState::SameState(_) => return Ok(false),
             NextState::NewState(unval) => unval,
         };
@@ -130,13 +203,13 @@ impl DirMgr {
     /// Run a complete bootstrapping process, using information from our
     /// cache when it is up-to-date enough.
     pub async fn bootstrap_directory(&self, circmgr: Arc<CircMgr>) -> Result<()> {
-        self.fetch_directory(circmgr, true).await
+        self.fetch_directory(circmgr, CacheUsage::CacheOkay).await
     }
 
     /// Get a new directory, starting with a fresh consensus download.
     ///
     async fn fetch_new_directory(&self, circmgr: Arc<CircMgr>) -> Result<()> {
-        self.fetch_directory(circmgr, false).await
+        self.fetch_directory(circmgr, CacheUsage::MustDownload).await
     }
 
     /// Try to fetch and add a new set of microdescriptors to the
@@ -163,14 +236,21 @@ impl DirMgr {
                 "{} missing microdescsriptors. Attempting to download...",
                 n_missing
             );
-            let mds = download_mds(
+            let (mds, exhausted) = download_mds(
                 missing,
                 mark_listed,
+                &self.config.schedule_microdescs(),
                 &self.store,
                 netdir.as_ref().into(),
                 circmgr,
             )
             .await?;
+            if !exhausted.is_empty() {
+                debug!(
+                    "Giving up on {} microdescriptors after repeated failures.",
+                    exhausted.len()
+                );
+            }
             if mds.is_empty() {
                 return Ok(n_missing);
             }
@@ -190,7 +270,16 @@ impl DirMgr {
             }
         };
 
-        Ok(new_netdir.missing_microdescs().count())
+        let n_missing = new_netdir.missing_microdescs().count();
+        let prev = self.progress.read().expect("lock poisoned").clone();
+        let total = prev.microdescs.1.max(prev.microdescs.0 + n_missing);
+        self.publish_progress(event::DirProgress {
+            consensus: true,
+            certs: prev.certs,
+            microdescs: (total.saturating_sub(n_missing), total),
+        });
+
+        Ok(n_missing)
     }
 
     /// Launch an updater task that periodically re-fetches the
@@ -209,19 +298,24 @@ impl DirMgr {
     }
 
     /// Run a complete bootstrapping process, using information from our
-    /// cache when it is up-to-date enough.  When complete, update our
+    /// cache when `cache_usage` allows it.  When complete, update our
     /// NetDir with the one we've fetched.
     ///
-    /// If use_cached_consensus is true, we start with a cached
-    /// consensus if it is live; otherwise, we start with a consensus
-    /// download.
+    /// `cache_usage` governs where the directory comes from: `CacheOnly`
+    /// builds it from the cache alone through [`DirMgr::load_directory`],
+    /// never touching the network, and fails if the cache holds no usable
+    /// directory and we have none yet; `CacheOkay` starts from a live
+    /// cached consensus if one exists, falling back to the network if it
+    /// doesn't; `MustDownload` always starts with a fresh download. Under
+    /// `CacheOkay` and `MustDownload`, any certs or microdescriptors the
+    /// cache is missing are fetched from the network.
     // TODO: We'll likely need to refactor this before too long.
     // TODO: This needs to exit with a failure if the consensus expires
     // partway through the process.
     pub async fn fetch_directory(
         &self,
         circmgr: Arc<CircMgr>,
-        use_cached_consensus: bool,
+        cache_usage: CacheUsage,
     ) -> Result<()> {
         let store = &self.store;
 
@@ -232,10 +326,16 @@ impl DirMgr {
         };
 
         let noinfo = NoInformation::new();
-        let nextstate = if use_cached_consensus {
-            noinfo.load(true, &self.config, store).await?
-        } else {
-            NextState::SameState(noinfo)
+        let nextstate = match cache_usage {
+            CacheUsage::MustDownload => NextState::SameState(noinfo),
+            CacheUsage::CacheOkay => noinfo.load(cache_usage, &self.config, store).await?,
+            CacheUsage::CacheOnly => {
+                // Never touch the network: build from the cache or fail.
+                if self.load_directory().await? || self.netdir().await.is_some() {
+                    return Ok(());
+                }
+                return Err(anyhow!("No usable directory in the cache"));
+            }
         };
 
         // TODO: XXXX-A1: Also check the age of our current one.
@@ -248,20 +348,34 @@ impl DirMgr {
             }
             NextState::NewState(unval) => unval,
         };
+        self.publish_progress(unval.describe_progress(&self.config));
 
-        unval.load(&self.config, store).await?;
+        // `unval.fetch_certs` starts by loading whatever's already cached.
         info!("Fetching certificate(s).");
         unval
-            .fetch_certs(&self.config, store, dirinfo, Arc::clone(&circmgr))
+            .fetch_certs(
+                &self.config,
+                store,
+                dirinfo,
+                Arc::clone(&circmgr),
+                &|p| self.publish_progress(p),
+            )
             .await?;
         let mut partial = match unval.advance(&self.config)? {
             NextState::SameState(_) => return Err(anyhow!("Couldn't get certs")),
             NextState::NewState(p) => p,
         };
+        self.publish_progress(partial.describe_progress());
 
         partial.load(store, self.netdir().await).await?;
         partial
-            .fetch_mds(store, dirinfo, Arc::clone(&circmgr))
+            .fetch_mds(
+                &self.config,
+                store,
+                dirinfo,
+                Arc::clone(&circmgr),
+                &|p| self.publish_progress(p),
+            )
             .await?;
 
         let nd = match partial.advance() {
@@ -269,11 +383,80 @@ impl DirMgr {
             NextState::SameState(_) => return Err(anyhow!("Didn't get enough mds")),
         };
 
+        let nd = Arc::new(nd);
         {
             let mut w = self.netdir.write().await;
-            *w = Some(Arc::new(nd));
+            *w = Some(Arc::clone(&nd));
+        }
+
+        #[cfg(feature = "routerdesc")]
+        self.fetch_routerdescs(&nd, circmgr).await?;
+        #[cfg(not(feature = "routerdesc"))]
+        let _ = circmgr;
+
+        Ok(())
+    }
+
+    /// If we're configured to want full router descriptors -- for features
+    /// that microdescriptors omit -- fetch whatever ones `netdir`'s
+    /// consensus lists that we don't already have cached.
+    ///
+    /// Does nothing if [`NetDirConfig::download_routerdescs`] says we
+    /// don't want them.
+    #[cfg(feature = "routerdesc")]
+    async fn fetch_routerdescs(&self, netdir: &Arc<NetDir>, circmgr: Arc<CircMgr>) -> Result<()> {
+        if !self.config.download_routerdescs() {
+            return Ok(());
+        }
+
+        let mark_listed = netdir.lifetime().valid_after();
+        let wanted: Vec<RdDigest> = netdir.consensus_routerdesc_digests().collect();
+        if wanted.is_empty() {
+            return Ok(());
         }
 
+        // Whatever we already have cached, we don't need to re-download;
+        // we just need to bump its last-listed time so it survives the
+        // next `expire_all`. Mirrors how `load_mds` handles microdescriptors
+        // that are already on disk.
+        let missing: Vec<RdDigest> = {
+            let cached = {
+                let r = self.store.lock().await;
+                r.routerdescs(&wanted)?
+            };
+            let already_have: Vec<RdDigest> = cached.keys().copied().collect();
+            if !already_have.is_empty() {
+                let mut w = self.store.lock().await;
+                w.update_routerdescs_listed(&already_have, mark_listed)?;
+            }
+            wanted
+                .into_iter()
+                .filter(|d| !cached.contains_key(d))
+                .collect()
+        };
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        debug!(
+            "{} router descriptors listed in the consensus. Attempting to download...",
+            missing.len()
+        );
+        let (_rds, exhausted) = download_routerdescs(
+            missing,
+            mark_listed,
+            &self.config.schedule_routerdescs(),
+            &self.store,
+            netdir.as_ref().into(),
+            circmgr,
+        )
+        .await?;
+        if !exhausted.is_empty() {
+            debug!(
+                "Giving up on {} router descriptors after repeated failures.",
+                exhausted.len()
+            );
+        }
         Ok(())
     }
 
@@ -299,6 +482,20 @@ impl DirMgr {
     }
 }
 
+/// A downloaded document turned out to be unparseable or failed
+/// validation: if it came from a directory server, ask `circmgr` to
+/// retire the circuit we used to reach it, so we don't keep asking the
+/// same relay for documents it can't (or won't) serve correctly.
+///
+/// Documents from [`DocSource::LocalCache`] have no circuit to blame, so
+/// this does nothing for them.
+fn retire_bad_source(source: &DocSource, circmgr: &CircMgr) {
+    if let DocSource::DirServer { id: Some(id) } = source {
+        // XXXX-A1 warn if this fails; for now, best-effort is fine.
+        let _ = circmgr.retire_circs_to(id);
+    }
+}
+
 /// Abstraction to handle the idea of a possible state transition
 /// after fetching or loading directory information.
 #[derive(Clone, Debug)]
@@ -350,6 +547,12 @@ struct PartialDir {
     from_cache: bool,
     /// Information about digests and lifetimes of the consensus.
     consensus_meta: ConsensusMeta,
+    /// The number of authority certificates that validated this
+    /// consensus; since we got this far, it's also the number we needed.
+    n_certs: u16,
+    /// The total number of microdescriptors this consensus lists, as seen
+    /// when we first entered this state.
+    n_mds_total: usize,
     /// The consensus directory, partially filled in with microdescriptors.
     dir: PartialNetDir,
 }
@@ -361,20 +564,35 @@ impl NoInformation {
         NoInformation {}
     }
 
+    /// Describe how far we've progressed towards having a usable
+    /// directory: by definition, not at all yet.
+    fn describe_progress(&self) -> event::DirProgress {
+        event::DirProgress::default()
+    }
+
     /// Try to fetch a currently timely consensus directory document
-    /// from the local cache in `store`.  If `pending`, then we'll
-    /// happily return a pending document; otherwise, we'll only
-    /// return a document that has been marked as having been completely
-    /// bootstrapped.
+    /// from the local cache in `store`.
+    ///
+    /// Under [`CacheUsage::CacheOnly`], we only return a consensus that's
+    /// already been marked as completely bootstrapped; under
+    /// `CacheOkay` (or `MustDownload`, though callers shouldn't ask for
+    /// that case), we'll happily return one that's still pending.
+    ///
+    /// This document's [`DocSource`] is always `LocalCache`, so there's no
+    /// circuit to retire if it turns out to be bad. An expired consensus
+    /// gives `NextState::SameState(self)`, so the caller can re-fetch from
+    /// the network; a consensus that fails to parse is returned as an
+    /// error instead.
     async fn load(
         self,
-        pending: bool,
+        cache_usage: CacheUsage,
         config: &NetDirConfig,
-        store: &Mutex<SqliteStore>,
+        store: &Mutex<DynStore>,
     ) -> Result<NextState<Self, UnvalidatedDir>> {
+        let pending = !matches!(cache_usage, CacheUsage::CacheOnly);
         let consensus_text = {
             let store = store.lock().await;
-            match store.latest_consensus(pending)? {
+            match store.latest_consensus(ConsensusFlavor::Microdesc, pending)? {
                 Some(c) => c,
                 None => return Ok(NextState::SameState(self)),
             }
@@ -407,24 +625,23 @@ impl NoInformation {
     async fn fetch_consensus(
         &self,
         config: &NetDirConfig,
-        store: &Mutex<SqliteStore>,
+        store: &Mutex<DynStore>,
         info: DirInfo<'_>,
         circmgr: Arc<CircMgr>,
     ) -> Result<UnvalidatedDir> {
-        // XXXX make this configurable.
         // XXXX-A1 add a "keep trying forever" option for when we have no consensus.
-        let n_retries = 3_u32;
-        let mut retry_delay = RetryDelay::default();
+        let mut schedule = config.schedule_consensus().schedule();
 
         let mut last_err: Option<anyhow::Error> = None;
-        for _ in 0..n_retries {
+        while schedule.more_attempts() {
             let cm = Arc::clone(&circmgr);
             match self.fetch_consensus_once(config, store, info, cm).await {
                 Ok(v) => return Ok(v),
                 Err(e) => {
                     last_err = Some(e);
-                    let delay = retry_delay.next_delay(&mut rand::thread_rng());
-                    tor_rtcompat::task::sleep(delay).await;
+                    if let Some(delay) = schedule.next_delay(&mut rand::thread_rng()) {
+                        tor_rtcompat::task::sleep(delay).await;
+                    }
                 }
             }
         }
@@ -438,7 +655,7 @@ impl NoInformation {
     async fn fetch_consensus_once(
         &self,
         config: &NetDirConfig,
-        store: &Mutex<SqliteStore>,
+        store: &Mutex<DynStore>,
         info: DirInfo<'_>,
         circmgr: Arc<CircMgr>,
     ) -> Result<UnvalidatedDir> {
@@ -446,22 +663,41 @@ impl NoInformation {
 
         {
             let r = store.lock().await;
-            if let Some(valid_after) = r.latest_consensus_time()? {
+            if let Some(valid_after) = r.latest_consensus_time(ConsensusFlavor::Microdesc)? {
                 resource.set_last_consensus_date(valid_after.into());
             }
         }
-        let response = tor_dirclient::get_resource(resource, info, circmgr).await?;
+        let response = tor_dirclient::get_resource(resource, info, circmgr.clone()).await?;
+        let source = DocSource::DirServer {
+            id: response.source_id(),
+        };
         let text = response.output();
-        // XXXX-A1 In some of the below error cases we should retire the circuit
-        // to the cache that gave us this stuff.
 
-        let (signedval, remainder, parsed) = MDConsensus::parse(&text)?;
-        let unvalidated = parsed.check_valid_now()?;
+        let (signedval, remainder, parsed) = match MDConsensus::parse(&text) {
+            Ok(v) => v,
+            Err(e) => {
+                retire_bad_source(&source, &circmgr);
+                return Err(e.into());
+            }
+        };
+        let unvalidated = match parsed.check_valid_now() {
+            Ok(v) => v,
+            Err(e) => {
+                retire_bad_source(&source, &circmgr);
+                return Err(e.into());
+            }
+        };
         let meta = ConsensusMeta::from_unvalidated(signedval, remainder, &unvalidated);
 
         {
             let mut w = store.lock().await;
-            w.store_consensus(&meta, true, &text)?;
+            w.store_consensus(
+                &meta,
+                ConsensusFlavor::Microdesc,
+                true,
+                &text,
+                &crate::storage::ExpirationConfig::default(),
+            )?;
         }
         let n_authorities = config.authorities().len() as u16;
         let unvalidated = unvalidated.set_n_authorities(n_authorities);
@@ -503,17 +739,31 @@ impl UnvalidatedDir {
         }
     }
 
+    /// Describe how far we've progressed towards having a usable
+    /// directory: we have a consensus, and some number of the certs we
+    /// need to validate it.
+    fn describe_progress(&self, config: &NetDirConfig) -> event::DirProgress {
+        let needed = config.authorities().len() as u16;
+        let have = self.certs.len() as u16;
+        event::DirProgress {
+            consensus: true,
+            certs: (have.min(needed), needed),
+            microdescs: (0, 0),
+        }
+    }
+
     /// Load authority certificates from our local cache.
-    async fn load(&mut self, config: &NetDirConfig, store: &Mutex<SqliteStore>) -> Result<()> {
+    async fn load(&mut self, config: &NetDirConfig, store: &Mutex<DynStore>) -> Result<()> {
         let missing = self.missing_certs(config);
+        let ids: Vec<DocId> = missing.into_iter().map(DocId::AuthCert).collect();
 
         let newcerts = {
             let r = store.lock().await;
-            r.authcerts(&missing[..])?
+            r.lookup(&ids)?
         };
 
-        for c in newcerts.values() {
-            let cert = AuthCert::parse(c)?.check_signature()?;
+        for doc in &newcerts {
+            let cert = AuthCert::parse(doc.text.as_str()?)?.check_signature()?;
             if let Ok(cert) = cert.check_valid_now() {
                 // XXXX-A1: Complain if we find a cert we didn't want. That's a bug.
                 self.certs.push(cert);
@@ -531,41 +781,20 @@ impl UnvalidatedDir {
     async fn fetch_certs(
         &mut self,
         config: &NetDirConfig,
-        store: &Mutex<SqliteStore>,
+        store: &Mutex<DynStore>,
         info: DirInfo<'_>,
         circmgr: Arc<CircMgr>,
+        on_progress: &dyn Fn(event::DirProgress),
     ) -> Result<()> {
-        // XXXX make this configurable
         // XXXX-A1 add a "keep trying forever" option for when we have no consensus.
-        let n_retries = 3_u32;
-        let mut retry_delay = RetryDelay::default();
-
-        let mut last_err: Option<anyhow::Error> = None;
-        for _ in 0..n_retries {
-            let cm = Arc::clone(&circmgr);
-            if let Err(e) = self.fetch_certs_once(config, store, info, cm).await {
-                last_err = Some(e);
-            }
-
-            if self.missing_certs(config).is_empty() {
-                // We have enough certificates to validate the consensus.
-                return Ok(());
-            }
-            let delay = retry_delay.next_delay(&mut rand::thread_rng());
-            tor_rtcompat::task::sleep(delay).await;
-        }
-
-        match last_err {
-            Some(e) => Err(e),
-            None => Err(anyhow!("Couldn't get certs after retries.")),
-        }
+        state::bootstrap(self, config, store, info, circmgr, on_progress).await
     }
 
     /// Try to fetch authority certificates from the network.
     async fn fetch_certs_once(
         &mut self,
         config: &NetDirConfig,
-        store: &Mutex<SqliteStore>,
+        store: &Mutex<DynStore>,
         info: DirInfo<'_>,
         circmgr: Arc<CircMgr>,
     ) -> Result<()> {
@@ -579,10 +808,11 @@ impl UnvalidatedDir {
             resource.push(m.clone());
         }
 
-        let response = tor_dirclient::get_resource(resource, info, circmgr).await?;
+        let response = tor_dirclient::get_resource(resource, info, circmgr.clone()).await?;
+        let source = DocSource::DirServer {
+            id: response.source_id(),
+        };
         let text = response.output();
-        // XXXX-A1 In some of the below error cases we should retire the circuit
-        // to the cache that gave us this stuff.
 
         let mut newcerts = Vec::new();
         for cert in AuthCert::parse_multiple(&text) {
@@ -597,6 +827,13 @@ impl UnvalidatedDir {
             // XXXX-A1 warn on error.
         }
 
+        if newcerts.is_empty() {
+            // The cache gave us a response, but none of it was a cert we
+            // could use: it's misbehaving (or just very unlucky), so stop
+            // asking it.
+            retire_bad_source(&source, &circmgr);
+        }
+
         // Throw away any that we didn't ask for.
         self.certs
             .retain(|cert| missing.iter().any(|m| m == cert.key_ids()));
@@ -629,10 +866,14 @@ impl UnvalidatedDir {
         if missing.is_empty() {
             // Either we can validate, or we never will.
             let validated = self.consensus.check_signature(&self.certs[..])?;
+            let dir = PartialNetDir::new(validated);
+            let n_mds_total = dir.missing_microdescs().count();
             Ok(NextState::NewState(PartialDir {
                 from_cache: self.from_cache,
                 consensus_meta: self.consensus_meta,
-                dir: PartialNetDir::new(validated),
+                n_certs: self.certs.len() as u16,
+                n_mds_total,
+                dir,
             }))
         } else {
             Ok(NextState::SameState(self))
@@ -640,9 +881,42 @@ impl UnvalidatedDir {
     }
 }
 
+#[async_trait]
+impl DirState for UnvalidatedDir {
+    fn have_enough(&mut self, config: &NetDirConfig) -> bool {
+        self.missing_certs(config).is_empty()
+    }
+
+    fn dl_config(&self, config: &NetDirConfig) -> DownloadSchedule {
+        config.schedule_certs()
+    }
+
+    fn describe_progress(&self, config: &NetDirConfig) -> event::DirProgress {
+        UnvalidatedDir::describe_progress(self, config)
+    }
+
+    async fn add_from_cache(
+        &mut self,
+        config: &NetDirConfig,
+        store: &Mutex<DynStore>,
+    ) -> Result<()> {
+        self.load(config, store).await
+    }
+
+    async fn add_from_download(
+        &mut self,
+        config: &NetDirConfig,
+        store: &Mutex<DynStore>,
+        info: DirInfo<'_>,
+        circmgr: Arc<CircMgr>,
+    ) -> Result<()> {
+        self.fetch_certs_once(config, store, info, circmgr).await
+    }
+}
+
 impl PartialDir {
     /// Try to load microdescriptors from our local cache.
-    async fn load(&mut self, store: &Mutex<SqliteStore>, prev: Option<Arc<NetDir>>) -> Result<()> {
+    async fn load(&mut self, store: &Mutex<DynStore>, prev: Option<Arc<NetDir>>) -> Result<()> {
         let mark_listed = Some(SystemTime::now()); // XXXX-A1 use validafter, conditionally.
 
         load_mds(&mut self.dir, prev, mark_listed, store).await
@@ -653,47 +927,33 @@ impl PartialDir {
     /// Retry if we didn't get enough to build circuits.
     async fn fetch_mds(
         &mut self,
-        store: &Mutex<SqliteStore>,
+        config: &NetDirConfig,
+        store: &Mutex<DynStore>,
         info: DirInfo<'_>,
         circmgr: Arc<CircMgr>,
+        on_progress: &dyn Fn(event::DirProgress),
     ) -> Result<()> {
-        // XXXX Make this configurable
         // XXXX-A1 add a "keep trying forever" option for when we have no consensus.
-        let n_retries = 3_u32;
-        let mut retry_delay = RetryDelay::default();
-
-        let mut last_err: Option<anyhow::Error> = None;
-        for _ in 0..n_retries {
-            let cm = Arc::clone(&circmgr);
-            if let Err(e) = self.fetch_mds_once(store, info, cm).await {
-                last_err = Some(e);
-            }
-
-            if self.dir.have_enough_paths() {
-                // We can build circuits; return!
-                return Ok(());
-            }
-            let delay = retry_delay.next_delay(&mut rand::thread_rng());
-            tor_rtcompat::task::sleep(delay).await;
-        }
-
-        match last_err {
-            Some(e) => Err(e),
-            None => Err(anyhow!("Couldn't get microdescs after retries.")),
-        }
+        state::bootstrap(self, config, store, info, circmgr, on_progress).await
     }
     /// Try to fetch microdescriptors from the network.
     async fn fetch_mds_once(
         &mut self,
-        store: &Mutex<SqliteStore>,
+        dl_schedule: &DownloadSchedule,
+        store: &Mutex<DynStore>,
         info: DirInfo<'_>,
         circmgr: Arc<CircMgr>,
     ) -> Result<()> {
         let mark_listed = SystemTime::now(); // XXXX-A1 use validafter
         let missing: Vec<MDDigest> = self.dir.missing_microdescs().map(Clone::clone).collect();
-        let mds = download_mds(missing, mark_listed, store, info, circmgr).await?;
-        for md in mds {
-            self.dir.add_microdesc(md);
+        let exhausted =
+            download_mds_into(missing, mark_listed, dl_schedule, &mut self.dir, store, info, circmgr)
+                .await?;
+        if !exhausted.is_empty() && !self.dir.have_enough_paths() {
+            info!(
+                "Giving up on {} microdescriptors after repeated failures.",
+                exhausted.len()
+            );
         }
         if self.dir.have_enough_paths() {
             // XXXX no need to do this if it was already non-pending.
@@ -701,7 +961,7 @@ impl PartialDir {
             let mut w = store.lock().await;
             w.mark_consensus_usable(&self.consensus_meta)?;
             // Expire on getting a valid directory.
-            w.expire_all()?;
+            w.expire_all(&crate::storage::ExpirationConfig::default())?;
         }
         Ok(())
     }
@@ -714,10 +974,63 @@ impl PartialDir {
             Err(partial) => NextState::SameState(PartialDir {
                 from_cache: self.from_cache,
                 consensus_meta: self.consensus_meta,
+                n_certs: self.n_certs,
+                n_mds_total: self.n_mds_total,
                 dir: partial,
             }),
         }
     }
+
+    /// Describe how far we've progressed towards having a usable
+    /// directory: we have a validated consensus, and some number of the
+    /// microdescriptors it lists.
+    fn describe_progress(&self) -> event::DirProgress {
+        let missing = self.dir.missing_microdescs().count();
+        let have = self.n_mds_total.saturating_sub(missing);
+        event::DirProgress {
+            consensus: true,
+            certs: (self.n_certs, self.n_certs),
+            microdescs: (have, self.n_mds_total),
+        }
+    }
+}
+
+#[async_trait]
+impl DirState for PartialDir {
+    fn have_enough(&mut self, _config: &NetDirConfig) -> bool {
+        self.dir.have_enough_paths()
+    }
+
+    fn dl_config(&self, config: &NetDirConfig) -> DownloadSchedule {
+        config.schedule_microdescs()
+    }
+
+    fn describe_progress(&self, _config: &NetDirConfig) -> event::DirProgress {
+        PartialDir::describe_progress(self)
+    }
+
+    async fn add_from_cache(
+        &mut self,
+        _config: &NetDirConfig,
+        store: &Mutex<DynStore>,
+    ) -> Result<()> {
+        // We don't have a previous NetDir to fall back on here: that fast
+        // path is only available to `DirMgr`, which already takes it
+        // before calling `fetch_mds`. This just mops up whatever's in the
+        // cache for whatever's still missing.
+        self.load(store, None).await
+    }
+
+    async fn add_from_download(
+        &mut self,
+        config: &NetDirConfig,
+        store: &Mutex<DynStore>,
+        info: DirInfo<'_>,
+        circmgr: Arc<CircMgr>,
+    ) -> Result<()> {
+        let dl_schedule = config.schedule_microdescs();
+        self.fetch_mds_once(&dl_schedule, store, info, circmgr).await
+    }
 }
 
 /// Helper to load microdescriptors from the cache and store them into
@@ -726,123 +1039,553 @@ async fn load_mds(
     doc: &mut PartialNetDir,
     prev: Option<Arc<NetDir>>,
     mark_listed: Option<SystemTime>,
-    store: &Mutex<SqliteStore>,
+    store: &Mutex<DynStore>,
 ) -> Result<()> {
-    let mut loaded = if let Some(ref prev_netdir) = prev {
+    let mut loaded: Vec<MDDigest> = if let Some(ref prev_netdir) = prev {
         doc.fill_from_previous_netdir(prev_netdir.as_ref())
+            .into_iter()
+            .copied()
+            .collect()
     } else {
         Vec::new()
     };
 
+    let ids: Vec<DocId> = doc
+        .missing_microdescs()
+        .cloned()
+        .map(DocId::Microdesc)
+        .collect();
+    let n_wanted = ids.len();
     let microdescs = {
         let r = store.lock().await;
-        r.microdescs(doc.missing_microdescs())?
+        r.lookup(&ids)?
     };
-
-    for (digest, text) in microdescs.iter() {
-        let md = Microdesc::parse(text)?; // XXXX-A1 recover from this
+    debug!(
+        "Found {}/{} missing microdescriptors in the cache.",
+        microdescs.len(),
+        n_wanted
+    );
+
+    for DocumentText { id, text } in &microdescs {
+        let digest = match id {
+            DocId::Microdesc(digest) => digest,
+            _ => continue,
+        };
+        let md = Microdesc::parse(text.as_str()?)?; // XXXX-A1 recover from this
         if md.digest() != digest {
             // whoa! XXXX Log something about this.
             continue;
         }
         if doc.add_microdesc(md) {
-            loaded.push(digest);
+            loaded.push(*digest);
         }
     }
 
     if let Some(when) = mark_listed {
         let mut w = store.lock().await;
-        w.update_microdescs_listed(loaded, when)?;
+        w.update_microdescs_listed(&loaded, when)?;
     }
 
     Ok(())
 }
 
+/// Dispatch `chunks` through `fetch_chunk`, using `parallelism` to decide
+/// how many requests to keep in flight at once.
+///
+/// Chunks are issued in waves: each wave's width is whatever
+/// `parallelism.width()` says right when the wave starts, and the
+/// requests within a wave are staggered by `parallelism.min_spacing()` so
+/// that a wide budget doesn't turn into a burst of near-simultaneous
+/// connections to the same cache. After each chunk finishes, `parallelism`
+/// is told how long it took and whether it came back with anything
+/// usable, so it can grow or shrink before the next wave starts.
+async fn dispatch_chunks_adaptive<T, U, F, Fut>(
+    chunks: Vec<T>,
+    parallelism: &mut AdaptiveParallelism,
+    fetch_chunk: F,
+) -> Vec<U>
+where
+    F: Fn(T) -> Fut,
+    Fut: std::future::Future<Output = Vec<U>>,
+{
+    let mut results = Vec::new();
+    let mut remaining = chunks.into_iter();
+
+    loop {
+        let width = parallelism.width();
+        let wave: Vec<T> = (&mut remaining).take(width).collect();
+        if wave.is_empty() {
+            break;
+        }
+        let spacing = parallelism.min_spacing();
+
+        let wave_results: Vec<(Duration, Vec<U>)> = futures::stream::iter(wave)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let fut = fetch_chunk(chunk);
+                async move {
+                    if i > 0 {
+                        tor_rtcompat::task::sleep(spacing * i as u32).await;
+                    }
+                    let started = Instant::now();
+                    let items = fut.await;
+                    (started.elapsed(), items)
+                }
+            })
+            .buffer_unordered(width)
+            .collect()
+            .await;
+
+        for (elapsed, items) in wave_results {
+            parallelism.on_chunk_done(!items.is_empty(), elapsed);
+            results.extend(items);
+        }
+    }
+
+    results
+}
+
+/// Fetch a single chunk (at most 500) of the microdescriptors listed in
+/// `chunk`, and return whatever ones we got back and could use.
+///
+/// On a response that yields nothing usable, records the failure in
+/// `reputation`, and once that relay has done this repeatedly, retires
+/// the circuit to it via [`retire_bad_source`] and stops asking it for
+/// anything further.
+async fn fetch_md_chunk(
+    chunk: Vec<MDDigest>,
+    info: DirInfo<'_>,
+    circmgr: Arc<CircMgr>,
+    reputation: &CacheReputation,
+) -> Vec<(String, Microdesc)> {
+    info!("Fetching {} microdescriptors...", chunk.len());
+    let mut resource = tor_dirclient::request::MicrodescRequest::new();
+    for md in chunk.iter() {
+        resource.push(*md);
+    }
+    let want: HashSet<_> = chunk.iter().collect();
+
+    let res = tor_dirclient::get_resource(resource, info, circmgr.clone()).await;
+
+    let mut my_new_mds = Vec::new();
+
+    // XXXX-A1 log error.
+    if let Ok(response) = res {
+        let source = DocSource::DirServer {
+            id: response.source_id(),
+        };
+        let text = response.output();
+
+        for annot in MicrodescReader::new(&text, AllowAnnotations::AnnotationsNotAllowed) {
+            if let Ok(anno) = annot {
+                let txt = anno.within(&text).unwrap().to_string(); //XXXX ugly copy
+                let md = anno.into_microdesc();
+                if want.contains(md.digest()) {
+                    my_new_mds.push((txt, md))
+                } // XXXX-A1 warn if we didn't want this.
+            }
+            // XXXX-A1 log error
+        }
+
+        if my_new_mds.is_empty() {
+            // We got a response, but couldn't use any of it. That alone
+            // doesn't mean the relay is misbehaving -- it may just not
+            // have what we wanted yet -- so only retire the circuit once
+            // this keeps happening.
+            if reputation.record_failure(&source) {
+                retire_bad_source(&source, &circmgr);
+            }
+        }
+    }
+
+    info!("Received {} microdescriptors.", my_new_mds.len());
+    my_new_mds
+}
+
+/// Issue one round of requests for `want`, in chunks of up to 500, and
+/// return whatever microdescriptors came back.
+///
+/// Before issuing the round, asks `circmgr` to avoid any relay that
+/// `reputation` has already flagged as repeatedly misbehaving, so those
+/// relays aren't picked to serve this round's chunks either. How many
+/// chunks run at once is up to `parallelism`, which grows or shrinks
+/// between waves based on how each chunk goes; see
+/// [`dispatch_chunks_adaptive`].
+async fn download_mds_once(
+    want: &[MDDigest],
+    info: DirInfo<'_>,
+    circmgr: Arc<CircMgr>,
+    parallelism: &mut AdaptiveParallelism,
+    reputation: &CacheReputation,
+) -> Vec<(String, Microdesc)> {
+    circmgr.avoid_directory_caches(&reputation.banned());
+
+    // Break 'want' into the chunks we're going to fetch.
+    let chunksize: usize = std::cmp::min(500, (want.len() + 2) / 3).max(1);
+    let chunks: Vec<Vec<_>> = want.chunks(chunksize).map(|s| s.to_vec()).collect();
+
+    dispatch_chunks_adaptive(chunks, parallelism, |chunk| {
+        fetch_md_chunk(chunk, info, Arc::clone(&circmgr), reputation)
+    })
+    .await
+}
+
+/// Like [`download_mds_once`], but feeds each chunk's microdescriptors
+/// into `doc` as soon as it arrives, and stops issuing new chunks --
+/// dropping whatever's still in flight -- the moment `doc` reports
+/// [`PartialNetDir::have_enough_paths`]. Returns the digests we received
+/// and used.
+///
+/// This is what lets a client with a fast connection start building
+/// circuits as soon as it has enough microdescriptors, rather than
+/// waiting for every chunk of a download of up to several thousand
+/// microdescriptors to land.
+async fn download_mds_streaming(
+    want: &[MDDigest],
+    doc: &mut PartialNetDir,
+    info: DirInfo<'_>,
+    circmgr: Arc<CircMgr>,
+    parallelism: &mut AdaptiveParallelism,
+    reputation: &CacheReputation,
+) -> Vec<(String, MDDigest)> {
+    circmgr.avoid_directory_caches(&reputation.banned());
+
+    let chunksize: usize = std::cmp::min(500, (want.len() + 2) / 3).max(1);
+    let chunks: Vec<Vec<_>> = want.chunks(chunksize).map(|s| s.to_vec()).collect();
+    let mut remaining = chunks.into_iter();
+
+    let mut received = Vec::new();
+    'waves: loop {
+        let width = parallelism.width();
+        let wave: Vec<_> = (&mut remaining).take(width).collect();
+        if wave.is_empty() {
+            break;
+        }
+        let spacing = parallelism.min_spacing();
+
+        let mut stream = futures::stream::iter(wave)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let fut = fetch_md_chunk(chunk, info, Arc::clone(&circmgr), reputation);
+                async move {
+                    if i > 0 {
+                        tor_rtcompat::task::sleep(spacing * i as u32).await;
+                    }
+                    let started = Instant::now();
+                    let items = fut.await;
+                    (started.elapsed(), items)
+                }
+            })
+            .buffer_unordered(width);
+
+        while let Some((elapsed, chunk_mds)) = stream.next().await {
+            parallelism.on_chunk_done(!chunk_mds.is_empty(), elapsed);
+            for (txt, md) in chunk_mds {
+                let digest = *md.digest();
+                if doc.add_microdesc(md) {
+                    received.push((txt, digest));
+                }
+            }
+            if doc.have_enough_paths() {
+                // Dropping `stream` below cancels whatever chunks are
+                // still in flight: we already have enough to build
+                // circuits.
+                break 'waves;
+            }
+        }
+    }
+
+    received
+}
+
 /// Helper to fetch microdescriptors from the network and store them either
 /// into a PartialNetDir or a NetDir.
+///
+/// A single network round can easily miss some descriptors -- a busy
+/// cache that times out, a relay that's since gone offline -- so we keep
+/// asking for whatever's still missing, backing off between rounds
+/// according to `dl_schedule`, until either nothing's missing or the
+/// schedule runs out of attempts. Whatever digests we never managed to
+/// get back are returned to the caller, which can decide whether that
+/// still leaves enough to build circuits.
+///
+/// Tracks a fresh [`CacheReputation`] for the duration of this call, so a
+/// relay that keeps sending unusable responses stops being asked again
+/// partway through.
 async fn download_mds(
     mut missing: Vec<MDDigest>,
     mark_listed: SystemTime,
-    store: &Mutex<SqliteStore>,
+    dl_schedule: &DownloadSchedule,
+    store: &Mutex<DynStore>,
+    info: DirInfo<'_>,
+    circmgr: Arc<CircMgr>,
+) -> Result<(Vec<Microdesc>, Vec<MDDigest>)> {
+    missing.sort_unstable();
+    if missing.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    let mut parallelism = AdaptiveParallelism::new(
+        dl_schedule.parallelism() as usize,
+        dl_schedule.max_parallelism() as usize,
+    );
+    let mut want = missing;
+    let mut all_new_mds = Vec::new();
+    let mut schedule = dl_schedule.schedule();
+    let reputation = CacheReputation::new();
+
+    while schedule.more_attempts() {
+        let new_mds = download_mds_once(
+            &want,
+            info,
+            Arc::clone(&circmgr),
+            &mut parallelism,
+            &reputation,
+        )
+        .await;
+
+        let received: HashSet<MDDigest> = new_mds.iter().map(|(_, md)| *md.digest()).collect();
+        want.retain(|d| !received.contains(d));
+        all_new_mds.extend(new_mds);
+
+        if want.is_empty() {
+            break;
+        }
+        if let Some(delay) = schedule.next_delay(&mut rand::thread_rng()) {
+            info!("{} microdescriptors still missing; retrying...", want.len());
+            tor_rtcompat::task::sleep(delay).await;
+        }
+    }
+
+    // Now save it to the database
+    {
+        let to_store: Vec<(String, MDDigest)> = all_new_mds
+            .iter()
+            .map(|(txt, md)| (txt.clone(), *md.digest()))
+            .collect();
+        let mut w = store.lock().await;
+        w.store_microdescs(&to_store, mark_listed)?;
+    }
+
+    Ok((
+        all_new_mds.into_iter().map(|(_, md)| md).collect(),
+        want,
+    ))
+}
+
+/// Fetch microdescriptors from the network directly into `doc`, stopping
+/// as soon as `doc.have_enough_paths()` -- rather than waiting for every
+/// chunk across every retry round to finish -- and retrying whatever's
+/// still missing (if we don't yet have enough) according to `dl_schedule`.
+///
+/// Tracks a fresh [`CacheReputation`] for the duration of this call, so a
+/// relay that keeps sending unusable responses stops being asked again
+/// partway through.
+///
+/// Returns the digests that were never fetched, whether because we gave
+/// up early (we already had enough) or because the schedule ran out of
+/// attempts.
+async fn download_mds_into(
+    mut missing: Vec<MDDigest>,
+    mark_listed: SystemTime,
+    dl_schedule: &DownloadSchedule,
+    doc: &mut PartialNetDir,
+    store: &Mutex<DynStore>,
     info: DirInfo<'_>,
     circmgr: Arc<CircMgr>,
-) -> Result<Vec<Microdesc>> {
+) -> Result<Vec<MDDigest>> {
     missing.sort_unstable();
     if missing.is_empty() {
         return Ok(Vec::new());
     }
-    let chunksize: usize = std::cmp::min(500, (missing.len() + 2) / 3);
 
-    let n_parallel_requests = 4; // TODO make this configurable.
+    let mut parallelism = AdaptiveParallelism::new(
+        dl_schedule.parallelism() as usize,
+        dl_schedule.max_parallelism() as usize,
+    );
+    let mut want = missing;
+    let mut schedule = dl_schedule.schedule();
+    let reputation = CacheReputation::new();
+
+    while schedule.more_attempts() {
+        let received = download_mds_streaming(
+            &want,
+            doc,
+            info,
+            Arc::clone(&circmgr),
+            &mut parallelism,
+            &reputation,
+        )
+        .await;
 
-    // Now we're going to fetch the descriptors up to 500 at a time,
-    // in up to n_parallel_requests requests.
+        if !received.is_empty() {
+            let mut w = store.lock().await;
+            w.store_microdescs(&received, mark_listed)?;
+        }
 
-    // TODO: we should maybe exit early if we wind up with a working
-    // list.
-    // TODO: we should maybe try to keep concurrent requests on
-    // separate circuits?
+        let got: HashSet<MDDigest> = received.into_iter().map(|(_, d)| d).collect();
+        want.retain(|d| !got.contains(d));
 
-    // Break 'missing' into the chunks we're going to fetch.
-    // XXXX: I hate having to do all these copies, but otherwise I
-    // wind up with lifetime issues.
-    let missing: Vec<Vec<_>> = missing[..].chunks(chunksize).map(|s| s.to_vec()).collect();
+        if want.is_empty() || doc.have_enough_paths() {
+            break;
+        }
+        if let Some(delay) = schedule.next_delay(&mut rand::thread_rng()) {
+            info!("{} microdescriptors still missing; retrying...", want.len());
+            tor_rtcompat::task::sleep(delay).await;
+        }
+    }
 
-    let new_mds: Vec<_> = futures::stream::iter(missing.into_iter())
-        .map(|chunk| {
-            let cm = Arc::clone(&circmgr);
-            async move {
-                info!("Fetching {} microdescriptors...", chunksize);
-                let mut resource = tor_dirclient::request::MicrodescRequest::new();
-                for md in chunk.iter() {
-                    resource.push(*md);
-                }
-                let want: HashSet<_> = chunk.iter().collect();
-
-                let res = tor_dirclient::get_resource(resource, info, cm).await;
-
-                let mut my_new_mds = Vec::new();
-
-                // XXXX-A1 log error.
-                if let Ok(response) = res {
-                    let text = response.output();
-                    // XXXX-A1 In some of the below error cases we should
-                    // retire the circuit to the cache that gave us
-                    // this stuff.
-
-                    for annot in
-                        MicrodescReader::new(&text, AllowAnnotations::AnnotationsNotAllowed)
-                    {
-                        if let Ok(anno) = annot {
-                            let txt = anno.within(&text).unwrap().to_string(); //XXXX ugly copy
-                            let md = anno.into_microdesc();
-                            if want.contains(md.digest()) {
-                                my_new_mds.push((txt, md))
-                            } // XXXX-A1 warn if we didn't want this.
-                        }
-                        // XXXX-A1 log error
-                    }
-                }
+    Ok(want)
+}
+
+/// Fetch a single chunk (at most 500) of the router descriptors listed in
+/// `chunk`, verifying each one against its expected digest, and return
+/// whatever ones we got back and could use.
+///
+/// This is [`fetch_md_chunk`]'s counterpart for full router descriptors:
+/// same chunking, same [`retire_bad_source`] and [`CacheReputation`]
+/// bookkeeping on a response that yields nothing usable.
+#[cfg(feature = "routerdesc")]
+async fn fetch_rd_chunk(
+    chunk: Vec<RdDigest>,
+    info: DirInfo<'_>,
+    circmgr: Arc<CircMgr>,
+    reputation: &CacheReputation,
+) -> Vec<(String, SystemTime, RdDigest)> {
+    info!("Fetching {} router descriptors...", chunk.len());
+    let mut resource = tor_dirclient::request::RouterDescRequest::new();
+    for rd in chunk.iter() {
+        resource.push(*rd);
+    }
+    let want: HashSet<_> = chunk.iter().collect();
+
+    let res = tor_dirclient::get_resource(resource, info, circmgr.clone()).await;
+
+    let mut my_new_rds = Vec::new();
+
+    // XXXX-A1 log error.
+    if let Ok(response) = res {
+        let source = DocSource::DirServer {
+            id: response.source_id(),
+        };
+        let text = response.output();
 
-                info!("Received {} microdescriptors.", my_new_mds.len());
-                my_new_mds
+        for parsed in RouterDescReader::new(&text) {
+            if let Ok(rd) = parsed {
+                let txt = rd.within(&text).unwrap().to_string(); //XXXX ugly copy
+                let digest = *rd.digest();
+                let published = rd.published();
+                if want.contains(&digest) {
+                    my_new_rds.push((txt, published, digest))
+                } // XXXX-A1 warn if we didn't want this.
             }
-        })
-        .buffer_unordered(n_parallel_requests)
-        .collect()
+            // XXXX-A1 log error
+        }
+
+        if my_new_rds.is_empty() {
+            // We got a response, but couldn't use any of it. As with
+            // fetch_md_chunk, only retire the circuit once this keeps
+            // happening, rather than on the first empty response.
+            if reputation.record_failure(&source) {
+                retire_bad_source(&source, &circmgr);
+            }
+        }
+    }
+
+    info!("Received {} router descriptors.", my_new_rds.len());
+    my_new_rds
+}
+
+/// Issue one round of requests for `want`, in chunks of up to 500, and
+/// return whatever router descriptors came back. See
+/// [`download_mds_once`], which this mirrors.
+#[cfg(feature = "routerdesc")]
+async fn download_routerdescs_once(
+    want: &[RdDigest],
+    info: DirInfo<'_>,
+    circmgr: Arc<CircMgr>,
+    parallelism: &mut AdaptiveParallelism,
+    reputation: &CacheReputation,
+) -> Vec<(String, SystemTime, RdDigest)> {
+    circmgr.avoid_directory_caches(&reputation.banned());
+
+    let chunksize: usize = std::cmp::min(500, (want.len() + 2) / 3).max(1);
+    let chunks: Vec<Vec<_>> = want.chunks(chunksize).map(|s| s.to_vec()).collect();
+
+    dispatch_chunks_adaptive(chunks, parallelism, |chunk| {
+        fetch_rd_chunk(chunk, info, Arc::clone(&circmgr), reputation)
+    })
+    .await
+}
+
+/// Fetch the router descriptors in `missing` from the network and store
+/// them via [`storage::Store::store_routerdescs`].
+///
+/// This is [`download_mds`]'s counterpart for clients that want full
+/// router descriptors rather than (or in addition to) microdescriptors:
+/// it reuses the same chunking, adaptive parallelism, retry-with-backoff,
+/// and [`CacheReputation`] tracking, but has no early-exit equivalent to
+/// [`download_mds_into`], since router descriptors aren't needed to build
+/// circuits and there's no `PartialNetDir` to feed as they arrive.
+///
+/// Whatever digests we never managed to get back are returned to the
+/// caller alongside the ones we did.
+#[cfg(feature = "routerdesc")]
+async fn download_routerdescs(
+    mut missing: Vec<RdDigest>,
+    mark_listed: SystemTime,
+    dl_schedule: &DownloadSchedule,
+    store: &Mutex<DynStore>,
+    info: DirInfo<'_>,
+    circmgr: Arc<CircMgr>,
+) -> Result<(Vec<RdDigest>, Vec<RdDigest>)> {
+    missing.sort_unstable();
+    if missing.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    let mut parallelism = AdaptiveParallelism::new(
+        dl_schedule.parallelism() as usize,
+        dl_schedule.max_parallelism() as usize,
+    );
+    let mut want = missing;
+    let mut all_new_rds = Vec::new();
+    let mut schedule = dl_schedule.schedule();
+    let reputation = CacheReputation::new();
+
+    while schedule.more_attempts() {
+        let new_rds = download_routerdescs_once(
+            &want,
+            info,
+            Arc::clone(&circmgr),
+            &mut parallelism,
+            &reputation,
+        )
         .await;
 
-    // Now save it to the database
+        let received: HashSet<RdDigest> = new_rds.iter().map(|(_, _, d)| *d).collect();
+        want.retain(|d| !received.contains(d));
+        all_new_rds.extend(new_rds);
+
+        if want.is_empty() {
+            break;
+        }
+        if let Some(delay) = schedule.next_delay(&mut rand::thread_rng()) {
+            info!(
+                "{} router descriptors still missing; retrying...",
+                want.len()
+            );
+            tor_rtcompat::task::sleep(delay).await;
+        }
+    }
+
     {
         let mut w = store.lock().await;
-        w.store_microdescs(
-            new_mds
-                .iter()
-                .flatten()
-                .map(|(txt, md)| (&txt[..], md.digest())),
-            mark_listed,
-        )?;
+        w.store_routerdescs(&all_new_rds, mark_listed)?;
     }
 
-    Ok(new_mds.into_iter().flatten().map(|(_, md)| md).collect())
+    Ok((
+        all_new_rds.into_iter().map(|(_, _, d)| d).collect(),
+        want,
+    ))
 }
@@ -0,0 +1,75 @@
+//! Track which directory caches have recently served us bad microdescriptor
+//! responses, so repeatedly-misbehaving relays can be skipped instead of
+//! tried again on every chunk.
+//!
+//! A single unusable response often just means the relay didn't have what
+//! we wanted yet, not that it's misbehaving, so
+//! [`retire_bad_source`](crate::retire_bad_source) is only invoked once
+//! [`record_failure`](CacheReputation::record_failure) reports that a
+//! relay has failed [`MAX_FAILURES`] times. [`CacheReputation`]
+//! remembers that count across chunks and rounds within a single
+//! download, so a relay that keeps failing stops being offered new
+//! requests at all, rather than just losing its current circuit. A good
+//! response does not reset a relay's count.
+
+use crate::docid::DocSource;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tor_llcrypto::pk::rsa::RSAIdentity;
+
+/// How many bad responses, in all, we'll tolerate from a single relay
+/// before we start avoiding it for the rest of this download.
+const MAX_FAILURES: u32 = 2;
+
+/// Per-relay counts of bad directory responses, scoped to a single
+/// bootstrap download (a fresh one is made for each call to
+/// [`download_mds`](crate::download_mds) or
+/// [`download_mds_into`](crate::download_mds_into)).
+#[derive(Default)]
+pub(crate) struct CacheReputation {
+    /// Number of bad responses we've seen from each relay during this
+    /// download.
+    failures: Mutex<HashMap<RSAIdentity, u32>>,
+}
+
+impl CacheReputation {
+    /// Construct a fresh, empty `CacheReputation`.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `source` just gave us a response we couldn't use.
+    ///
+    /// Has no effect for a [`DocSource`] that doesn't identify a relay
+    /// (`LocalCache`, or a `DirServer` whose identity we don't know), and
+    /// returns `false` in that case.
+    ///
+    /// Returns `true` once `source` has now failed at least
+    /// [`MAX_FAILURES`] times, so callers can debounce a harsher
+    /// response (like retiring the circuit) until a relay has shown a
+    /// pattern of bad responses, rather than reacting to the first one --
+    /// which just as often means the relay simply didn't have what we
+    /// wanted yet, not that it's misbehaving.
+    pub(crate) fn record_failure(&self, source: &DocSource) -> bool {
+        if let DocSource::DirServer { id: Some(id) } = source {
+            let mut failures = self.failures.lock().expect("lock poisoned");
+            let count = failures.entry(id.clone()).or_insert(0);
+            *count += 1;
+            *count >= MAX_FAILURES
+        } else {
+            false
+        }
+    }
+
+    /// Return every relay that has failed often enough that we should
+    /// stop handing it further requests.
+    pub(crate) fn banned(&self) -> Vec<RSAIdentity> {
+        self.failures
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .filter(|(_, &count)| count >= MAX_FAILURES)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,142 @@
+//! Configurable retry schedules for downloading directory documents.
+
+use rand::Rng;
+use std::time::Duration;
+
+/// A schedule for retrying a download: how many attempts to make, how long
+/// to wait between them, and how many of them may run in parallel.
+///
+/// Previously, every download method in this crate hardcoded
+/// `n_retries = 3` and a fresh backoff timer, each with a `// XXXX make
+/// this configurable` note attached. A `DownloadSchedule` is that
+/// configuration, made explicit and settable per document kind via
+/// [`NetDirConfigBuilder`](crate::NetDirConfigBuilder).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DownloadSchedule {
+    /// The total number of attempts to make before giving up.
+    attempts: u32,
+    /// The upper bound of the random delay to wait after the first failed
+    /// attempt.
+    initial_delay: Duration,
+    /// The largest delay we'll wait between attempts, no matter how many
+    /// rounds of backoff we've gone through.
+    max_delay: Duration,
+    /// The number of requests for disjoint subsets of a document that may
+    /// be in flight at once when an attempt starts.
+    parallelism: u16,
+    /// The most requests for disjoint subsets of a document that may be
+    /// in flight at once, no matter how far the adaptive controller in
+    /// [`crate::parallelism`] has grown it.
+    max_parallelism: u16,
+}
+
+impl Default for DownloadSchedule {
+    fn default() -> Self {
+        DownloadSchedule {
+            attempts: 3,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60 * 60),
+            parallelism: 1,
+            max_parallelism: 16,
+        }
+    }
+}
+
+impl DownloadSchedule {
+    /// Create a new `DownloadSchedule` that makes up to `attempts`
+    /// attempts, waiting a random delay below `initial_delay` (with that
+    /// bound doubling on each subsequent failure) between them, with up to
+    /// `parallelism` concurrent
+    /// requests per attempt.
+    pub fn new(attempts: u32, initial_delay: Duration, parallelism: u16) -> Self {
+        DownloadSchedule {
+            attempts,
+            initial_delay,
+            parallelism: parallelism.max(1),
+            ..Self::default()
+        }
+    }
+
+    /// Set the largest delay this schedule will ever wait between
+    /// attempts, regardless of how many rounds of backoff have elapsed.
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Set the most requests for disjoint subsets of a document that the
+    /// adaptive parallelism controller is ever allowed to grow to.
+    pub fn with_max_parallelism(mut self, max_parallelism: u16) -> Self {
+        self.max_parallelism = max_parallelism;
+        self
+    }
+
+    /// Return the number of requests for disjoint subsets of a document
+    /// that may run in parallel when an attempt starts.
+    pub fn parallelism(&self) -> u16 {
+        self.parallelism.max(1)
+    }
+
+    /// Return the most requests for disjoint subsets of a document that
+    /// may ever run in parallel during an attempt.
+    pub fn max_parallelism(&self) -> u16 {
+        self.max_parallelism.max(self.parallelism())
+    }
+
+    /// Return a [`RetrySchedule`] that tracks the attempts this schedule
+    /// allows.
+    ///
+    /// A `RetrySchedule` is not an [`Iterator`]: call
+    /// [`RetrySchedule::more_attempts`] before each attempt, and
+    /// [`RetrySchedule::next_delay`] after a failed attempt to learn how
+    /// long to wait (if at all) before the next one.
+    pub fn schedule(&self) -> RetrySchedule {
+        RetrySchedule {
+            schedule: *self,
+            remaining: self.attempts,
+            base: self.initial_delay,
+        }
+    }
+}
+
+/// A in-progress run through a [`DownloadSchedule`].
+///
+/// Tracks how many attempts remain, and produces the decorrelated,
+/// fully-jittered backoff delay to wait after each failed attempt: the
+/// delay is drawn uniformly from `[0, base)`, where `base` doubles after
+/// every failure, up to the schedule's configured maximum.
+pub struct RetrySchedule {
+    /// The schedule we're iterating over.
+    schedule: DownloadSchedule,
+    /// The number of attempts left, including the one about to be made.
+    remaining: u32,
+    /// The current backoff base.
+    base: Duration,
+}
+
+impl RetrySchedule {
+    /// Return true if there is at least one attempt left to make.
+    pub fn more_attempts(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// Record that an attempt was just made, and return the delay to wait
+    /// before the next one -- or `None` if there are no attempts left.
+    pub fn next_delay<R: Rng>(&mut self, rng: &mut R) -> Option<Duration> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let base_ms = u64::try_from(self.base.as_millis()).unwrap_or(u64::MAX).max(1);
+        let delay = Duration::from_millis(rng.gen_range(0..base_ms));
+
+        let max_ms = u64::try_from(self.schedule.max_delay.as_millis()).unwrap_or(u64::MAX);
+        self.base = Duration::from_millis(base_ms.saturating_mul(2).min(max_ms));
+
+        Some(delay)
+    }
+}
@@ -0,0 +1,161 @@
+//! An ephemeral, in-memory [`Store`](super::Store) implementation.
+//!
+//! Unlike [`SqliteStore`](super::sqlite::SqliteStore), a [`MemoryStore`]
+//! never touches the filesystem: everything it holds is lost when it's
+//! dropped. That makes it convenient for tests, and for embedders that
+//! don't want (or aren't able) to persist a directory cache to disk.
+
+use crate::docid::ConsensusFlavor;
+use crate::docmeta::{AuthCertMeta, ConsensusMeta};
+use crate::storage::{ExpirationConfig, InputString, Store};
+use crate::Result;
+
+use tor_netdoc::doc::authcert::AuthCertKeyIds;
+use tor_netdoc::doc::microdesc::MDDigest;
+#[cfg(feature = "routerdesc")]
+use tor_netdoc::doc::routerdesc::RdDigest;
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// A [`Store`] that keeps everything in memory, and forgets it all on drop.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// The most recently stored consensus of each flavor, if any, and
+    /// whether it's pending.
+    consensus: HashMap<ConsensusFlavor, (ConsensusMeta, bool, String)>,
+    /// Authority certificates we've been given, keyed by their identity keys.
+    authcerts: HashMap<AuthCertKeyIds, (AuthCertMeta, String)>,
+    /// Microdescriptors we've been given, keyed by digest.
+    microdescs: HashMap<MDDigest, String>,
+    /// Router descriptors we've been given, keyed by digest.
+    #[cfg(feature = "routerdesc")]
+    routerdescs: HashMap<RdDigest, String>,
+}
+
+impl MemoryStore {
+    /// Construct a new, empty `MemoryStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn expire_all(&mut self, _expiration: &ExpirationConfig) -> Result<()> {
+        // Nothing to expire: we don't track timestamps well enough to know
+        // what's stale, and everything here disappears once we're dropped.
+        Ok(())
+    }
+
+    fn latest_consensus(
+        &self,
+        flavor: ConsensusFlavor,
+        pending: bool,
+    ) -> Result<Option<InputString>> {
+        Ok(self.consensus.get(&flavor).and_then(|(_, p, text)| {
+            (pending || !p).then(|| InputString::from(text.clone()))
+        }))
+    }
+
+    fn latest_consensus_time(
+        &self,
+        flavor: ConsensusFlavor,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+        Ok(self
+            .consensus
+            .get(&flavor)
+            .map(|(meta, ..)| meta.lifetime().valid_after().into()))
+    }
+
+    fn store_consensus(
+        &mut self,
+        cmeta: &ConsensusMeta,
+        flavor: ConsensusFlavor,
+        pending: bool,
+        contents: &str,
+        _expiration: &ExpirationConfig,
+    ) -> Result<()> {
+        self.consensus
+            .insert(flavor, (cmeta.clone(), pending, contents.to_owned()));
+        Ok(())
+    }
+
+    fn mark_consensus_usable(&mut self, cmeta: &ConsensusMeta) -> Result<()> {
+        for (meta, pending, _) in self.consensus.values_mut() {
+            if meta.sha3_256_of_whole() == cmeta.sha3_256_of_whole() {
+                *pending = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn authcerts(&self, certs: &[AuthCertKeyIds]) -> Result<HashMap<AuthCertKeyIds, String>> {
+        Ok(certs
+            .iter()
+            .filter_map(|ids| {
+                self.authcerts
+                    .get(ids)
+                    .map(|(_, content)| (ids.clone(), content.clone()))
+            })
+            .collect())
+    }
+
+    fn store_authcerts(&mut self, certs: &[(AuthCertMeta, &str)]) -> Result<()> {
+        for (meta, content) in certs {
+            self.authcerts
+                .insert(meta.key_ids().clone(), (meta.clone(), (*content).to_owned()));
+        }
+        Ok(())
+    }
+
+    fn microdescs(&self, digests: &[MDDigest]) -> Result<HashMap<MDDigest, String>> {
+        Ok(digests
+            .iter()
+            .filter_map(|d| self.microdescs.get(d).map(|text| (*d, text.clone())))
+            .collect())
+    }
+
+    fn update_microdescs_listed(
+        &mut self,
+        _digests: &[MDDigest],
+        _when: SystemTime,
+    ) -> Result<()> {
+        // We don't track last-listed times; everything we hold is kept
+        // until it's explicitly overwritten or we're dropped.
+        Ok(())
+    }
+
+    fn store_microdescs(&mut self, mds: &[(String, MDDigest)], _when: SystemTime) -> Result<()> {
+        for (text, digest) in mds {
+            self.microdescs.insert(*digest, text.clone());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "routerdesc")]
+    fn store_routerdescs(
+        &mut self,
+        descs: &[(String, SystemTime, RdDigest)],
+        _when: SystemTime,
+    ) -> Result<()> {
+        for (text, _published, digest) in descs {
+            self.routerdescs.insert(*digest, text.clone());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "routerdesc")]
+    fn routerdescs(&self, digests: &[RdDigest]) -> Result<HashMap<RdDigest, String>> {
+        Ok(digests
+            .iter()
+            .filter_map(|d| self.routerdescs.get(d).map(|text| (*d, text.clone())))
+            .collect())
+    }
+
+    #[cfg(feature = "routerdesc")]
+    fn update_routerdescs_listed(&mut self, _digests: &[RdDigest], _when: SystemTime) -> Result<()> {
+        // We don't track last-listed times; everything we hold is kept
+        // until it's explicitly overwritten or we're dropped.
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! Abstraction over how we cache directory documents on disk.
+//!
+//! [`Store`] is the interface [`DirMgr`](crate::DirMgr) uses to read and
+//! write cached consensus, certificate, and microdescriptor data; it knows
+//! nothing about SQL or sqlite.  [`sqlite::SqliteStore`] is (for now) our
+//! only real implementation, but a [`DynStore`] lets `DirMgr` be built
+//! against any implementation -- including, for tests, [`MemoryStore`], an
+//! ephemeral implementation that never touches the filesystem.
+
+mod memory;
+pub mod sqlite;
+
+use crate::docid::{ConsensusFlavor, DocId, DocumentText};
+use crate::docmeta::{AuthCertMeta, ConsensusMeta};
+use crate::{Error, Result};
+
+use tor_netdoc::doc::authcert::AuthCertKeyIds;
+use tor_netdoc::doc::microdesc::MDDigest;
+#[cfg(feature = "routerdesc")]
+use tor_netdoc::doc::routerdesc::RdDigest;
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+pub use memory::MemoryStore;
+
+/// A boxed [`Store`], so that [`DirMgr`](crate::DirMgr) doesn't need to be
+/// generic over (or hardwired to) a particular cache implementation.
+pub type DynStore = Box<dyn Store + Send>;
+
+/// Configuration for how aggressively [`Store::expire_all`] reclaims
+/// cached documents.
+///
+/// Previously these were fixed constants (`"-3 months"` for
+/// microdescriptors and router descriptors, `"-2 days"` for consensuses)
+/// baked directly into the cache's SQL. Making them an explicit struct
+/// lets an embedder on a constrained device shrink the cache aggressively,
+/// and lets test code exercise expiry deterministically instead of waiting
+/// on real wall-clock time.
+#[derive(Clone, Debug)]
+pub struct ExpirationConfig {
+    /// How long past its `valid_until` time we keep a consensus's metadata
+    /// row before it's eligible for removal.
+    pub consensuses: chrono::Duration,
+    /// How long past its `valid_until` time we keep a consensus's raw blob
+    /// on disk -- longer than `consensuses`, so a diff can still name an
+    /// old base as its `hash-prev` for a little while after the base's
+    /// row is gone.
+    pub consensus_blobs: chrono::Duration,
+    /// How long past its `expires` time we keep an authority certificate.
+    pub authcerts: chrono::Duration,
+    /// How long we keep a microdescriptor that hasn't been relisted in any
+    /// consensus.
+    pub microdescs: chrono::Duration,
+    /// How long we keep a router descriptor that hasn't been relisted in
+    /// any consensus.
+    pub routerdescs: chrono::Duration,
+}
+
+impl Default for ExpirationConfig {
+    fn default() -> Self {
+        ExpirationConfig {
+            consensuses: chrono::Duration::days(2),
+            consensus_blobs: chrono::Duration::days(4),
+            authcerts: chrono::Duration::seconds(0),
+            microdescs: chrono::Duration::days(3 * 30),
+            routerdescs: chrono::Duration::days(3 * 30),
+        }
+    }
+}
+
+/// A directory cache, holding consensus, certificate, and microdescriptor
+/// documents on behalf of a [`DirMgr`](crate::DirMgr).
+///
+/// This is deliberately a plain (object-safe) trait rather than a set of
+/// inherent methods on [`sqlite::SqliteStore`], so that it can be used as
+/// `dyn Store` behind a [`DynStore`].
+pub trait Store {
+    /// Delete all completely-expired objects from the cache, according to
+    /// `expiration`.
+    fn expire_all(&mut self, expiration: &ExpirationConfig) -> Result<()>;
+
+    /// Load the latest consensus of a given `flavor` from the cache. If
+    /// `pending` is true, we can return a consensus that hasn't got enough
+    /// microdescs yet. Otherwise, we only want a consensus we have full
+    /// directory information for.
+    fn latest_consensus(
+        &self,
+        flavor: ConsensusFlavor,
+        pending: bool,
+    ) -> Result<Option<InputString>>;
+
+    /// Return the date when the most recent consensus of a given `flavor`
+    /// in the cache became valid, if any.
+    fn latest_consensus_time(
+        &self,
+        flavor: ConsensusFlavor,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>>;
+
+    /// Save a consensus document of a given `flavor` to the cache.
+    ///
+    /// `expiration.consensus_blobs` governs how long past the consensus's
+    /// own `valid_until` its raw blob is kept on disk; see
+    /// [`ExpirationConfig`] for why that's longer than the metadata row's
+    /// own retention.
+    fn store_consensus(
+        &mut self,
+        cmeta: &ConsensusMeta,
+        flavor: ConsensusFlavor,
+        pending: bool,
+        contents: &str,
+        expiration: &ExpirationConfig,
+    ) -> Result<()>;
+
+    /// Mark the consensus generated from `cmeta` as no longer pending.
+    fn mark_consensus_usable(&mut self, cmeta: &ConsensusMeta) -> Result<()>;
+
+    /// Read all of the specified authority certs from the cache.
+    fn authcerts(&self, certs: &[AuthCertKeyIds]) -> Result<HashMap<AuthCertKeyIds, String>>;
+
+    /// Save a list of authority certificates to the cache.
+    fn store_authcerts(&mut self, certs: &[(AuthCertMeta, &str)]) -> Result<()>;
+
+    /// Read all the microdescriptors listed in `digests` from the cache.
+    fn microdescs(&self, digests: &[MDDigest]) -> Result<HashMap<MDDigest, String>>;
+
+    /// Update the `last-listed` time of every microdescriptor in `digests`
+    /// to `when` or later.
+    fn update_microdescs_listed(&mut self, digests: &[MDDigest], when: SystemTime) -> Result<()>;
+
+    /// Store every `(text, digest)` microdescriptor in `mds` into the
+    /// cache, and say that it was last listed at `when`.
+    fn store_microdescs(&mut self, mds: &[(String, MDDigest)], when: SystemTime) -> Result<()>;
+
+    /// Store every `(text, published, digest)` router descriptor in
+    /// `descs` into the cache, and say that it was last listed at `when`.
+    ///
+    /// Gated on the `routerdesc` feature, like the rest of the full
+    /// router-descriptor cache: builds that don't want this extra
+    /// storage (and network cost) don't pay for it.
+    #[cfg(feature = "routerdesc")]
+    fn store_routerdescs(
+        &mut self,
+        descs: &[(String, SystemTime, RdDigest)],
+        when: SystemTime,
+    ) -> Result<()>;
+
+    /// Read all the router descriptors listed in `digests` from the cache.
+    #[cfg(feature = "routerdesc")]
+    fn routerdescs(&self, digests: &[RdDigest]) -> Result<HashMap<RdDigest, String>>;
+
+    /// Update the `last-listed` time of every router descriptor in
+    /// `digests` to `when` or later.
+    #[cfg(feature = "routerdesc")]
+    fn update_routerdescs_listed(&mut self, digests: &[RdDigest], when: SystemTime) -> Result<()>;
+
+    /// Look up every document named by `ids` in the cache, in one pass.
+    ///
+    /// This handles every [`DocId`] kind, so that a caller doesn't need to
+    /// know which underlying table (or digest lookup) backs each kind of
+    /// document; a `LatestConsensus` id may return a consensus that is
+    /// still pending. A missing document is simply absent from the result,
+    /// rather than an error.
+    fn lookup(&self, ids: &[DocId]) -> Result<Vec<DocumentText>> {
+        let mut result = Vec::new();
+
+        let mut want_consensus: Option<ConsensusFlavor> = None;
+        let mut want_certs = Vec::new();
+        let mut want_mds = Vec::new();
+        for id in ids {
+            match id {
+                DocId::LatestConsensus { flavor } => want_consensus = Some(*flavor),
+                DocId::AuthCert(keyid) => want_certs.push(keyid.clone()),
+                DocId::Microdesc(digest) => want_mds.push(*digest),
+            }
+        }
+
+        if let Some(flavor) = want_consensus {
+            if let Some(text) = self.latest_consensus(flavor, true)? {
+                result.push(DocumentText::new(DocId::LatestConsensus { flavor }, text));
+            }
+        }
+
+        if !want_certs.is_empty() {
+            for (keyid, text) in self.authcerts(&want_certs)? {
+                result.push(DocumentText::new(DocId::AuthCert(keyid), InputString::from(text)));
+            }
+        }
+
+        if !want_mds.is_empty() {
+            for (digest, text) in self.microdescs(&want_mds)? {
+                result.push(DocumentText::new(
+                    DocId::Microdesc(digest),
+                    InputString::from(text),
+                ));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// A string that we've read from a file in our cache.
+///
+/// An uncompressed blob can be mapped into memory with no copy at all; a
+/// compressed one has to be decompressed into a buffer of its own on the
+/// way in. Either way, [`InputString::as_str`] is the only thing a caller
+/// needs.
+pub struct InputString {
+    /// Where the text actually lives.
+    buf: InputBuf,
+}
+
+/// The backing storage for an [`InputString`].
+enum InputBuf {
+    /// Mapped directly from an uncompressed file on disk.
+    #[cfg(feature = "mmap")]
+    Mapped(Mmap),
+    /// Decompressed, or otherwise not backed by a file we can map.
+    Owned(String),
+}
+
+impl InputString {
+    /// Return the contents of this string as a `&str`.
+    pub fn as_str(&self) -> Result<&str> {
+        match &self.buf {
+            #[cfg(feature = "mmap")]
+            InputBuf::Mapped(m) => std::str::from_utf8(m)
+                .map_err(|_| Error::CacheCorruption("Mapped blob is not valid UTF-8").into()),
+            InputBuf::Owned(s) => Ok(s.as_str()),
+        }
+    }
+}
+
+impl From<String> for InputString {
+    fn from(text: String) -> Self {
+        InputString {
+            buf: InputBuf::Owned(text),
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl From<Mmap> for InputString {
+    fn from(mapped: Mmap) -> Self {
+        InputString {
+            buf: InputBuf::Mapped(mapped),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! A Tokio codec for relay messages.
+//!
+//! Wraps the manual `encode`/`decode` methods on [`RelayCell`] in
+//! [`tokio_util::codec::Encoder`]/[`Decoder`] impls, so a circuit's stream of
+//! decrypted cell bodies can be driven as an ordinary `Stream`/`Sink` of
+//! [`RelayCell`] instead of being framed by hand.
+
+use super::msg::RelayCell;
+use crate::chancell::CELL_DATA_LEN;
+use crate::crypto::cell::RelayCellBody;
+use bytes::BytesMut;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, Rng};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A Tokio codec that frames a byte stream of decrypted relay-cell bodies
+/// into [`RelayCell`]s, and back.
+///
+/// Each frame is exactly [`CELL_DATA_LEN`] bytes long, matching the fixed
+/// size of a (decrypted) RELAY or RELAY_EARLY cell body. This codec performs
+/// no cryptography of its own: it's meant to sit on top of a circuit's
+/// already-decrypted cell stream, not directly on a channel's wire bytes.
+pub struct RelayCellCodec<R = OsRng> {
+    /// Source of randomness used to pad encoded cells.
+    rng: R,
+}
+
+impl Default for RelayCellCodec<OsRng> {
+    fn default() -> Self {
+        RelayCellCodec { rng: OsRng }
+    }
+}
+
+impl<R: Rng + CryptoRng> RelayCellCodec<R> {
+    /// Create a new codec that uses `rng` to pad encoded cells.
+    pub fn new(rng: R) -> Self {
+        RelayCellCodec { rng }
+    }
+}
+
+impl<R> Decoder for RelayCellCodec<R> {
+    type Item = RelayCell;
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < CELL_DATA_LEN {
+            return Ok(None);
+        }
+        let frame = src.split_to(CELL_DATA_LEN);
+        let mut raw = [0_u8; CELL_DATA_LEN];
+        raw.copy_from_slice(&frame);
+        let body: RelayCellBody = raw.into();
+        Ok(Some(RelayCell::decode(body)?))
+    }
+}
+
+impl<R: Rng + CryptoRng> Encoder<RelayCell> for RelayCellCodec<R> {
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: RelayCell, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let body = item.encode(&mut self.rng)?;
+        dst.extend_from_slice(body.as_ref());
+        Ok(())
+    }
+}
@@ -14,9 +14,122 @@ use tor_bytes::{Readable, Reader, Writeable, Writer};
 use tor_linkspec::LinkSpec;
 use tor_llcrypto::pk::rsa::RSAIdentity;
 
-use arrayref::array_mut_ref;
 use rand::{CryptoRng, Rng};
 
+/// A [`Writer`] that encodes directly into a preallocated, fixed-size
+/// buffer, instead of growing a heap-allocated `Vec`.
+///
+/// Used by [`RelayCell::encode`] to avoid the extra allocate-then-copy pass
+/// that building a `Vec` and copying it into the cell body would require.
+/// If a write would overflow the buffer, it's silently dropped and
+/// `overflowed()` starts returning `true`; callers must check it once
+/// they're done writing.
+struct CellWriter<'a> {
+    /// The buffer we're encoding into.
+    buf: &'a mut [u8],
+    /// The number of bytes written so far.
+    pos: usize,
+    /// Set once a write would have overflowed `buf`.
+    overflowed: bool,
+}
+
+impl<'a> CellWriter<'a> {
+    /// Start encoding into `buf`, from the beginning.
+    fn new(buf: &'a mut [u8]) -> Self {
+        CellWriter {
+            buf,
+            pos: 0,
+            overflowed: false,
+        }
+    }
+    /// Return the number of bytes written so far.
+    fn position(&self) -> usize {
+        self.pos
+    }
+    /// Return true if some write was too large to fit in the buffer.
+    fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl<'a> Writer for CellWriter<'a> {
+    fn write_all(&mut self, bytes: &[u8]) {
+        let end = self.pos + bytes.len();
+        if self.overflowed || end > self.buf.len() {
+            self.overflowed = true;
+            return;
+        }
+        self.buf[self.pos..end].copy_from_slice(bytes);
+        self.pos = end;
+    }
+}
+
+/// How the unused tail of an encoded [`RelayCell`]'s payload should be filled.
+///
+/// Tor's wire format for RELAY cells leaves the bytes after the encoded
+/// message unspecified beyond "padding"; per `tor-spec.txt` section 6.1,
+/// real implementations fill them with random data so that padded and
+/// unpadded cells can't be told apart by an observer on the wire.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PaddingStyle {
+    /// Fill the padding with cryptographically random bytes. This is what
+    /// the rest of the Tor network expects, and the only style that
+    /// actually resists fingerprinting; it's the default.
+    Random,
+    /// Fill the padding with zero bytes. Mainly useful for tests that want
+    /// deterministic, human-readable cell bodies.
+    Zero,
+}
+
+impl Default for PaddingStyle {
+    fn default() -> Self {
+        PaddingStyle::Random
+    }
+}
+
+/// A policy describing how [`RelayCell::encode_with_padding`] should pad the
+/// unused tail of an encoded cell.
+///
+/// Previously, [`RelayCell::encode`] padded every cell with a hard-coded
+/// `MIN_SPACE_BEFORE_PADDING = 4` constant and a comment admitting that the
+/// value needed to be pinned down more exactly. This type makes that
+/// spacing (and the fill style) an explicit, adjustable policy instead, so
+/// it can be set to match whatever the spec or a given test requires
+/// without editing `encode` itself.
+#[derive(Clone, Copy, Debug)]
+pub struct CellPaddingPolicy {
+    /// How many zero-valued bytes must immediately follow the encoded
+    /// message, before any randomized padding begins.
+    min_zero_bytes: usize,
+    /// How to fill the bytes after `min_zero_bytes`.
+    style: PaddingStyle,
+}
+
+impl Default for CellPaddingPolicy {
+    fn default() -> Self {
+        // Four zero bytes immediately after the message, matching the
+        // reference C tor implementation's relay_cell padding as of
+        // tor-spec.txt section 6.1; random fill beyond that.
+        CellPaddingPolicy {
+            min_zero_bytes: 4,
+            style: PaddingStyle::Random,
+        }
+    }
+}
+
+impl CellPaddingPolicy {
+    /// Construct a new padding policy.
+    ///
+    /// `min_zero_bytes` bytes immediately following the encoded message are
+    /// left zeroed; any remaining bytes are filled according to `style`.
+    pub fn new(min_zero_bytes: usize, style: PaddingStyle) -> Self {
+        CellPaddingPolicy {
+            min_zero_bytes,
+            style,
+        }
+    }
+}
+
 /// A parsed relay cell.
 pub struct RelayCell {
     streamid: StreamID,
@@ -41,49 +154,49 @@ impl RelayCell {
     }
     /// Consume this relay message and encode it as a 509-byte padded cell
     /// body.
+    ///
+    /// Encodes directly into the preallocated cell body, with no
+    /// intermediate `Vec` allocation or copy.
     pub fn encode<R: Rng + CryptoRng>(self, rng: &mut R) -> crate::Result<RelayCellBody> {
-        // always this many zero-values bytes before padding.
-        // XXXX We should specify this value more exactly, to avoid fingerprinting
-        const MIN_SPACE_BEFORE_PADDING: usize = 4;
-
-        // TODO: This implementation is inefficient; it copies too much.
-        let encoded = self.encode_to_vec();
-        let enc_len = encoded.len();
-        if enc_len > CELL_DATA_LEN {
+        self.encode_with_padding(rng, &CellPaddingPolicy::default())
+    }
+    /// As [`RelayCell::encode`], but pad the unused tail of the cell
+    /// according to the given [`CellPaddingPolicy`] instead of the default.
+    pub fn encode_with_padding<R: Rng + CryptoRng>(
+        self,
+        rng: &mut R,
+        padding: &CellPaddingPolicy,
+    ) -> crate::Result<RelayCellBody> {
+        let mut raw = [0_u8; CELL_DATA_LEN];
+        let mut w = CellWriter::new(&mut raw);
+        w.write_u8(self.body.get_cmd().into());
+        w.write_u16(0); // "Recognized"
+        w.write_u16(self.streamid.0);
+        w.write_u32(0); // Digest
+        let len_pos = w.position();
+        w.write_u16(0); // Length; patched in below once we know it.
+        let body_pos = w.position();
+        self.body.encode_onto(&mut w);
+        if w.overflowed() {
             return Err(crate::Error::InternalError(
                 "too many bytes in relay cell".into(),
             ));
         }
-        let mut raw = [0u8; CELL_DATA_LEN];
-        raw[0..enc_len].copy_from_slice(&encoded);
+        let enc_len = w.position();
+        let payload_len = enc_len - body_pos;
+        assert!(payload_len <= std::u16::MAX as usize);
+        raw[len_pos..len_pos + 2].copy_from_slice(&(payload_len as u16).to_be_bytes());
 
-        if enc_len < CELL_DATA_LEN - MIN_SPACE_BEFORE_PADDING {
-            rng.fill_bytes(&mut raw[enc_len + MIN_SPACE_BEFORE_PADDING..]);
+        if enc_len + padding.min_zero_bytes < CELL_DATA_LEN {
+            let pad_start = enc_len + padding.min_zero_bytes;
+            match padding.style {
+                PaddingStyle::Random => rng.fill_bytes(&mut raw[pad_start..]),
+                PaddingStyle::Zero => (), // raw is already zero-initialized.
+            }
         }
 
         Ok(raw.into())
     }
-
-    /// Consume a relay cell and return its contents, encoded for use
-    /// in a RELAY or RELAY_EARLY cell
-    ///
-    /// TODO: not the best interface, as this requires copying into a cell.
-    fn encode_to_vec(self) -> Vec<u8> {
-        let mut w = Vec::new();
-        w.write_u8(self.body.get_cmd().into());
-        w.write_u16(0); // "Recognized"
-        w.write_u16(self.streamid.0);
-        w.write_u32(0); // Digest
-        let len_pos = w.len();
-        w.write_u16(0); // Length.
-        let body_pos = w.len();
-        self.body.encode_onto(&mut w);
-        assert!(w.len() >= body_pos); // nothing was removed
-        let payload_len = w.len() - body_pos;
-        assert!(payload_len <= std::u16::MAX as usize);
-        *(array_mut_ref![w, len_pos, 2]) = (payload_len as u16).to_be_bytes();
-        w
-    }
     /// Parse a RELAY or RELAY_EARLY cell body into a RelayCell.
     ///
     /// Requires that the cryptographic checks on the message have already been
@@ -144,9 +257,27 @@ pub enum RelayMsg {
     /// Start a directory stream
     BeginDir,
 
+    /// Establish an introduction point for an onion service.
+    EstablishIntro(EstablishIntro),
+    /// Acknowledge a successful EstablishIntro.
+    IntroEstablished(IntroEstablished),
+    /// Establish a rendezvous point for a client.
+    EstablishRendezvous(EstablishRendezvous),
+    /// Acknowledge a successful EstablishRendezvous.
+    RendezvousEstablished(RendezvousEstablished),
+    /// Introduce a client to an onion service, via its introduction point.
+    Introduce1(Introduce1),
+    /// Forwarded by the introduction point to the onion service.
+    Introduce2(Introduce2),
+    /// Acknowledge an Introduce1, from the introduction point to the client.
+    IntroduceAck(IntroduceAck),
+    /// Sent by the service to the rendezvous point to join a client's circuit.
+    Rendezvous1(Rendezvous1),
+    /// Forwarded by the rendezvous point to the client.
+    Rendezvous2(Rendezvous2),
+
     /// An unrecognized command.
     Unrecognized(Unrecognized),
-    // No hs for now.
 }
 
 /// Internal: traits in common different cell bodies.
@@ -156,7 +287,7 @@ pub trait Body: Sized {
     /// Decode a relay cell body from a provided reader.
     fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self>;
     /// Encode the body of this cell into the end of a vec.
-    fn encode_onto(self, w: &mut Vec<u8>);
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B);
 }
 
 impl RelayMsg {
@@ -179,6 +310,15 @@ impl RelayMsg {
             Resolve(_) => StreamCmd::RESOLVE,
             Resolved(_) => StreamCmd::RESOLVED,
             BeginDir => StreamCmd::BEGIN_DIR,
+            EstablishIntro(_) => StreamCmd::ESTABLISH_INTRO,
+            IntroEstablished(_) => StreamCmd::INTRO_ESTABLISHED,
+            EstablishRendezvous(_) => StreamCmd::ESTABLISH_RENDEZVOUS,
+            RendezvousEstablished(_) => StreamCmd::RENDEZVOUS_ESTABLISHED,
+            Introduce1(_) => StreamCmd::INTRODUCE1,
+            Introduce2(_) => StreamCmd::INTRODUCE2,
+            IntroduceAck(_) => StreamCmd::INTRODUCE_ACK,
+            Rendezvous1(_) => StreamCmd::RENDEZVOUS1,
+            Rendezvous2(_) => StreamCmd::RENDEZVOUS2,
             Unrecognized(u) => u.get_cmd(),
         }
     }
@@ -200,12 +340,27 @@ impl RelayMsg {
             StreamCmd::RESOLVE => RelayMsg::Resolve(Resolve::decode_from_reader(r)?),
             StreamCmd::RESOLVED => RelayMsg::Resolved(Resolved::decode_from_reader(r)?),
             StreamCmd::BEGIN_DIR => RelayMsg::BeginDir,
+            StreamCmd::ESTABLISH_INTRO => RelayMsg::EstablishIntro(EstablishIntro::decode_from_reader(r)?),
+            StreamCmd::INTRO_ESTABLISHED => {
+                RelayMsg::IntroEstablished(IntroEstablished::decode_from_reader(r)?)
+            }
+            StreamCmd::ESTABLISH_RENDEZVOUS => {
+                RelayMsg::EstablishRendezvous(EstablishRendezvous::decode_from_reader(r)?)
+            }
+            StreamCmd::RENDEZVOUS_ESTABLISHED => {
+                RelayMsg::RendezvousEstablished(RendezvousEstablished::decode_from_reader(r)?)
+            }
+            StreamCmd::INTRODUCE1 => RelayMsg::Introduce1(Introduce1::decode_from_reader(r)?),
+            StreamCmd::INTRODUCE2 => RelayMsg::Introduce2(Introduce2::decode_from_reader(r)?),
+            StreamCmd::INTRODUCE_ACK => RelayMsg::IntroduceAck(IntroduceAck::decode_from_reader(r)?),
+            StreamCmd::RENDEZVOUS1 => RelayMsg::Rendezvous1(Rendezvous1::decode_from_reader(r)?),
+            StreamCmd::RENDEZVOUS2 => RelayMsg::Rendezvous2(Rendezvous2::decode_from_reader(r)?),
 
             _ => RelayMsg::Unrecognized(Unrecognized::decode_with_cmd(c, r)?),
         })
     }
     /// Encode the body of this message, not including command or length
-    pub fn encode_onto(self, w: &mut Vec<u8>) {
+    pub fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         use RelayMsg::*;
         match self {
             Begin(b) => b.encode_onto(w),
@@ -223,18 +378,120 @@ impl RelayMsg {
             Resolve(b) => b.encode_onto(w),
             Resolved(b) => b.encode_onto(w),
             BeginDir => (),
+            EstablishIntro(b) => b.encode_onto(w),
+            IntroEstablished(b) => b.encode_onto(w),
+            EstablishRendezvous(b) => b.encode_onto(w),
+            RendezvousEstablished(b) => b.encode_onto(w),
+            Introduce1(b) => b.encode_onto(w),
+            Introduce2(b) => b.encode_onto(w),
+            IntroduceAck(b) => b.encode_onto(w),
+            Rendezvous1(b) => b.encode_onto(w),
+            Rendezvous2(b) => b.encode_onto(w),
             Unrecognized(b) => b.encode_onto(w),
         }
     }
 }
 
-/// Message to create a enw stream
+/// A bit within a [`Begin`] message's flags field, as defined by
+/// `tor-spec.txt` section 6.2.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum BeginFlag {
+    /// The client would accept a connection to an IPv6 address.
+    IPv6Ok = 1 << 0,
+    /// The client would *not* accept a connection to an IPv4 address.
+    IPv4NotOk = 1 << 1,
+    /// The client would rather have an IPv6 address than an IPv4 one.
+    IPv6Preferred = 1 << 2,
+}
+
+/// The flags on a [`Begin`] message, as a typed view over the raw `u32`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BeginFlags(u32);
+
+impl BeginFlags {
+    /// Return true if `flag` is set.
+    pub fn is_set(&self, flag: BeginFlag) -> bool {
+        self.0 & (flag as u32) != 0
+    }
+    /// Return true if the client will accept an IPv6 address in response.
+    pub fn ipv6_ok(&self) -> bool {
+        self.is_set(BeginFlag::IPv6Ok)
+    }
+    /// Return true if the client will *not* accept an IPv4 address in response.
+    pub fn ipv4_not_ok(&self) -> bool {
+        self.is_set(BeginFlag::IPv4NotOk)
+    }
+    /// Return true if the client would prefer an IPv6 address over an IPv4 one.
+    pub fn ipv6_preferred(&self) -> bool {
+        self.is_set(BeginFlag::IPv6Preferred)
+    }
+}
+
+impl From<u32> for BeginFlags {
+    fn from(bits: u32) -> Self {
+        BeginFlags(bits)
+    }
+}
+impl From<BeginFlags> for u32 {
+    fn from(flags: BeginFlags) -> Self {
+        flags.0
+    }
+}
+
+/// A builder for [`Begin`] messages, to set flags by name instead of by bit.
+#[derive(Clone, Debug, Default)]
+pub struct BeginBuilder {
+    /// The flags accumulated so far.
+    flags: BeginFlags,
+}
+
+impl BeginBuilder {
+    /// Create a new `BeginBuilder` with no flags set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set or clear `flag`.
+    pub fn flag(&mut self, flag: BeginFlag, set: bool) -> &mut Self {
+        if set {
+            self.flags.0 |= flag as u32;
+        } else {
+            self.flags.0 &= !(flag as u32);
+        }
+        self
+    }
+    /// Finish building, producing a `Begin` message for `addr`:`port`.
+    pub fn build(&self, addr: impl Into<Vec<u8>>, port: u16) -> Begin {
+        Begin {
+            addr: addr.into(),
+            port,
+            flags: self.flags.0,
+        }
+    }
+}
+
+/// Message to create a new stream
 pub struct Begin {
     addr: Vec<u8>,
     port: u16,
     flags: u32,
 }
 
+impl Begin {
+    /// Return the target address of this request, as a (possibly non-UTF-8) string.
+    pub fn addr(&self) -> &[u8] {
+        &self.addr
+    }
+    /// Return the target port of this request.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    /// Return the typed flags set on this request.
+    pub fn flags(&self) -> BeginFlags {
+        BeginFlags(self.flags)
+    }
+}
+
 impl Body for Begin {
     fn as_message(self) -> RelayMsg {
         RelayMsg::Begin(self)
@@ -260,7 +517,7 @@ impl Body for Begin {
             flags,
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         w.write_all(&self.addr[..]);
         w.write_u8(b':');
         w.write_all(self.port.to_string().as_bytes());
@@ -283,8 +540,8 @@ impl Body for Data {
             body: r.take(r.remaining())?.into(),
         })
     }
-    fn encode_onto(mut self, w: &mut Vec<u8>) {
-        w.append(&mut self.body);
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.body);
     }
 }
 
@@ -325,7 +582,7 @@ impl Body for End {
             Ok(End { reason, addr: None })
         }
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         w.write_u8(self.reason);
         if self.reason == REASON_EXITPOLICY && self.addr.is_some() {
             let (addr, ttl) = self.addr.unwrap();
@@ -365,7 +622,7 @@ impl Body for Connected {
             addr: Some((addr, ttl)),
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         if let Some((addr, ttl)) = self.addr {
             match addr {
                 IpAddr::V4(v4) => w.write(&v4),
@@ -394,10 +651,10 @@ impl Body for Sendme {
             digest: Some(r.take(r.remaining())?.into()),
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         match self.digest {
             None => (),
-            Some(mut x) => w.append(&mut x),
+            Some(mut x) => w.write_all(&x),
         }
     }
 }
@@ -426,7 +683,7 @@ impl Body for Extend {
             rsaid,
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         w.write(&self.addr);
         w.write_u16(self.port);
         w.write_all(&self.handshake[..]);
@@ -447,8 +704,8 @@ impl Body for Extended {
         let handshake = r.take(TAP_S_HANDSHAKE_LEN)?.into();
         Ok(Extended { handshake })
     }
-    fn encode_onto(mut self, w: &mut Vec<u8>) {
-        w.append(&mut self.handshake)
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.handshake)
     }
 }
 
@@ -485,7 +742,7 @@ impl Body for Extend2 {
             handshake,
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         assert!(self.linkspec.len() <= std::u8::MAX as usize);
         w.write_u8(self.linkspec.len() as u8);
         for ls in self.linkspec.iter() {
@@ -517,7 +774,7 @@ impl Body for Extended2 {
             handshake: handshake.into(),
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         assert!(self.handshake.len() <= std::u16::MAX as usize);
         w.write_u16(self.handshake.len() as u16);
         w.write_all(&self.handshake[..]);
@@ -534,7 +791,7 @@ impl Body for Truncate {
     fn decode_from_reader(_r: &mut Reader<'_>) -> Result<Self> {
         Ok(Truncate {})
     }
-    fn encode_onto(self, _w: &mut Vec<u8>) {}
+    fn encode_onto<B: Writer + ?Sized>(self, _w: &mut B) {}
 }
 
 /// The remaining hops of this circuit have gone away
@@ -551,7 +808,7 @@ impl Body for Truncated {
             reason: r.take_u8()?,
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         w.write_u8(self.reason);
     }
 }
@@ -571,7 +828,7 @@ impl Body for Resolve {
             query: query.into(),
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         w.write_all(&self.query[..]);
         w.write_u8(0);
     }
@@ -691,7 +948,7 @@ impl Body for Resolved {
         }
         Ok(Resolved { answers })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         for (rv, ttl) in self.answers.iter() {
             w.write(rv);
             w.write_u32(*ttl);
@@ -728,7 +985,240 @@ impl Body for Unrecognized {
             body: r.take(r.remaining())?.into(),
         })
     }
-    fn encode_onto(self, w: &mut Vec<u8>) {
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
         w.write_all(&self.body[..])
     }
 }
+
+/// Length in bytes of a rendezvous cookie, as used in EstablishRendezvous and Rendezvous1.
+const REND_COOKIE_LEN: usize = 20;
+
+/// Establish an introduction point for an onion service.
+///
+/// The full message carries an authentication key, a set of extensions, and
+/// a signature over the rest of the cell; for now we treat everything after
+/// the command as an opaque blob, the same way `Extend`/`Extended` treat
+/// their legacy TAP handshake payloads.
+pub struct EstablishIntro {
+    /// The encoded auth key, extensions, and signature.
+    body: Vec<u8>,
+}
+impl Body for EstablishIntro {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::EstablishIntro(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        Ok(EstablishIntro {
+            body: r.take(r.remaining())?.into(),
+        })
+    }
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.body);
+    }
+}
+
+/// Acknowledges a successful `EstablishIntro`.
+pub struct IntroEstablished {
+    /// Any extensions sent along with the acknowledgement.
+    body: Vec<u8>,
+}
+impl Body for IntroEstablished {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::IntroEstablished(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        Ok(IntroEstablished {
+            body: r.take(r.remaining())?.into(),
+        })
+    }
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.body);
+    }
+}
+
+/// Establish a rendezvous point for a client, ahead of an Introduce1.
+pub struct EstablishRendezvous {
+    /// The rendezvous cookie the service will use to recognize this circuit.
+    cookie: Vec<u8>,
+}
+impl Body for EstablishRendezvous {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::EstablishRendezvous(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        if r.remaining() < REND_COOKIE_LEN {
+            return Err(Error::BadMessage("rendezvous cookie too short"));
+        }
+        Ok(EstablishRendezvous {
+            cookie: r.take(REND_COOKIE_LEN)?.into(),
+        })
+    }
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.cookie);
+    }
+}
+
+/// Acknowledges a successful `EstablishRendezvous`.
+pub struct RendezvousEstablished {}
+impl Body for RendezvousEstablished {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::RendezvousEstablished(self)
+    }
+    fn decode_from_reader(_r: &mut Reader<'_>) -> Result<Self> {
+        Ok(RendezvousEstablished {})
+    }
+    fn encode_onto<B: Writer + ?Sized>(self, _w: &mut B) {}
+}
+
+/// Sent by a client to an introduction point, to introduce itself to an onion service.
+pub struct Introduce1 {
+    /// The encoded auth key, extensions, and encrypted introduce body.
+    body: Vec<u8>,
+}
+impl Body for Introduce1 {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::Introduce1(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        Ok(Introduce1 {
+            body: r.take(r.remaining())?.into(),
+        })
+    }
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.body);
+    }
+}
+
+/// Forwarded by the introduction point to the onion service, unmodified from
+/// the client's `Introduce1`, except for the leading identifying fields the
+/// introduction point strips off.
+pub struct Introduce2 {
+    /// The forwarded introduce body.
+    body: Vec<u8>,
+}
+impl Body for Introduce2 {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::Introduce2(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        Ok(Introduce2 {
+            body: r.take(r.remaining())?.into(),
+        })
+    }
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.body);
+    }
+}
+
+/// Sent by the introduction point to the client, acknowledging an `Introduce1`.
+pub struct IntroduceAck {
+    /// A status code: zero on success, nonzero on failure.
+    status: u16,
+}
+impl Body for IntroduceAck {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::IntroduceAck(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        let status = if r.remaining() >= 2 { r.take_u16()? } else { 0 };
+        Ok(IntroduceAck { status })
+    }
+    fn encode_onto<B: Writer + ?Sized>(self, w: &mut B) {
+        w.write_u16(self.status);
+    }
+}
+
+/// Sent by the onion service to the rendezvous point, to join the client's circuit.
+pub struct Rendezvous1 {
+    /// The rendezvous cookie identifying which client circuit to join.
+    cookie: Vec<u8>,
+    /// The service's half of the rendezvous handshake.
+    handshake: Vec<u8>,
+}
+impl Body for Rendezvous1 {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::Rendezvous1(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        if r.remaining() < REND_COOKIE_LEN {
+            return Err(Error::BadMessage("rendezvous cookie too short"));
+        }
+        let cookie = r.take(REND_COOKIE_LEN)?.into();
+        let handshake = r.take(r.remaining())?.into();
+        Ok(Rendezvous1 { cookie, handshake })
+    }
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.cookie);
+        w.write_all(&self.handshake);
+    }
+}
+
+/// Forwarded by the rendezvous point to the client, completing the rendezvous handshake.
+pub struct Rendezvous2 {
+    /// The service's half of the rendezvous handshake, as forwarded from `Rendezvous1`.
+    handshake: Vec<u8>,
+}
+impl Body for Rendezvous2 {
+    fn as_message(self) -> RelayMsg {
+        RelayMsg::Rendezvous2(self)
+    }
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        Ok(Rendezvous2 {
+            handshake: r.take(r.remaining())?.into(),
+        })
+    }
+    fn encode_onto<B: Writer + ?Sized>(mut self, w: &mut B) {
+        w.write_all(&self.handshake);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    /// The size, in bytes, of a `Drop` message's encoded header: one byte of
+    /// command, two of "recognized", two of stream ID, four of digest, and
+    /// two of length. `Drop` itself has no body, so this is also the offset
+    /// where padding begins.
+    const DROP_HEADER_LEN: usize = 11;
+
+    /// Encode a `Drop` message under `policy`, and return the raw cell body.
+    fn encode_drop_cell(policy: &CellPaddingPolicy) -> RelayCellBody {
+        let cell = RelayCell::new(StreamID(7), RelayMsg::Drop);
+        cell.encode_with_padding(&mut rand::thread_rng(), policy)
+            .unwrap()
+    }
+
+    #[test]
+    fn zero_style_pads_with_all_zeroes() {
+        let policy = CellPaddingPolicy::new(0, PaddingStyle::Zero);
+        let raw = encode_drop_cell(&policy);
+        assert!(raw.as_ref()[DROP_HEADER_LEN..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn random_style_zeroes_only_min_zero_bytes() {
+        let policy = CellPaddingPolicy::new(4, PaddingStyle::Random);
+        let raw = encode_drop_cell(&policy);
+        let zero_region = DROP_HEADER_LEN..DROP_HEADER_LEN + 4;
+        assert!(raw.as_ref()[zero_region].iter().all(|&b| b == 0));
+
+        // The rest should actually be randomized, not left at the buffer's
+        // zero-initialized default: with this many bytes, a real RNG
+        // producing all zeroes here is astronomically unlikely.
+        assert!(raw.as_ref()[DROP_HEADER_LEN + 4..]
+            .iter()
+            .any(|&b| b != 0));
+    }
+
+    #[test]
+    fn encode_then_decode_round_trips_cmd_and_streamid() {
+        let policy = CellPaddingPolicy::new(4, PaddingStyle::Random);
+        let raw = encode_drop_cell(&policy);
+        let decoded = RelayCell::decode(raw).unwrap();
+        assert_eq!(decoded.get_cmd(), StreamCmd::DROP);
+        let (streamid, _msg) = decoded.into_streamid_and_msg();
+        assert_eq!(streamid.0, 7);
+    }
+}